@@ -1,4 +1,4 @@
-use std::sync::atomic::AtomicBool;
+use std::sync::{atomic::AtomicBool, Arc};
 use std::thread;
 
 use chrono::{Duration, FixedOffset};
@@ -6,21 +6,62 @@ use eframe::{egui, CreationContext};
 
 use crate::config::Config;
 use crate::hook::HookExecutor;
-use crate::util::shared;
 use crate::{
-  backend::Backend,
+  backend::{Backend, Conflict, TrashedEvent},
+  event::{Event, EventId},
+  history::HistoryStore,
+  journal::Journal,
   notifier::Notifier,
-  util::{now, today, Result, Shared},
+  util::{now, today, utc_now, DateTime, Result},
   widget,
+  worker::{Applied, BackendWorker, Request, Response},
 };
 
 pub struct App {
   scheduler_ui: widget::ScheduleUi,
-  backend: Shared<dyn Backend>,
-  notifier: Shared<Notifier>,
+  worker: BackendWorker,
   refresh_timer: Option<thread::JoinHandle<()>>,
   hook: HookExecutor,
   last_rect: Option<egui::Rect>,
+  // changes not saved because another program changed the file
+  conflicts: Vec<Conflict>,
+  // errors to show, the oldest first
+  errors: Vec<ErrorMessage>,
+  // changes that failed to save, kept until saved by a retry
+  unsaved: Vec<Event>,
+  // changes left unsaved by the last run, to be saved or discarded
+  recovered: Vec<Event>,
+  // the deleted events, when the trash panel is open
+  trash: Option<Vec<TrashedEvent>>,
+  timezone: FixedOffset,
+  // the id of the last events requested
+  last_load: u64,
+  // the events waited for
+  pending_load: Option<PendingLoad>,
+  // the search text waited for
+  pending_search: Option<String>,
+}
+
+// an error shown in the errors panel
+struct ErrorMessage {
+  text: String,
+  // the change that failed to save, removed when retried
+  unsaved: Option<EventId>,
+}
+
+impl ErrorMessage {
+  fn new(text: String) -> Self {
+    Self {
+      text,
+      unsaved: None,
+    }
+  }
+}
+
+struct PendingLoad {
+  id: u64,
+  // changes were sent after the request, the events may not have them
+  outdated: bool,
 }
 
 static SCROLL: AtomicBool = AtomicBool::new(true);
@@ -31,11 +72,15 @@ impl eframe::App for App {
     ctx: &eframe::egui::Context,
     _frame: &mut eframe::Frame,
   ) {
+    self.handle_responses();
     self.refresh_events();
     self.load_events();
 
     self.scheduler_ui.update_current_time();
 
+    // panels go before the central panel
+    self.show_errors(ctx);
+
     egui::CentralPanel::default().show(ctx, |ui| {
       let mut scroll_area = egui::ScrollArea::both();
 
@@ -49,6 +94,8 @@ impl eframe::App for App {
           None => true,
           Some(rect) => rect != ui.max_rect(),
         };
+        let just_resized =
+          just_resized | std::mem::take(&mut self.scheduler_ui.refit_requested);
         if just_resized {
           self.last_rect = Some(ui.max_rect());
           self.scheduler_ui.refit_into_ui(ui);
@@ -58,13 +105,26 @@ impl eframe::App for App {
       });
     });
 
-    self.apply_event_changes().expect("Failed applying changes");
+    self.search_events();
+    self.show_conflicts(ctx);
+    self.show_recovered(ctx);
+    self.show_trash(ctx);
+
+    self.apply_event_changes();
+    self.store_history();
   }
 }
 
 impl App {
   pub fn setup(mut self, ctx: &CreationContext) -> Self {
     let ctx = ctx.egui_ctx.clone();
+
+    // repaint as soon as the backend responds
+    let repaint_ctx = ctx.clone();
+    self
+      .worker
+      .set_waker(Arc::new(move || repaint_ctx.request_repaint()));
+
     self.refresh_timer = Some(thread::spawn(move || loop {
       thread::sleep(std::time::Duration::from_millis(1000));
       ctx.request_repaint();
@@ -74,39 +134,78 @@ impl App {
 
   pub fn new(
     config: &Config,
-    day_count: usize,
     timezone: FixedOffset,
     backend: impl Backend + 'static,
-  ) -> Result<Self> {
-    let first_day = today(&timezone) - Duration::days(day_count as i64 / 2);
-    let backend: Shared<dyn Backend> = shared(backend);
-    let notifier = shared(Notifier::start(config, &backend)?);
-
+    journal: Option<Journal>,
+    history: Option<HistoryStore>,
+  ) -> Self {
+    let day_view = match (config.day_view.as_deref(), config.day_count) {
+      (Some("week"), _) => widget::DayView::Week,
+      (Some("work_week"), _) => widget::DayView::WorkWeek,
+      (_, Some(n)) => widget::DayView::Days(n),
+      (_, None) => widget::DayView::Fit,
+    };
     let min_width = config.day_column_width.unwrap_or(260.0);
 
-    let scheduler_ui = widget::ScheduleUiBuilder::default()
-      .new_event_calendar(config.calendar_name.clone())
-      .first_day(first_day)
+    let changes = match history.as_ref().map(HistoryStore::load) {
+      Some(Ok(changes)) => changes,
+      Some(Err(e)) => {
+        log::error!("Failed reading the history: {e:?}");
+        Default::default()
+      }
+      None => Default::default(),
+    };
+    // the calendars are loaded by the first refresh, on the worker
+    let mut scheduler_ui = widget::ScheduleUiBuilder::default()
+      .new_event_calendar(config.default_calendar().name)
+      .first_day(today(&timezone))
       .current_time(now(&timezone))
       .timezone(timezone)
-      .day_count(day_count)
+      .day_count(day_view.day_count().unwrap_or(3))
+      .day_view(day_view)
+      .first_weekday(config.first_weekday())
+      .history(changes)
       .refresh_requested(true)
       .scope_updated(true)
       .day_width(min_width)
       .day_min_width((min_width - 100.0).min(200.0))
       .build()
       .expect("failed to build scheduler");
+    scheduler_ui.show_date(today(&timezone));
 
     let hook = HookExecutor::new(config);
 
-    Ok(Self {
+    let recovered = match journal.as_ref().map(Journal::pending) {
+      Some(Ok(events)) => events,
+      Some(Err(e)) => {
+        log::error!("Failed reading the journal: {e:?}");
+        vec![]
+      }
+      None => vec![],
+    };
+
+    let notifier = Notifier::new(config);
+    let worker =
+      BackendWorker::start(Box::new(backend), notifier, journal, history);
+    let retention = Duration::days(config.trash_retention_days.into());
+    worker.send(Request::PurgeTrash(utc_now() - retention));
+
+    Self {
       scheduler_ui,
-      backend,
-      notifier,
+      worker,
       hook,
       last_rect: None,
       refresh_timer: None,
-    })
+      conflicts: vec![],
+      errors: vec![],
+      unsaved: vec![],
+      recovered,
+      trash: None,
+      timezone,
+      last_load: 0,
+      pending_load: None,
+      pending_search: None,
+    }
   }
 
   pub fn refresh_events(&mut self) {
@@ -114,15 +213,7 @@ impl App {
       return;
     }
 
-    self
-      .backend
-      .lock()
-      .unwrap()
-      .force_refresh()
-      .expect("failed to reload event");
-
-    self.load_events();
-
+    self.worker.send(Request::Refresh);
     self.scheduler_ui.refresh_requested = false;
   }
 
@@ -131,45 +222,343 @@ impl App {
       return;
     }
 
-    let (start, end) = self.scheduler_ui.time_range();
-    let events = self
-      .backend
-      .lock()
-      .unwrap()
-      .get_events(start, end)
-      .expect("load events");
+    let (from, to) = self.scheduler_ui.time_range();
+    self.last_load += 1;
+    let id = self.last_load;
+    self.worker.send(Request::LoadEvents { id, from, to });
 
-    self.scheduler_ui.load_events(events);
+    // the events of earlier requests are ignored
+    self.pending_load = Some(PendingLoad {
+      id,
+      outdated: false,
+    });
     self.scheduler_ui.scope_updated = false;
+    self.scheduler_ui.loading = true;
   }
 
-  fn apply_event_changes(&mut self) -> Result<()> {
-    let mut anything_changed = false;
-    let mut backend = self.backend.lock().unwrap();
-    let events = self.scheduler_ui.events_mut();
-    for event in events.iter() {
-      if event.deleted {
-        backend.delete_event(&event.id)?;
-        anything_changed = true;
-      } else if event.changed {
-        backend.update_event(event)?;
-        anything_changed = true;
+  fn search_events(&mut self) {
+    let Some(query) = self.scheduler_ui.pending_search() else {
+      return;
+    };
+    if self.pending_search.as_ref() == Some(&query.text) {
+      return;
+    }
+
+    self.pending_search = Some(query.text.clone());
+    self.worker.send(Request::Search(query));
+  }
+
+  fn handle_responses(&mut self) {
+    for response in self.worker.take_responses() {
+      match response {
+        // the calendar name or color may have changed as well
+        Response::Calendars(Ok(calendars)) => {
+          self.scheduler_ui.load_calendars(calendars)
+        }
+        Response::Calendars(Err(e)) => {
+          self.report_error("Failed reloading calendars", e)
+        }
+        Response::Events { id, events } => self.events_loaded(id, events),
+        Response::SearchResults(query, hits) => {
+          if self.pending_search.as_ref() == Some(&query.text) {
+            self.pending_search = None;
+          }
+
+          let hits = hits.unwrap_or_else(|e| {
+            self.report_error(&format!("Failed searching {:?}", query.text), e);
+            vec![]
+          });
+          self.scheduler_ui.load_search_results(&query, hits);
+        }
+        Response::Applied(applied) => self.changes_applied(applied),
+        Response::Trash(Ok(trashed)) => {
+          if let Some(trash) = self.trash.as_mut() {
+            *trash = trashed;
+          }
+        }
+        Response::Trash(Err(e)) => {
+          self.report_error("Failed listing deleted events", e)
+        }
+        Response::Restored(Ok(())) => self.scheduler_ui.scope_updated = true,
+        Response::Restored(Err(e)) => {
+          self.report_error("Failed restoring event", e)
+        }
+        // reload the events changed by other programs
+        Response::Changed => self.scheduler_ui.scope_updated = true,
+        Response::Stopped(reason) => {
+          self.errors.push(ErrorMessage::new(format!(
+            "Calendars can no longer be loaded or saved, restart malakal: \
+             {reason}"
+          )))
+        }
       }
     }
+  }
 
-    events.retain(|e| !e.deleted);
+  fn events_loaded(&mut self, id: u64, events: Result<Vec<Event>>) {
+    match self.pending_load.take_if(|load| load.id == id) {
+      // superseded by a later request
+      None => return,
+      // load them again after the changes
+      Some(load) if load.outdated => self.scheduler_ui.scope_updated = true,
+      Some(_) => match events {
+        Ok(events) => {
+          let events = self.with_unsaved(events);
+          self.scheduler_ui.load_events(events);
+        }
+        // keep showing the events loaded before
+        Err(e) => self.report_error("Failed loading events", e),
+      },
+    }
 
-    for event in events.iter_mut() {
+    self.scheduler_ui.loading = false;
+  }
+
+  // the loaded events with the changes not saved yet on top
+  fn with_unsaved(&self, mut events: Vec<Event>) -> Vec<Event> {
+    let (from, to) = self.scheduler_ui.time_range();
+
+    for change in self.unsaved.iter() {
+      events.retain(|e| e.id != change.id);
+      if change.deleted || change.end < from || change.start > to {
+        continue;
+      }
+
+      let mut event = change.clone();
       event.reset_dirty_flags();
+      events.push(event);
     }
 
-    drop(backend);
+    events
+  }
 
-    if anything_changed {
-      self.notifier.lock().unwrap().events_updated();
+  fn changes_applied(&mut self, applied: Applied) {
+    if applied.series_changed {
+      self.scheduler_ui.scope_updated = true;
+    }
+    if !applied.saved.is_empty() {
       self.hook.report_updated();
     }
+    self.scheduler_ui.set_series_changes(&applied.series);
+    self.conflicts.extend(applied.conflicts);
+
+    // later changes to an event replace the unsaved ones
+    self.unsaved.retain(|e| !applied.saved.contains(&e.id));
+    for (event, error) in applied.failed {
+      // replacing the error of an earlier failure to save the event
+      self
+        .errors
+        .retain(|e| e.unsaved.as_ref() != Some(&event.id));
+      self.errors.push(ErrorMessage {
+        text: format!("Failed saving \"{}\": {error}", event.title),
+        unsaved: Some(event.id.clone()),
+      });
+      self.unsaved.retain(|e| e.id != event.id);
+      self.unsaved.push(event);
+    }
+  }
 
-    Ok(())
+  fn report_error(&mut self, message: &str, error: anyhow::Error) {
+    log::error!("{message}: {error:?}");
+    self
+      .errors
+      .push(ErrorMessage::new(format!("{message}: {error:#}")));
+  }
+
+  fn retry_unsaved(&mut self) {
+    // the changes failing again report their errors again
+    let unsaved = &self.unsaved;
+    self.errors.retain(|error| match &error.unsaved {
+      Some(id) => !unsaved.iter().any(|e| &e.id == id),
+      None => true,
+    });
+    if let Some(load) = self.pending_load.as_mut() {
+      load.outdated = true;
+    }
+
+    self.worker.send(Request::Apply(self.unsaved.clone()));
+  }
+
+  fn show_errors(&mut self, ctx: &egui::Context) {
+    if self.errors.is_empty() && self.unsaved.is_empty() {
+      return;
+    }
+
+    egui::TopBottomPanel::bottom("errors").show(ctx, |ui| {
+      let mut dismissed = None;
+      for (i, error) in self.errors.iter().enumerate() {
+        ui.horizontal(|ui| {
+          if ui.small_button("Dismiss").clicked() {
+            dismissed = Some(i);
+          }
+          ui.colored_label(ui.visuals().error_fg_color, &error.text);
+        });
+      }
+      if let Some(i) = dismissed {
+        self.errors.remove(i);
+      }
+
+      if !self.unsaved.is_empty() {
+        ui.horizontal(|ui| {
+          if ui.button("Retry").clicked() {
+            self.retry_unsaved();
+          }
+          ui.label(format!("{} changes not saved", self.unsaved.len()));
+        });
+      }
+    });
+  }
+
+  fn show_conflicts(&mut self, ctx: &egui::Context) {
+    if self.conflicts.is_empty() {
+      return;
+    }
+
+    egui::Window::new("Changes not saved")
+      .collapsible(false)
+      .resizable(false)
+      .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+      .show(ctx, |ui| {
+        for conflict in self.conflicts.iter() {
+          ui.label(format!(
+            "{} was changed by another program, your change to the \
+             event was discarded.",
+            conflict.path.display()
+          ));
+        }
+
+        if ui.button("OK").clicked() {
+          self.conflicts.clear();
+        }
+      });
+  }
+
+  fn show_recovered(&mut self, ctx: &egui::Context) {
+    if self.recovered.is_empty() {
+      return;
+    }
+
+    let mut save = None;
+    egui::Window::new("Changes not saved last time")
+      .collapsible(false)
+      .resizable(false)
+      .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+      .show(ctx, |ui| {
+        for event in self.recovered.iter() {
+          let action = if event.deleted { "Delete" } else { "Save" };
+          ui.label(format!(
+            "{action} \"{}\" ({})",
+            event.title,
+            event.start.format("%F %H:%M")
+          ));
+        }
+
+        ui.horizontal(|ui| {
+          if ui.button("Save").clicked() {
+            save = Some(true);
+          }
+          if ui.button("Discard").clicked() {
+            save = Some(false);
+          }
+        });
+      });
+
+    match save {
+      Some(true) => {
+        let changes = std::mem::take(&mut self.recovered);
+        self.worker.send(Request::Apply(changes));
+        // show the events with the changes
+        self.scheduler_ui.scope_updated = true;
+      }
+      Some(false) => {
+        let event_ids = self.recovered.drain(..).map(|e| e.id).collect();
+        self.worker.send(Request::Discard(event_ids));
+      }
+      None => (),
+    }
+  }
+
+  fn show_trash(&mut self, ctx: &egui::Context) {
+    if std::mem::take(&mut self.scheduler_ui.trash_requested) {
+      self.trash = Some(vec![]);
+      self.worker.send(Request::ListTrash);
+    }
+
+    let Some(trash) = self.trash.as_ref() else {
+      return;
+    };
+
+    let time =
+      |t: &DateTime| t.with_timezone(&self.timezone).format("%F %H:%M");
+    let mut restore = None;
+    let mut close = false;
+    egui::Window::new("Trash")
+      .collapsible(false)
+      .resizable(false)
+      .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+      .show(ctx, |ui| {
+        if trash.is_empty() {
+          ui.label("No deleted events");
+        }
+
+        egui::ScrollArea::vertical()
+          .max_height(300.0)
+          .show(ui, |ui| {
+            egui::Grid::new("trash").striped(true).show(ui, |ui| {
+              for trashed in trash.iter() {
+                ui.label(&trashed.event.title);
+                ui.label(time(&trashed.event.start).to_string());
+                ui.label(format!("deleted {}", time(&trashed.deleted_at)));
+                if ui.button("Restore").clicked() {
+                  restore = Some(trashed.clone());
+                }
+                ui.end_row();
+              }
+            });
+          });
+
+        if ui.button("Close").clicked() {
+          close = true;
+        }
+      });
+
+    if let Some(trashed) = restore {
+      self.worker.send(Request::Restore(Box::new(trashed)));
+      self.worker.send(Request::ListTrash);
+    }
+    if close {
+      self.trash = None;
+    }
+  }
+
+  fn apply_event_changes(&mut self) {
+    let events = self.scheduler_ui.events_mut();
+    let changes: Vec<Event> = events
+      .iter()
+      .filter(|e| e.changed || e.deleted)
+      .cloned()
+      .collect();
+    if changes.is_empty() {
+      return;
+    }
+
+    events.retain(|e| !e.deleted);
+
+    for event in events.iter_mut() {
+      event.reset_dirty_flags();
+    }
+
+    if let Some(load) = self.pending_load.as_mut() {
+      load.outdated = true;
+    }
+
+    self.worker.send(Request::Apply(changes));
+  }
+
+  fn store_history(&mut self) {
+    let updates = self.scheduler_ui.take_history_updates();
+    if !updates.is_empty() {
+      self.worker.send(Request::UpdateHistory(updates));
+    }
   }
 }
@@ -1,25 +1,160 @@
+mod in_memory;
 mod indexed_local_dir;
 mod local_dir;
+mod multi_calendar;
+#[cfg(test)]
+mod tests;
+mod trash;
+mod watcher;
+
+use std::path::PathBuf;
 
 use super::event::{Event, EventId};
-use crate::util::{DateTime, Result};
+use crate::recurrence::RecurrenceScope;
+use crate::search::SearchQuery;
+use crate::util::{anyhow, DateTime, Result};
 
+pub use in_memory::InMemory;
 pub use indexed_local_dir::IndexedLocalDir;
 pub use local_dir::{LocalDir, LocalDirBuilder};
+pub use multi_calendar::MultiCalendar;
+
+// wakes up the app to pick up changes made outside of it
+pub type Waker = std::sync::Arc<dyn Fn() + Send + Sync>;
+
+// Identifies the content of an ics file, to tell whether another
+// program changed the file since an event was read from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+  pub size: u64,
+  pub hash: u64,
+}
+
+impl Fingerprint {
+  pub fn of(content: &[u8]) -> Self {
+    // FNV-1a, which unlike the std hasher is stable across releases
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+      hash ^= *byte as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    Self {
+      size: content.len() as u64,
+      hash,
+    }
+  }
+}
+
+// The file of an event was changed by another program (e.g.
+// vdirsyncer) since the event was read. The change is not written so
+// as not to overwrite theirs.
+#[derive(Clone, Debug)]
+pub struct Conflict {
+  pub event_id: EventId,
+  pub path: PathBuf,
+}
+
+impl std::fmt::Display for Conflict {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{} was changed by another program since event {} was loaded",
+      self.path.display(),
+      self.event_id
+    )
+  }
+}
+
+impl std::error::Error for Conflict {}
+
+// metadata of a calendar collection, as written by vdirsyncer
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalendarInfo {
+  pub name: String,
+  pub display_name: Option<String>,
+  pub color: Option<[f32; 3]>,
+}
+
+impl CalendarInfo {
+  // the name shown to the user
+  pub fn label(&self) -> &str {
+    self.display_name.as_deref().unwrap_or(&self.name)
+  }
+}
+
+// an event deleted from a calendar, which can be restored
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrashedEvent {
+  // identifies the event in the trash of its calendar
+  pub trash_id: String,
+  pub event: Event,
+  pub deleted_at: DateTime,
+}
 
 pub trait Backend: Send {
+  // the calendars the events come from
+  fn calendars(&self) -> Vec<CalendarInfo>;
+
+  #[allow(unused)]
   fn get_event(&mut self, event_id: &EventId) -> Result<Event>;
 
   // get events which overlap with the from..to interval.
   fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>>;
 
-  fn delete_event(&mut self, event_id: &EventId) -> Result<()>;
+  // delete the event, unless it was changed by another program since
+  // it was loaded
+  fn delete_event(&mut self, event: &Event) -> Result<()>;
+
+  // the events matching the query ordered by time, see SearchQuery
+  fn search(&mut self, query: &SearchQuery) -> Result<Vec<Event>>;
 
   fn update_event(&mut self, updated_event: &Event) -> Result<()>;
 
+  // update the occurrences of a recurring event selected by the scope
+  // according to the changes made to one occurrence, returns the id of
+  // the series split off when changing the following occurrences
+  fn update_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<Option<EventId>>;
+
+  // delete the occurrences of a recurring event selected by the scope
+  fn delete_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<()>;
+
+  #[allow(unused)]
   fn create_event(&mut self, event: &Event) -> Result<()>;
 
   fn force_refresh(&mut self) -> Result<()> {
     Ok(())
   }
+
+  // the deleted events kept in the trash, the latest deleted first
+  fn trashed_events(&mut self) -> Result<Vec<TrashedEvent>> {
+    Ok(vec![])
+  }
+
+  // put a deleted event back into its calendar
+  fn restore_event(&mut self, trashed: &TrashedEvent) -> Result<()> {
+    Err(anyhow!("calendar {} has no trash", trashed.event.calendar))
+  }
+
+  // remove the events deleted before the time from the trash for good
+  fn purge_trash(&mut self, _before: DateTime) -> Result<()> {
+    Ok(())
+  }
+
+  // called when events may have been changed by other programs
+  fn set_waker(&mut self, _waker: Waker) {}
+
+  // pick up the changes made by other programs, returns whether any
+  // events changed
+  fn sync_changes(&mut self) -> Result<bool> {
+    Ok(false)
+  }
 }
@@ -0,0 +1,253 @@
+use chrono::{Duration, FixedOffset, Weekday};
+
+use crate::{
+  backend::{Backend, CalendarInfo, TrashedEvent},
+  event::{Event, EventBuilder, EventId, EventTimezone},
+  recurrence::{
+    self, parse_occurrence_id, Frequency, RRule, Recurrence, RecurrenceScope,
+    WeekdayNum,
+  },
+  search::SearchQuery,
+  timezone::Zone,
+  util::{all_day_time, anyhow, today, utc_now, DateTime, Result},
+};
+
+use super::local_dir::instances_in_range;
+
+// A calendar kept in memory, for tests and the demo mode. Recurring
+// events are stored as series and expanded when queried, as the other
+// backends do.
+pub struct InMemory {
+  calendar: String,
+  // the standalone events and the series, not their occurrences
+  events: Vec<Event>,
+  trash: Vec<TrashedEvent>,
+}
+
+impl InMemory {
+  pub fn new(calendar: &str) -> Self {
+    Self {
+      calendar: calendar.to_string(),
+      events: vec![],
+      trash: vec![],
+    }
+  }
+
+  // a calendar with a few events around today
+  pub fn demo(calendar: &str, timezone: FixedOffset) -> Self {
+    let mut backend = Self::new(calendar);
+    let today = today(&timezone);
+    let at = |days: i64, hour: u32, minute: u32| {
+      (today + Duration::days(days))
+        .and_hms_opt(hour, minute, 0)
+        .and_then(|t| t.and_local_timezone(timezone).single())
+        .expect("invalid demo time")
+    };
+
+    let event = |title: &str, start: DateTime, minutes: i64| {
+      EventBuilder::default()
+        .id(uuid::Uuid::new_v4().hyphenated().to_string())
+        .calendar(calendar)
+        .title(title)
+        .start(start)
+        .end(start + Duration::minutes(minutes))
+        .timezone(EventTimezone::Zoned(Zone::local()))
+        .build()
+        .expect("invalid demo event")
+    };
+
+    use Weekday::*;
+    let mut workdays = RRule::new(Frequency::Weekly);
+    workdays.by_day = [Mon, Tue, Wed, Thu, Fri]
+      .map(|weekday| WeekdayNum { nth: None, weekday })
+      .into();
+
+    let mut standup = event("Standup", at(-3, 9, 30), 15);
+    standup.description = Some("What's done, what's next".to_string());
+    standup.recurrence = Some(Recurrence {
+      rule: Some(workdays),
+      ..Default::default()
+    });
+
+    let mut review = event("Weekly review", at(-2, 16, 0), 60);
+    review.recurrence = Some(Recurrence {
+      rule: Some(RRule::new(Frequency::Weekly)),
+      ..Default::default()
+    });
+
+    let mut trip = event("Conference trip", at(1, 0, 0), 0);
+    trip.all_day = true;
+    trip.start = all_day_time(today + Duration::days(1));
+    trip.end = trip.start + Duration::days(2);
+
+    let mut lunch = event("Lunch with Sam", at(0, 12, 30), 60);
+    lunch.color = Some([0.9, 0.45, 0.1]);
+
+    for event in [
+      standup,
+      review,
+      trip,
+      lunch,
+      event("Write report", at(0, 14, 0), 90),
+      event("Dentist", at(-1, 10, 0), 45),
+      event("Late night deploy", at(2, 23, 0), 120),
+    ] {
+      backend.events.push(event);
+    }
+
+    backend
+  }
+
+  fn position(&self, uid: &str) -> Option<usize> {
+    self.events.iter().position(|e| e.id == uid)
+  }
+
+  fn trash_event(&mut self, i: usize) {
+    let event = self.events.remove(i);
+    self.trash.insert(
+      0,
+      TrashedEvent {
+        trash_id: uuid::Uuid::new_v4().hyphenated().to_string(),
+        event,
+        deleted_at: utc_now(),
+      },
+    );
+  }
+
+  // the series an occurrence id refers to, unless an event has that id
+  fn split_occurrence_id<'a>(
+    &self,
+    event_id: &'a str,
+  ) -> Option<(&'a str, DateTime)> {
+    let occurrence = parse_occurrence_id(event_id)?;
+    self.position(event_id).is_none().then_some(occurrence)
+  }
+}
+
+impl Backend for InMemory {
+  fn calendars(&self) -> Vec<CalendarInfo> {
+    vec![CalendarInfo {
+      name: self.calendar.clone(),
+      display_name: None,
+      color: None,
+    }]
+  }
+
+  fn get_event(&mut self, event_id: &EventId) -> Result<Event> {
+    if let Some((uid, recurrence_id)) = self.split_occurrence_id(event_id) {
+      let master = self.get_event(&uid.to_string())?;
+      return Ok(recurrence::occurrence(&master, recurrence_id));
+    }
+
+    self
+      .position(event_id)
+      .map(|i| self.events[i].clone())
+      .ok_or_else(|| anyhow!("event {event_id} not found"))
+  }
+
+  fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>> {
+    let mut events = vec![];
+    for event in self.events.iter() {
+      events.extend(instances_in_range(event.clone(), from, to));
+    }
+
+    Ok(events)
+  }
+
+  fn search(&mut self, query: &SearchQuery) -> Result<Vec<Event>> {
+    let events = self.events.iter().filter(|e| query.matches(e)).cloned();
+    Ok(query.hits(events))
+  }
+
+  fn delete_event(&mut self, event: &Event) -> Result<()> {
+    if self.split_occurrence_id(&event.id).is_some() {
+      return self.delete_occurrences(event, RecurrenceScope::ThisOnly);
+    }
+
+    match self.position(&event.id) {
+      Some(i) => self.trash_event(i),
+      None => log::warn!("Event {} to delete not found", event.id),
+    }
+    Ok(())
+  }
+
+  fn update_event(&mut self, updated_event: &Event) -> Result<()> {
+    if updated_event.recurrence_id.is_some() {
+      self.update_occurrences(updated_event, RecurrenceScope::ThisOnly)?;
+      return Ok(());
+    }
+
+    // new events are saved through updates as well
+    match self.position(&updated_event.id) {
+      Some(i) => self.events[i] = updated_event.clone(),
+      None => self.events.push(updated_event.clone()),
+    }
+    Ok(())
+  }
+
+  fn update_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<Option<EventId>> {
+    let uid = occurrence.uid().to_string();
+    let i = self
+      .position(&uid)
+      .ok_or_else(|| anyhow!("event {uid} not found"))?;
+
+    let following =
+      recurrence::apply_change(&mut self.events[i], occurrence, scope);
+    let following_id = following.as_ref().map(|e| e.id.clone());
+    self.events.extend(following);
+    Ok(following_id)
+  }
+
+  fn delete_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<()> {
+    let event_id = &occurrence.id;
+    let (uid, recurrence_id) = parse_occurrence_id(event_id)
+      .ok_or_else(|| anyhow!("event {event_id} is not an occurrence"))?;
+    let i = self
+      .position(uid)
+      .ok_or_else(|| anyhow!("event {uid} not found"))?;
+
+    let master = &mut self.events[i];
+    if !recurrence::delete_occurrences(master, recurrence_id, scope) {
+      self.trash_event(i);
+    }
+    Ok(())
+  }
+
+  fn create_event(&mut self, event: &Event) -> Result<()> {
+    self.events.push(event.clone());
+    Ok(())
+  }
+
+  fn trashed_events(&mut self) -> Result<Vec<TrashedEvent>> {
+    Ok(self.trash.clone())
+  }
+
+  fn restore_event(&mut self, trashed: &TrashedEvent) -> Result<()> {
+    let i = self
+      .trash
+      .iter()
+      .position(|t| t.trash_id == trashed.trash_id)
+      .ok_or_else(|| anyhow!("{} is not in the trash", trashed.trash_id))?;
+    let uid = &self.trash[i].event.id;
+    if self.position(uid).is_some() {
+      return Err(anyhow!("event {uid} already exists"));
+    }
+
+    let restored = self.trash.remove(i);
+    self.events.push(restored.event);
+    Ok(())
+  }
+
+  fn purge_trash(&mut self, before: DateTime) -> Result<()> {
+    self.trash.retain(|t| t.deleted_at >= before);
+    Ok(())
+  }
+}
@@ -1,22 +1,50 @@
 use chrono::{Timelike, Utc};
-use rusqlite::{params, Connection};
+use rusqlite::{
+  params, params_from_iter, types::Value, Connection, OptionalExtension, Row,
+};
 use std::time::Duration;
 use std::{
-  cell::RefCell, fs::create_dir_all, fs::Metadata, path::Path, time::Instant,
+  cell::RefCell,
+  ffi::OsStr,
+  fs::create_dir_all,
+  fs::Metadata,
+  path::{Path, PathBuf},
+  time::Instant,
 };
 
 use crate::util::DateTime;
 use crate::{
-  backend::Backend,
-  event::{Event, EventId},
-  util::Result,
+  backend::{Backend, CalendarInfo, Fingerprint, TrashedEvent, Waker},
+  event::{Event, EventBuilder, EventId, EventTimezone},
+  ical::{
+    color_hex, format_zone_definition, parse_color, parse_zone_definition,
+  },
+  recurrence::{self, Recurrence, RecurrenceScope},
+  search::SearchQuery,
+  timezone::Zone,
+  util::{anyhow, Result},
+};
+
+use super::{
+  local_dir::instances_in_range,
+  watcher::{Change, DirWatcher},
+  LocalDir,
 };
 
-use super::LocalDir;
+// bump this when the schema of the index changes. The index will be
+// rebuilt from the ics files.
+const SCHEMA_VERSION: i64 = 8;
+
+// the columns of the events and overrides tables holding the parsed
+// event, read by read_event
+const EVENT_COLUMNS: &str = "calendar, title, description, start, end, \
+  all_day, color, timezone, vtimezone, created_at, modified_at, timestamp";
 
 pub struct IndexedLocalDir {
   backend: LocalDir,
   conn: RefCell<Connection>,
+  // the directory is polled when it can't be watched
+  watcher: Option<DirWatcher>,
   refresh_interval: Duration,
   next_refresh_at: Instant,
 }
@@ -24,6 +52,8 @@ pub struct IndexedLocalDir {
 struct ICSFileEntry {
   size: usize,
   modified_at: chrono::DateTime<Utc>,
+  hash: u64,
+  indexed_at: chrono::DateTime<Utc>,
 }
 
 impl IndexedLocalDir {
@@ -39,11 +69,20 @@ impl IndexedLocalDir {
     conn.pragma_update(None, "mmap_size", 30_000_000)?;
 
     let conn = RefCell::new(conn);
+    // watch before the first scan so no changes are missed
+    let watcher = match DirWatcher::new(backend.dir()) {
+      Ok(watcher) => Some(watcher),
+      Err(e) => {
+        log::warn!("Cannot watch {:?}, polling instead: {e:?}", backend.dir());
+        None
+      }
+    };
     let refresh_interval = Duration::from_secs(60);
     let next_refresh_at = Instant::now() + refresh_interval;
     let mut new_self = Self {
       backend,
       conn,
+      watcher,
       refresh_interval,
       next_refresh_at,
     };
@@ -54,65 +93,215 @@ impl IndexedLocalDir {
     Ok(new_self)
   }
 
-  fn get_single_event_entry(
+  fn get_file_entry(
     &self,
     conn: &Connection,
-    event_id: &str,
+    path: &Path,
   ) -> Result<ICSFileEntry> {
     let mut stmt = conn.prepare_cached(
       "
-SELECT content_length, modification_date
+SELECT content_length, modification_date, content_hash, indexed_at
 FROM events
-WHERE event_id = ?
+WHERE path = ?
 LIMIT 1
 ",
     )?;
 
     stmt
-      .query_row(params![event_id], |row| {
+      .query_row(params![path_str(path)], |row| {
         Ok(ICSFileEntry {
           size: row.get(0)?,
           modified_at: from_unix_timestamp(row.get(1)?),
+          hash: row.get::<_, i64>(2)? as u64,
+          indexed_at: from_unix_timestamp(row.get(3)?),
         })
       })
       .map_err(Into::into)
   }
 
+  fn delete_file_entries(&self, conn: &Connection, path: &Path) -> Result<()> {
+    conn.execute(
+      "
+DELETE FROM overrides
+WHERE master IN (SELECT id FROM events WHERE path = ?)
+",
+      params![path_str(path)],
+    )?;
+    conn
+      .execute("DELETE FROM events WHERE path = ?", params![path_str(path)])?;
+
+    Ok(())
+  }
+
+  // the same UID may be found in another file
   fn delete_event_entry(
     &self,
     conn: &Connection,
-    event_id: &EventId,
+    event_id: &str,
   ) -> Result<()> {
+    conn.execute(
+      "
+DELETE FROM overrides
+WHERE master IN (SELECT id FROM events WHERE event_id = ?)
+",
+      params![event_id],
+    )?;
     conn.execute("DELETE FROM events WHERE event_id = ?", params![event_id])?;
 
     Ok(())
   }
 
-  fn all_event_entry_ids(&self, conn: &Connection) -> Result<Vec<EventId>> {
-    let mut stmt = conn.prepare_cached("SELECT event_id FROM events")?;
-    let event_ids = stmt
-      .query_map([], |row| row.get::<_, EventId>(0))?
+  // the file an indexed event is in
+  fn event_entry_path(
+    &self,
+    conn: &Connection,
+    event_id: &str,
+  ) -> Result<Option<PathBuf>> {
+    let mut stmt =
+      conn.prepare_cached("SELECT path FROM events WHERE event_id = ?")?;
+    let path = stmt
+      .query_row(params![event_id], |row| row.get::<_, String>(0))
+      .optional()?;
+    Ok(path.map(PathBuf::from))
+  }
+
+  fn all_file_entry_paths(&self, conn: &Connection) -> Result<Vec<PathBuf>> {
+    let mut stmt = conn.prepare_cached("SELECT DISTINCT path FROM events")?;
+    let paths = stmt
+      .query_map([], |row| row.get::<_, String>(0))?
       .filter_map(|x| x.ok())
+      .map(PathBuf::from)
       .collect();
-    Ok(event_ids)
+    Ok(paths)
   }
 
   pub fn create_table(&self) -> Result<()> {
+    let conn = self.conn.borrow();
+    let version: i64 =
+      conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version != SCHEMA_VERSION {
+      log::info!("Rebuilding index (schema version {version})");
+      conn.execute_batch(
+        "
+DROP TABLE IF EXISTS events;
+DROP TABLE IF EXISTS events_span;
+DROP TABLE IF EXISTS overrides;
+DROP TABLE IF EXISTS events_fts;
+",
+      )?;
+      conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    }
+
     log::debug!("Creating index table");
-    self.conn.borrow().execute_batch(
+    conn.execute_batch(
       "
 BEGIN;
 CREATE TABLE IF NOT EXISTS events (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   event_id TEXT NOT NULL UNIQUE,
+  -- the ics file, which may contain several events
+  path TEXT NOT NULL,
+  content_length INTEGER NOT NULL,
+  modification_date INTEGER NOT NULL,
+  -- the fingerprint hash of the file content when it was indexed
+  content_hash INTEGER NOT NULL,
+  -- when the file was indexed, changes made within the same second
+  -- don't show in the modification date
+  indexed_at INTEGER NOT NULL,
+  -- from the start to the end of the last occurrence for recurring
+  -- events
+  span_start INTEGER NOT NULL,
+  span_end INTEGER NOT NULL,
+
+  calendar TEXT NOT NULL,
+  title TEXT NOT NULL,
+  description TEXT,
   start INTEGER NOT NULL,
   end INTEGER NOT NULL,
-  content_length INTEGER NOT NULL,
-  modification_date INTEGER NOT NULL
+  all_day INTEGER NOT NULL,
+  color TEXT,
+  -- the TZID, NULL for UTC and empty for floating times
+  timezone TEXT,
+  -- the VTIMEZONE of timezones not in the tz database
+  vtimezone TEXT,
+  created_at INTEGER NOT NULL,
+  modified_at INTEGER NOT NULL,
+  timestamp INTEGER NOT NULL,
+
+  -- only used for searching
+  location TEXT,
+  -- the titles, descriptions and locations of the overridden
+  -- occurrences, only used for searching
+  override_text TEXT,
+
+  recurring INTEGER NOT NULL,
+  rrule TEXT,
+  -- comma separated unix timestamps
+  rdates TEXT NOT NULL,
+  exdates TEXT NOT NULL
 );
 
 CREATE INDEX IF NOT EXISTS events_id ON events (event_id);
-CREATE INDEX IF NOT EXISTS events_start ON events (start);
+CREATE INDEX IF NOT EXISTS events_path ON events (path);
+
+-- the modified occurrences of recurring events
+CREATE TABLE IF NOT EXISTS overrides (
+  master INTEGER NOT NULL,
+  recurrence_id INTEGER NOT NULL,
+
+  calendar TEXT NOT NULL,
+  title TEXT NOT NULL,
+  description TEXT,
+  start INTEGER NOT NULL,
+  end INTEGER NOT NULL,
+  all_day INTEGER NOT NULL,
+  color TEXT,
+  timezone TEXT,
+  vtimezone TEXT,
+  created_at INTEGER NOT NULL,
+  modified_at INTEGER NOT NULL,
+  timestamp INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS overrides_master ON overrides (master);
+
+-- the span of each event, for overlap queries. The r-tree rounds the
+-- bounds outwards, so it may return a few events just outside the
+-- interval.
+CREATE VIRTUAL TABLE IF NOT EXISTS events_span
+USING rtree(id, span_start, span_end);
+
+CREATE TRIGGER IF NOT EXISTS events_span_insert AFTER INSERT ON events
+BEGIN
+  INSERT INTO events_span VALUES
+    (NEW.id, NEW.span_start, max(NEW.span_start, NEW.span_end));
+END;
+
+CREATE TRIGGER IF NOT EXISTS events_span_delete AFTER DELETE ON events
+BEGIN
+  DELETE FROM events_span WHERE id = OLD.id;
+END;
+
+-- the text searched, kept in sync with the events table. Diacritics
+-- are kept so that the scan of LocalDir finds the same events.
+CREATE VIRTUAL TABLE IF NOT EXISTS events_fts
+USING fts5(title, description, location, override_text, content='events',
+           content_rowid='id', tokenize='unicode61 remove_diacritics 0');
+
+CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events
+BEGIN
+  INSERT INTO events_fts (rowid, title, description, location, override_text)
+  VALUES (NEW.id, NEW.title, NEW.description, NEW.location,
+          NEW.override_text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events
+BEGIN
+  INSERT INTO events_fts
+    (events_fts, rowid, title, description, location, override_text)
+  VALUES ('delete', OLD.id, OLD.title, OLD.description, OLD.location,
+          OLD.override_text);
+END;
 COMMIT;
 ",
     )?;
@@ -124,181 +313,538 @@ COMMIT;
     &self,
     conn: &Connection,
     event: &Event,
+    path: &Path,
     metadata: &Metadata,
+    indexed_at: i64,
   ) -> Result<()> {
-    let event_id = &event.id;
-    let start = event.start.timestamp();
-    let end = &event.end.timestamp();
-    let length = metadata.len() as usize;
+    let overrides = event.recurrence.as_ref().map_or(&[][..], |r| &r.overrides);
+    let span_start = overrides
+      .iter()
+      .map(|e| e.start)
+      .chain([event.start])
+      .min()
+      .unwrap_or(event.start)
+      .timestamp();
+    // recurring events without an end are always in range
+    let span_end =
+      recurrence::last_end(event).map_or(i64::MAX, |t| t.timestamp());
+    let fingerprint = event
+      .fingerprint
+      .ok_or_else(|| anyhow!("event {} not read from a file", event.id))?;
     let modification_date = metadata.modified()?;
     let modification_timestamp = modification_date
       .duration_since(std::time::SystemTime::UNIX_EPOCH)?
       .as_secs();
 
-    let mut stmt = conn.prepare_cached(
-      "
-INSERT INTO events (event_id, start, end, content_length, modification_date)
-VALUES (?1, ?2, ?3, ?4, ?5)
-ON CONFLICT(event_id)
-DO UPDATE SET start=?2, end=?3, content_length=?4, modification_date=?5
-",
-    )?;
+    let recurrence = event.recurrence.as_ref();
+    let rrule = recurrence.and_then(|r| r.rule.as_ref().map(|r| r.to_string()));
+    let rdates = recurrence.map_or(&[][..], |r| &r.rdates);
+    let exdates = recurrence.map_or(&[][..], |r| &r.exdates);
 
-    stmt.execute(params![
-      event_id,
-      start,
-      end,
-      length,
-      modification_timestamp
-    ])?;
+    self.delete_event_entry(conn, &event.id)?;
+
+    let mut stmt = conn.prepare_cached(&format!(
+      "
+INSERT INTO events
+  (event_id, path, content_length, modification_date, content_hash,
+   indexed_at, span_start, span_end, location, override_text, recurring,
+   rrule, rdates, exdates, {EVENT_COLUMNS})
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14,
+        ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)
+"
+    ))?;
+    let values: [Value; 14] = [
+      event.id.clone().into(),
+      path_str(path).into(),
+      (fingerprint.size as i64).into(),
+      (modification_timestamp as i64).into(),
+      (fingerprint.hash as i64).into(),
+      indexed_at.into(),
+      span_start.into(),
+      span_end.into(),
+      event.location().into(),
+      override_text(overrides).into(),
+      event.is_recurring().into(),
+      rrule.into(),
+      format_times(rdates).into(),
+      format_times(exdates).into(),
+    ];
+    stmt.execute(params_from_iter(
+      values.into_iter().chain(event_values(event)),
+    ))?;
+
+    let master = conn.last_insert_rowid();
+    let mut stmt = conn.prepare_cached(&format!(
+      "
+INSERT INTO overrides (master, recurrence_id, {EVENT_COLUMNS})
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+"
+    ))?;
+    for overridden in overrides {
+      let Some(recurrence_id) = overridden.recurrence_id else {
+        continue;
+      };
+      let values: [Value; 2] =
+        [master.into(), recurrence_id.timestamp().into()];
+      stmt.execute(params_from_iter(
+        values.into_iter().chain(event_values(overridden)),
+      ))?;
+    }
 
     Ok(())
   }
 
-  fn refresh(&mut self) {
+  // poll the directory for changes when it can't be watched
+  fn poll(&mut self) -> Result<bool> {
     if Instant::now() < self.next_refresh_at {
-      return;
+      return Ok(false);
     }
 
-    if let Err(e) = self.force_refresh() {
-      log::error!("Failed refreshing {:?}", e);
+    self.next_refresh_at = Instant::now() + self.refresh_interval;
+    self.rescan()
+  }
+
+  // bring the whole index in line with the directory, returns whether
+  // anything changed
+  fn rescan(&self) -> Result<bool> {
+    let updated = self.refresh_updated_files()?;
+    let deleted = self.refresh_deleted_files()?;
+    Ok(updated || deleted)
+  }
+
+  // whether the file differs from the one indexed
+  fn file_outdated(
+    &self,
+    conn: &Connection,
+    path: &Path,
+    metadata: &Metadata,
+  ) -> Result<bool> {
+    let Ok(event_entry) = self.get_file_entry(conn, path) else {
+      return Ok(true);
+    };
+
+    let file_size = metadata.len() as usize;
+    let mut mod_time: chrono::DateTime<Utc> = metadata.modified()?.into();
+
+    // we only care about second-level modification time
+    mod_time = mod_time
+      .with_nanosecond(0)
+      .expect("failed trimming sub-second units");
+
+    if event_entry.size != file_size || event_entry.modified_at != mod_time {
+      return Ok(true);
     }
 
-    self.next_refresh_at = Instant::now() + self.refresh_interval;
+    // Files last modified before they were indexed are unchanged.
+    // Otherwise they may have been changed to the same size within the
+    // second they were indexed in, which only their content tells.
+    if mod_time < event_entry.indexed_at {
+      return Ok(false);
+    }
+    let content = std::fs::read(path)?;
+    Ok(Fingerprint::of(&content).hash != event_entry.hash)
   }
 
-  fn refresh_updated_files(&self) -> Result<()> {
+  fn refresh_updated_files(&self) -> Result<bool> {
     let mut conn = self.conn.borrow_mut();
     let tx = conn.transaction()?;
+    let mut changed = false;
 
-    for file_entry in self.backend.all_event_file_entries() {
+    for file_entry in self.backend.all_event_file_entries()? {
       let path = file_entry.path();
-      let metadata = file_entry.metadata().unwrap();
-      let file_stem = path.file_stem().unwrap();
-      let event_id = file_stem.to_str().unwrap();
-      if let Ok(event_entry) = self.get_single_event_entry(&tx, event_id) {
-        let file_size = metadata.len() as usize;
-        let mut mod_time: chrono::DateTime<Utc> = metadata
-          .modified()
-          .expect("modification date not available")
-          .into();
-
-        // we only care about second-level modification time
-        mod_time = mod_time
-          .with_nanosecond(0)
-          .expect("failed trimming sub-second units");
-
-        if event_entry.size != file_size || event_entry.modified_at < mod_time {
-          log::debug!("Updating existing event {:?}", path);
-          self.update_event_entry(&tx, path)?;
-        }
-      } else {
-        log::debug!("Creating new event {:?}", path);
-        self.create_event_entry(&tx, path)?;
+      let metadata = file_entry.metadata()?;
+      if self.file_outdated(&tx, &path, &metadata)? {
+        log::debug!("Updating events {:?}", path);
+        self.update_file_entries(&tx, &path)?;
+        changed = true;
       }
     }
 
     tx.commit()?;
 
-    Ok(())
+    Ok(changed)
   }
 
-  fn refresh_deleted_files(&self) -> Result<()> {
+  fn refresh_deleted_files(&self) -> Result<bool> {
     let mut conn = self.conn.borrow_mut();
     let tx = conn.transaction()?;
-    for event_id in self.all_event_entry_ids(&tx)? {
-      let path = self.backend.event_path(&event_id);
+    let mut changed = false;
+    for path in self.all_file_entry_paths(&tx)? {
       if !path.exists() {
-        log::debug!("Deleting event {:?}", event_id);
-        self.delete_event_entry(&tx, &event_id)?;
+        log::debug!("Deleting events {:?}", path);
+        self.delete_file_entries(&tx, &path)?;
+        changed = true;
+      }
+    }
+
+    tx.commit()?;
+
+    Ok(changed)
+  }
+
+  // Apply the changes reported by the watcher, returns whether any
+  // events changed. Changes made by malakal itself are already
+  // indexed and skipped.
+  fn apply_watched_changes(&self, changes: Vec<Change>) -> Result<bool> {
+    let mut paths = vec![];
+    for change in changes {
+      match change {
+        Change::Paths(changed) => paths.extend(changed),
+        Change::Rescan => return self.rescan(),
+      }
+    }
+
+    paths.sort();
+    paths.dedup();
+    paths.retain(|path| {
+      path.parent() == Some(self.backend.dir())
+        && path.extension().and_then(OsStr::to_str) == Some("ics")
+    });
+
+    let mut conn = self.conn.borrow_mut();
+    let tx = conn.transaction()?;
+    let mut changed = false;
+    for path in paths {
+      match path.metadata() {
+        Ok(metadata) if metadata.is_file() => {
+          if !self.file_outdated(&tx, &path, &metadata)? {
+            continue;
+          }
+
+          log::debug!("Updating events {:?}", path);
+          // the file may be half written, it is indexed again once
+          // the writing is done
+          if let Err(e) = self.update_file_entries(&tx, &path) {
+            log::warn!("Failed indexing {:?}: {e:?}", path);
+            continue;
+          }
+          changed = true;
+        }
+        _ if self.get_file_entry(&tx, &path).is_ok() => {
+          log::debug!("Deleting events {:?}", path);
+          self.delete_file_entries(&tx, &path)?;
+          changed = true;
+        }
+        _ => (),
       }
     }
 
     tx.commit()?;
 
+    Ok(changed)
+  }
+
+  // replace the entries of the events in the file
+  fn update_file_entries(&self, conn: &Connection, path: &Path) -> Result<()> {
+    // taken before reading, so that later changes are never missed
+    let indexed_at = Utc::now().timestamp();
+    let metadata = path.metadata()?;
+    let events = self.backend.parse_events(path)?;
+
+    self.delete_file_entries(conn, path)?;
+    for event in events.iter() {
+      self.upsert(conn, event, path, &metadata, indexed_at)?;
+    }
     Ok(())
   }
 
-  fn create_event_entry<P: AsRef<Path>>(
-    &self,
-    conn: &Connection,
-    file: P,
-  ) -> Result<()> {
-    self.update_event_entry(conn, file)
+  // bring the entries of the given files in line with their content
+  fn reindex_files(&self, paths: &[PathBuf]) -> Result<()> {
+    let conn = self.conn.borrow();
+    for path in paths {
+      if path.exists() {
+        self.update_file_entries(&conn, path)?;
+      } else {
+        self.delete_file_entries(&conn, path)?;
+      }
+    }
+
+    Ok(())
   }
 
-  fn update_event_entry<P: AsRef<Path>>(
-    &self,
-    conn: &Connection,
-    file: P,
-  ) -> Result<()> {
-    let metadata = file.as_ref().metadata().unwrap();
-    let event = self.backend.parse_event(file)?;
-    self.upsert(conn, &event, &metadata)
+  // The file recorded for the event. Files not indexed yet are only
+  // found if they are named after the UID.
+  fn locate(&self, uid: &str) -> Option<PathBuf> {
+    let path = self.event_entry_path(&self.conn.borrow(), uid);
+    match path {
+      Ok(Some(path)) => Some(path),
+      Ok(None) => self.backend.named_event_file(uid),
+      Err(e) => {
+        log::error!("Failed looking up event {uid}: {e:?}");
+        None
+      }
+    }
   }
 
-  fn all_event_entry_ids_between(
+  // the events overlapping the interval, recurring events are not
+  // expanded
+  fn event_entries_between(
     &self,
     from: DateTime,
     to: DateTime,
-  ) -> Result<Vec<EventId>> {
-    let start = from.timestamp();
-    let end = to.timestamp();
+  ) -> Result<Vec<Event>> {
+    self.event_entries(
+      "
+FROM events_span JOIN events ON events.id = events_span.id
+WHERE events_span.span_start <= ?2 AND events_span.span_end >= ?1
+",
+      params![from.timestamp(), to.timestamp()],
+    )
+  }
+
+  // the events matching the FTS5 query whose span overlaps the
+  // interval
+  fn event_entries_matching(
+    &self,
+    fts_query: &str,
+    from: Option<DateTime>,
+    to: Option<DateTime>,
+  ) -> Result<Vec<Event>> {
+    self.event_entries(
+      "
+FROM events
+WHERE id IN (SELECT rowid FROM events_fts WHERE events_fts MATCH ?1)
+  AND span_start <= ?3 AND span_end >= ?2
+",
+      params![
+        fts_query,
+        from.map_or(i64::MIN, |t| t.timestamp()),
+        to.map_or(i64::MAX, |t| t.timestamp())
+      ],
+    )
+  }
 
+  // the events of the rows selected by the FROM and WHERE clauses
+  fn event_entries(
+    &self,
+    clauses: &str,
+    params: impl rusqlite::Params,
+  ) -> Result<Vec<Event>> {
     let conn = self.conn.borrow();
-    let mut stmt = conn.prepare_cached(
-      "SELECT event_id FROM events WHERE start >= ? AND end <= ?",
-    )?;
-    let event_ids = stmt
-      .query_map([start, end], |row| row.get::<_, EventId>(0))?
-      .filter_map(|x| x.ok())
-      .collect();
-    Ok(event_ids)
+    let mut stmt = conn.prepare_cached(&format!(
+      "
+SELECT events.id, event_id, recurring, rrule, rdates, exdates,
+  content_length, content_hash, {EVENT_COLUMNS}
+{clauses}"
+    ))?;
+
+    let mut events = vec![];
+    let mut rows = stmt.query(params)?;
+    while let Some(row) = rows.next()? {
+      // a bad entry doesn't keep the other events from being shown
+      match self.event_entry(&conn, row) {
+        Ok(event) => events.push(event),
+        Err(e) => log::warn!("Skipping event in the index: {e:#}"),
+      }
+    }
+
+    Ok(events)
+  }
+
+  // the event of a row selected by event_entries
+  fn event_entry(&self, conn: &Connection, row: &Row) -> Result<Event> {
+    let event_id: String = row.get(1)?;
+    let mut event = read_event(row, 8, &event_id)
+      .map_err(|e| anyhow!("read event {event_id} from index: {e}"))?;
+    let fingerprint = Fingerprint {
+      size: row.get::<_, i64>(6)? as u64,
+      hash: row.get::<_, i64>(7)? as u64,
+    };
+
+    if row.get::<_, bool>(2)? {
+      event.recurrence = Some(Recurrence {
+        rule: row
+          .get::<_, Option<String>>(3)?
+          .map(|rule| rule.parse())
+          .transpose()?,
+        rdates: parse_times(&row.get::<_, String>(4)?)?,
+        exdates: parse_times(&row.get::<_, String>(5)?)?,
+        overrides: self.override_entries(conn, row.get(0)?, &event_id)?,
+      });
+    }
+
+    event.set_fingerprint(fingerprint);
+    Ok(event)
+  }
+
+  fn override_entries(
+    &self,
+    conn: &Connection,
+    master: i64,
+    event_id: &str,
+  ) -> Result<Vec<Event>> {
+    let mut stmt = conn.prepare_cached(&format!(
+      "SELECT recurrence_id, {EVENT_COLUMNS} FROM overrides WHERE master = ?"
+    ))?;
+
+    let mut overrides = vec![];
+    let mut rows = stmt.query([master])?;
+    while let Some(row) = rows.next()? {
+      let mut overridden = read_event(row, 1, event_id)?;
+      overridden.recurrence_id = Some(from_timestamp(row.get(0)?)?);
+      overrides.push(overridden);
+    }
+
+    Ok(overrides)
+  }
+
+  // Events served from the index don't carry the ical components they
+  // were parsed from, which keep the properties malakal doesn't know
+  // about. Those are read from the file before writing the event.
+  fn with_raw(&self, event: &Event) -> Event {
+    let mut event = event.clone();
+    if event.raw.is_some() {
+      return event;
+    }
+
+    let locate = |uid: &str| self.locate(uid);
+    if let Ok(stored) = self.backend.locate_event(&event.id, &locate) {
+      event.raw = stored.raw;
+    }
+    event
   }
 }
 
 impl Backend for IndexedLocalDir {
+  fn calendars(&self) -> Vec<CalendarInfo> {
+    vec![self.backend.calendar_info()]
+  }
+
   fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>> {
-    self.refresh();
+    let events = self.event_entries_between(from, to)?;
+
+    Ok(
+      events
+        .into_iter()
+        .flat_map(|event| instances_in_range(event, from, to))
+        .collect(),
+    )
+  }
 
-    let event_ids = self.all_event_entry_ids_between(from, to)?;
+  fn search(&mut self, query: &SearchQuery) -> Result<Vec<Event>> {
+    let Some(fts_query) = query.fts_query() else {
+      return Ok(vec![]);
+    };
 
-    let events = event_ids.into_iter().filter_map(|id| {
-      let path = self.backend.event_path(&id);
-      self.backend.parse_event(path).ok()
-    });
+    // the locations of a series and its overridden occurrences are
+    // only in the file, they tell which of them matched
+    let locate = |uid: &str| self.locate(uid);
+    let events = self
+      .event_entries_matching(&fts_query, query.from, query.to)?
+      .into_iter()
+      .map(|event| match event.is_recurring() {
+        true => self
+          .backend
+          .locate_event(&event.id, &locate)
+          .unwrap_or(event),
+        false => event,
+      });
+    Ok(query.hits(events))
+  }
+
+  fn delete_event(&mut self, event: &Event) -> Result<()> {
+    let locate = |uid: &str| self.locate(uid);
+    if self
+      .backend
+      .split_occurrence_id(&event.id, &locate)
+      .is_some()
+    {
+      return self.delete_occurrences(event, RecurrenceScope::ThisOnly);
+    }
 
-    Ok(events.collect())
+    let Some(path) = locate(&event.id) else {
+      log::warn!("Event {} to delete not found", event.id);
+      return Ok(());
+    };
+
+    self.backend.remove_event(&path, event)?;
+    self.reindex_files(&[path])
   }
 
-  fn delete_event(&mut self, event_id: &EventId) -> Result<()> {
-    self.backend.delete_event(event_id)?;
-    self.delete_event_entry(&self.conn.borrow(), event_id)?;
-    Ok(())
+  fn update_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<Option<EventId>> {
+    let occurrence = self.with_raw(occurrence);
+    let locate = |uid: &str| self.locate(uid);
+    let (touched, following) =
+      self.backend.update_series(&occurrence, scope, &locate)?;
+    self.reindex_files(&touched)?;
+    Ok(following)
+  }
+
+  fn delete_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<()> {
+    let locate = |uid: &str| self.locate(uid);
+    let touched = self.backend.delete_series(occurrence, scope, &locate)?;
+    self.reindex_files(&touched)
   }
 
   fn update_event(&mut self, event: &Event) -> Result<()> {
-    self.backend.update_event(event)?;
-    let path = self.backend.event_path(&event.id);
-    self.update_event_entry(&self.conn.borrow(), path)?;
-    Ok(())
+    if event.recurrence_id.is_some() {
+      self.update_occurrences(event, RecurrenceScope::ThisOnly)?;
+      return Ok(());
+    }
+
+    // new events are saved through updates as well
+    let event = self.with_raw(event);
+    let path = self.locate(&event.id);
+    let path = self.backend.write_event(path.as_deref(), &event)?;
+    self.reindex_files(&[path])
   }
 
   fn create_event(&mut self, event: &Event) -> Result<()> {
-    self.backend.create_event(event)?;
-    let path = self.backend.event_path(&event.id);
-    self.create_event_entry(&self.conn.borrow(), path)
+    let path = self.backend.write_event(None, event)?;
+    self.reindex_files(&[path])
   }
 
   fn get_event(&mut self, event_id: &EventId) -> Result<Event> {
-    self.backend.get_event(event_id)
+    self
+      .backend
+      .locate_event(event_id, &|uid: &str| self.locate(uid))
   }
 
   fn force_refresh(&mut self) -> Result<()> {
-    self.refresh_updated_files()?;
-    self.refresh_deleted_files()?;
+    self.rescan()?;
     Ok(())
   }
+
+  fn trashed_events(&mut self) -> Result<Vec<TrashedEvent>> {
+    self.backend.trashed_events()
+  }
+
+  fn restore_event(&mut self, trashed: &TrashedEvent) -> Result<()> {
+    let locate = |uid: &str| self.locate(uid);
+    let path = self
+      .backend
+      .restore_event_file(&trashed.trash_id, &locate)?;
+    self.reindex_files(&[path])
+  }
+
+  fn purge_trash(&mut self, before: DateTime) -> Result<()> {
+    self.backend.purge_trash(before)
+  }
+
+  fn set_waker(&mut self, waker: Waker) {
+    if let Some(watcher) = &self.watcher {
+      watcher.set_waker(waker);
+    }
+  }
+
+  fn sync_changes(&mut self) -> Result<bool> {
+    match &self.watcher {
+      Some(watcher) => self.apply_watched_changes(watcher.take_changes()),
+      None => self.poll(),
+    }
+  }
+}
+
+fn path_str(path: &Path) -> String {
+  path.to_string_lossy().into_owned()
 }
 
 fn from_unix_timestamp(i: i64) -> chrono::DateTime<Utc> {
@@ -306,3 +852,103 @@ fn from_unix_timestamp(i: i64) -> chrono::DateTime<Utc> {
   let d = UNIX_EPOCH + Duration::from_secs(i as u64);
   chrono::DateTime::<Utc>::from(d)
 }
+
+// the values of EVENT_COLUMNS
+fn event_values(event: &Event) -> [Value; 12] {
+  let (timezone, vtimezone) = match &event.timezone {
+    EventTimezone::Utc => (None, None),
+    EventTimezone::Floating => (Some(String::new()), None),
+    EventTimezone::Zoned(zone @ Zone::Iana(_)) => {
+      (Some(zone.tzid().to_string()), None)
+    }
+    EventTimezone::Zoned(Zone::Custom(zone)) => {
+      (Some(zone.tzid.clone()), Some(format_zone_definition(zone)))
+    }
+  };
+
+  [
+    event.calendar.clone().into(),
+    event.title.clone().into(),
+    event.description.clone().into(),
+    event.start.timestamp().into(),
+    event.end.timestamp().into(),
+    event.all_day.into(),
+    event.color.map(color_hex).into(),
+    timezone.into(),
+    vtimezone.into(),
+    event.created_at.timestamp().into(),
+    event.modified_at.timestamp().into(),
+    event.timestamp.timestamp().into(),
+  ]
+}
+
+// the text of the overridden occurrences searched along with the event,
+// None if there are none
+fn override_text(overrides: &[Event]) -> Option<String> {
+  let fields: Vec<_> = overrides
+    .iter()
+    .flat_map(|e| [Some(e.title.clone()), e.description.clone(), e.location()])
+    .flatten()
+    .collect();
+  (!fields.is_empty()).then(|| fields.join("\n"))
+}
+
+// an event from EVENT_COLUMNS, starting at the given column
+fn read_event(row: &Row, first: usize, event_id: &str) -> Result<Event> {
+  let column = |i: usize| first + i;
+  let time = |i: usize| from_timestamp(row.get(column(i))?);
+
+  let timezone = match (
+    row.get::<_, Option<String>>(column(7))?,
+    row.get::<_, Option<String>>(column(8))?,
+  ) {
+    (None, _) => EventTimezone::Utc,
+    (Some(tzid), _) if tzid.is_empty() => EventTimezone::Floating,
+    (Some(_), Some(vtimezone)) => {
+      EventTimezone::Zoned(parse_zone_definition(&vtimezone)?)
+    }
+    (Some(tzid), None) => EventTimezone::Zoned(
+      Zone::from_tzid(&tzid).ok_or_else(|| anyhow!("unknown TZID {tzid}"))?,
+    ),
+  };
+
+  let event = EventBuilder::default()
+    .id(event_id)
+    .calendar(row.get::<_, String>(column(0))?)
+    .title(row.get::<_, String>(column(1))?)
+    .description(row.get::<_, Option<String>>(column(2))?)
+    .start(time(3)?)
+    .end(time(4)?)
+    .all_day(row.get::<_, bool>(column(5))?)
+    .color(
+      row
+        .get::<_, Option<String>>(column(6))?
+        .and_then(|color| parse_color(&color)),
+    )
+    .timezone(timezone)
+    .created_at(time(9)?)
+    .modified_at(time(10)?)
+    .timestamp(time(11)?)
+    .build()?;
+
+  Ok(event)
+}
+
+fn from_timestamp(timestamp: i64) -> Result<DateTime> {
+  chrono::DateTime::from_timestamp(timestamp, 0)
+    .map(|t| t.fixed_offset())
+    .ok_or_else(|| anyhow!("invalid timestamp {timestamp}"))
+}
+
+fn format_times(times: &[DateTime]) -> String {
+  let timestamps: Vec<_> =
+    times.iter().map(|t| t.timestamp().to_string()).collect();
+  timestamps.join(",")
+}
+
+fn parse_times(s: &str) -> Result<Vec<DateTime>> {
+  s.split(',')
+    .filter(|t| !t.is_empty())
+    .map(|t| from_timestamp(t.parse()?))
+    .collect()
+}
@@ -2,124 +2,525 @@ use anyhow::Context;
 use derive_builder::Builder;
 use filetime::FileTime;
 use std::{
+  collections::HashMap,
   ffi::OsStr,
   fs::DirEntry,
+  io::Write,
   path::{Path, PathBuf},
+  sync::Mutex,
 };
+use uuid::Uuid;
+
+use super::trash::Trash;
 
 use crate::{
-  backend::Backend,
+  backend::{Backend, CalendarInfo, Conflict, Fingerprint, TrashedEvent},
   event::{Event, EventId},
-  ical::ICal,
-  util::{DateTime, Result},
+  ical::{parse_color, ICal},
+  recurrence::{self, parse_occurrence_id, RecurrenceScope},
+  search::SearchQuery,
+  util::{anyhow, DateTime, Result},
 };
 
+// file names longer than this (without the extension) are replaced
+const MAX_FILE_STEM_LENGTH: usize = 200;
+
+// finds the file containing an event by its UID
+pub(crate) type Locate<'a> = &'a dyn Fn(&str) -> Option<PathBuf>;
+
 #[derive(Builder)]
 #[builder(try_setter, setter(into))]
 pub struct LocalDir {
   dir: PathBuf,
   calendar: String,
+  // the content last written to each file, events read before are
+  // not in conflict with it
+  #[builder(setter(skip), default)]
+  written: Mutex<HashMap<PathBuf, Fingerprint>>,
 }
 
 impl LocalDir {
-  pub(crate) fn all_event_file_entries(
-    &self,
-  ) -> impl Iterator<Item = DirEntry> + '_ {
-    let entries = self.dir.read_dir().expect("read_dir failed");
-    entries
-      .into_iter()
-      .filter_map(|entry| entry.ok())
-      .filter(|entry| entry.file_type().unwrap().is_file())
-      .filter(|entry| {
-        entry.path().extension().and_then(OsStr::to_str) == Some("ics")
-      })
+  // the displayname and color files of a vdir collection
+  pub(crate) fn calendar_info(&self) -> CalendarInfo {
+    let read = |name: &str| {
+      std::fs::read_to_string(self.dir.join(name))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+    };
+
+    let color = read("color").and_then(|s| {
+      let color = parse_color(&s);
+      if color.is_none() {
+        log::warn!("Ignoring invalid calendar color {s}");
+      }
+      color
+    });
+
+    CalendarInfo {
+      name: self.calendar.clone(),
+      display_name: read("displayname"),
+      color,
+    }
+  }
+
+  pub(crate) fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  pub(crate) fn all_event_file_entries(&self) -> Result<Vec<DirEntry>> {
+    let entries = self
+      .dir
+      .read_dir()
+      .with_context(|| format!("read calendar dir {}", self.dir.display()))?;
+
+    let mut files = vec![];
+    for entry in entries {
+      let entry = entry?;
+      let is_ics =
+        entry.path().extension().and_then(OsStr::to_str) == Some("ics");
+      if is_ics && entry.file_type()?.is_file() {
+        files.push(entry);
+      }
+    }
+    Ok(files)
   }
 
-  pub(crate) fn parse_event<P: AsRef<Path>>(&self, path: P) -> Result<Event> {
+  // an ics file may contain several events
+  pub(crate) fn parse_events<P: AsRef<Path>>(
+    &self,
+    path: P,
+  ) -> Result<Vec<Event>> {
     let path = path.as_ref().to_owned();
     let content = std::fs::read(&path)?;
+    let fingerprint = Fingerprint::of(&content);
     let string = String::from_utf8(content)?;
 
-    ICal
+    let mut events = ICal
       .parse(&self.calendar, &string)
-      .with_context(|| format!("parse ics file: {}", path.display()))
+      .with_context(|| format!("parse ics file: {}", path.display()))?;
+    for event in events.iter_mut() {
+      event.set_fingerprint(fingerprint);
+    }
+    Ok(events)
   }
 
-  fn all_events(&self) -> impl Iterator<Item = Event> + '_ {
-    self
+  // the events of all files, files which can't be parsed are skipped
+  pub(crate) fn all_events(&self) -> Result<Vec<Event>> {
+    let events = self
+      .all_event_file_entries()?
+      .into_iter()
+      .filter_map(|entry| self.parse_events(entry.path()).ok())
+      .flatten()
+      .collect();
+    Ok(events)
+  }
+
+  // the file named after the UID, if it exists
+  pub(crate) fn named_event_file(&self, uid: &str) -> Option<PathBuf> {
+    let path = self.dir.join(format!("{}.ics", safe_file_stem(uid)));
+    path.exists().then_some(path)
+  }
+
+  // The file for a new event, named after the UID unless that name is
+  // too long or taken
+  fn new_event_path(&self, uid: &str) -> PathBuf {
+    let stem = safe_file_stem(uid);
+    let path = self.dir.join(format!("{stem}.ics"));
+    if stem.len() <= MAX_FILE_STEM_LENGTH && !path.exists() {
+      return path;
+    }
+
+    let stem = Uuid::new_v4().hyphenated().to_string();
+    self.dir.join(format!("{stem}.ics"))
+  }
+
+  // the file containing the event, usually named after the UID,
+  // otherwise the directory is searched for it
+  pub(crate) fn find_event_file(&self, uid: &str) -> Option<PathBuf> {
+    let contains_event = |path: &PathBuf| {
+      self
+        .parse_events(path)
+        .is_ok_and(|events| events.iter().any(|e| e.id == uid))
+    };
+
+    if let Some(path) = self.named_event_file(uid).filter(contains_event) {
+      return Some(path);
+    }
+
+    let entries = self
       .all_event_file_entries()
-      .filter_map(|entry| self.parse_event(entry.path()).ok())
+      .map_err(|e| log::error!("Failed to search for event {uid}: {e:#}"))
+      .ok()?;
+    entries
+      .into_iter()
+      .map(|entry| entry.path())
+      .find(contains_event)
+  }
+
+  pub(crate) fn read_event(&self, path: &Path, uid: &str) -> Result<Event> {
+    self
+      .parse_events(path)?
+      .into_iter()
+      .find(|e| e.id == uid)
+      .ok_or_else(|| anyhow!("event {uid} not found in {}", path.display()))
+  }
+
+  // Rewrite the event in the file containing it, or create a file for
+  // a new event. Returns the file written.
+  pub(crate) fn write_event(
+    &self,
+    path: Option<&Path>,
+    event: &Event,
+  ) -> Result<PathBuf> {
+    let Some(path) = path else {
+      let path = self.new_event_path(&event.id);
+      log::debug!("Creating event {:?}", path);
+      self.write_file(&path, &ICal.generate(event)?)?;
+      return Ok(path);
+    };
+
+    let content = std::fs::read_to_string(path)?;
+    self.check_conflict(path, &content, event)?;
+    let ics_content = ICal.update(&content, event)?;
+
+    log::debug!("Updating event {:?}", path);
+    self.write_file(path, &ics_content)?;
+    touch_dir(&self.dir);
+
+    Ok(path.to_owned())
+  }
+
+  // Move the event from its file to the trash, the file is deleted if
+  // no events are left in it.
+  pub(crate) fn remove_event(&self, path: &Path, event: &Event) -> Result<()> {
+    let uid = event.id.as_str();
+    let content = std::fs::read_to_string(path)?;
+    self.check_conflict(path, &content, event)?;
+    let trash = self.trash();
+    match ICal.remove(&content, uid)? {
+      Some(rest) => {
+        log::debug!("Removing event {uid} from {:?}", path);
+        let trash_path = trash.new_path(&safe_file_stem(uid))?;
+        self.write_file(&trash_path, &ICal.extract(&content, uid)?)?;
+        self.write_file(path, &rest)?;
+        touch_dir(&self.dir);
+      }
+      None => {
+        log::debug!("Removing event {:?}", path);
+        let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or(uid);
+        std::fs::rename(path, trash.new_path(stem)?)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  pub(crate) fn trash(&self) -> Trash {
+    Trash::of(&self.dir)
   }
 
-  pub(crate) fn event_path(&self, event_id: &EventId) -> PathBuf {
-    let mut path = self.dir.clone();
-    path.push(format!("{event_id}.ics"));
-    path
+  // Move a deleted event back from the trash, unless an event with
+  // its UID was created since. Returns the file restored.
+  pub(crate) fn restore_event_file(
+    &self,
+    trash_id: &str,
+    locate: Locate<'_>,
+  ) -> Result<PathBuf> {
+    let trash_path = self.trash().path(trash_id)?;
+    let uid = self
+      .parse_events(&trash_path)?
+      .into_iter()
+      .next()
+      .map(|e| e.id)
+      .ok_or_else(|| anyhow!("{trash_id} contains no events"))?;
+    if locate(&uid).is_some() {
+      return Err(anyhow!("event {uid} already exists"));
+    }
+
+    let path = self.new_event_path(&uid);
+    log::debug!("Restoring event {uid} to {:?}", path);
+    std::fs::rename(&trash_path, &path)?;
+    touch_dir(&self.dir);
+    Ok(path)
+  }
+
+  // Replace the file through a temporary file, so that it is never
+  // left half written
+  fn write_file(&self, path: &Path, content: &str) -> Result<()> {
+    let file_name = path
+      .file_name()
+      .and_then(OsStr::to_str)
+      .ok_or_else(|| anyhow!("invalid event file {}", path.display()))?;
+    // hidden and without the ics extension, so it's not taken for an
+    // event file
+    let temp_path = self
+      .dir
+      .join(format!(".{file_name}.{}.tmp", Uuid::new_v4()));
+
+    let write = || -> Result<()> {
+      let mut file = std::fs::File::create(&temp_path)?;
+      file.write_all(content.as_bytes())?;
+      file.sync_all()?;
+      std::fs::rename(&temp_path, path)?;
+      Ok(())
+    };
+
+    if let Err(e) = write() {
+      let _ = std::fs::remove_file(&temp_path);
+      return Err(e);
+    }
+
+    let fingerprint = Fingerprint::of(content.as_bytes());
+    self
+      .written
+      .lock()
+      .unwrap()
+      .insert(path.to_owned(), fingerprint);
+    Ok(())
+  }
+
+  // Fails if the file was changed by someone else since the event was
+  // read. Events not read from a file are never in conflict.
+  fn check_conflict(
+    &self,
+    path: &Path,
+    content: &str,
+    event: &Event,
+  ) -> Result<()> {
+    let Some(expected) = event.fingerprint else {
+      return Ok(());
+    };
+
+    let current = Fingerprint::of(content.as_bytes());
+    let written = self.written.lock().unwrap().get(path).copied();
+    if current == expected || written == Some(current) {
+      return Ok(());
+    }
+
+    Err(
+      Conflict {
+        event_id: event.id.clone(),
+        path: path.to_owned(),
+      }
+      .into(),
+    )
+  }
+
+  // the recurring event and the recurrence id an occurrence id refers
+  // to, if any
+  pub(crate) fn split_occurrence_id<'a>(
+    &self,
+    event_id: &'a EventId,
+    locate: Locate<'_>,
+  ) -> Option<(&'a str, DateTime)> {
+    let occurrence = parse_occurrence_id(event_id)?;
+    if locate(event_id).is_some() {
+      return None;
+    }
+
+    Some(occurrence)
+  }
+
+  pub(crate) fn locate_event(
+    &self,
+    event_id: &EventId,
+    locate: Locate<'_>,
+  ) -> Result<Event> {
+    if let Some((uid, recurrence_id)) =
+      self.split_occurrence_id(event_id, locate)
+    {
+      let master = self.locate_event(&uid.to_string(), locate)?;
+      return Ok(recurrence::occurrence(&master, recurrence_id));
+    }
+
+    let path =
+      locate(event_id).ok_or_else(|| anyhow!("event {event_id} not found"))?;
+    self.read_event(&path, event_id)
+  }
+
+  // Apply the change of an occurrence to the series, returns the
+  // files touched and the series split off, if any.
+  pub(crate) fn update_series(
+    &self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+    locate: Locate<'_>,
+  ) -> Result<(Vec<PathBuf>, Option<EventId>)> {
+    let uid = occurrence.uid().to_string();
+    occurrence
+      .recurrence_id
+      .ok_or_else(|| anyhow!("event {uid} is not an occurrence"))?;
+    let path = locate(&uid).ok_or_else(|| anyhow!("event {uid} not found"))?;
+    self.check_conflict(&path, &std::fs::read_to_string(&path)?, occurrence)?;
+    let mut master = self.read_event(&path, &uid)?;
+    let mut touched = vec![];
+
+    let following = recurrence::apply_change(&mut master, occurrence, scope);
+    if let Some(following) = following.as_ref() {
+      touched.push(self.write_event(None, following)?);
+    }
+
+    touched.push(self.write_event(Some(&path), &master)?);
+    Ok((touched, following.map(|e| e.id)))
+  }
+
+  // Delete the occurrences of a series, returns the files touched.
+  pub(crate) fn delete_series(
+    &self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+    locate: Locate<'_>,
+  ) -> Result<Vec<PathBuf>> {
+    let event_id = &occurrence.id;
+    let (uid, recurrence_id) = parse_occurrence_id(event_id)
+      .ok_or_else(|| anyhow!("event {event_id} is not an occurrence"))?;
+    let path = locate(uid).ok_or_else(|| anyhow!("event {uid} not found"))?;
+    self.check_conflict(&path, &std::fs::read_to_string(&path)?, occurrence)?;
+    let mut master = self.read_event(&path, uid)?;
+
+    if !recurrence::delete_occurrences(&mut master, recurrence_id, scope) {
+      self.remove_event(&path, &master)?;
+      return Ok(vec![path]);
+    }
+
+    self.write_event(Some(&path), &master)?;
+    Ok(vec![path])
   }
 }
 
 impl Backend for LocalDir {
+  fn calendars(&self) -> Vec<CalendarInfo> {
+    vec![self.calendar_info()]
+  }
+
   fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>> {
     let mut events = vec![];
-    for event in self.all_events() {
-      if event_visible_in_range(&event, from, to) {
-        events.push(event);
-      }
+    for event in self.all_events()? {
+      events.extend(instances_in_range(event, from, to));
     }
 
     Ok(events)
   }
 
-  fn delete_event(&mut self, event_id: &EventId) -> Result<()> {
-    let path = self.event_path(event_id);
-    if path.exists() {
-      log::debug!("Removing event {:?}", path);
-      std::fs::remove_file(path)?;
-    } else {
-      // TODO: log
+  // all files are read for every search
+  fn search(&mut self, query: &SearchQuery) -> Result<Vec<Event>> {
+    let events = self.all_events()?.into_iter().filter(|e| query.matches(e));
+    Ok(query.hits(events))
+  }
+
+  fn delete_event(&mut self, event: &Event) -> Result<()> {
+    let locate = |uid: &str| self.find_event_file(uid);
+    if self.split_occurrence_id(&event.id, &locate).is_some() {
+      self.delete_series(event, RecurrenceScope::ThisOnly, &locate)?;
+      return Ok(());
     }
 
-    Ok(())
+    match locate(&event.id) {
+      Some(path) => self.remove_event(&path, event),
+      None => {
+        log::warn!("Event {} to delete not found", event.id);
+        Ok(())
+      }
+    }
   }
 
   fn update_event(&mut self, updated_event: &Event) -> Result<()> {
-    let ics_content = ICal.generate(updated_event)?;
-    let path = self.event_path(&updated_event.id);
-
-    if !path.exists() {
-      // TODO: show warning
+    let locate = |uid: &str| self.find_event_file(uid);
+    if updated_event.recurrence_id.is_some() {
+      self.update_series(updated_event, RecurrenceScope::ThisOnly, &locate)?;
+      return Ok(());
     }
 
-    log::debug!("Updating event {:?}", path);
-    std::fs::write(path, ics_content)?;
-    touch_dir(&self.dir);
-
+    // new events are saved through updates as well
+    let path = locate(&updated_event.id);
+    self.write_event(path.as_deref(), updated_event)?;
     Ok(())
   }
 
   fn create_event(&mut self, event: &Event) -> Result<()> {
-    let ics_content = ICal.generate(event)?;
-    let path = self.event_path(&event.id);
+    self.write_event(None, event)?;
+    Ok(())
+  }
 
-    log::debug!("Creating event {:?}", path);
-    std::fs::write(path, ics_content)?;
+  fn update_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<Option<EventId>> {
+    let locate = |uid: &str| self.find_event_file(uid);
+    let (_, following) = self.update_series(occurrence, scope, &locate)?;
+    Ok(following)
+  }
 
+  fn delete_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<()> {
+    let locate = |uid: &str| self.find_event_file(uid);
+    self.delete_series(occurrence, scope, &locate)?;
     Ok(())
   }
 
   fn get_event(&mut self, event_id: &EventId) -> Result<Event> {
-    let path = self.event_path(event_id);
-    let buffer = std::fs::read(path)?;
-    let string = String::from_utf8(buffer)?;
+    self.locate_event(event_id, &|uid| self.find_event_file(uid))
+  }
+
+  fn trashed_events(&mut self) -> Result<Vec<TrashedEvent>> {
+    self.trash().entries(&self.calendar)
+  }
+
+  fn restore_event(&mut self, trashed: &TrashedEvent) -> Result<()> {
+    let locate = |uid: &str| self.find_event_file(uid);
+    self.restore_event_file(&trashed.trash_id, &locate)?;
+    Ok(())
+  }
 
-    ICal.parse(&self.calendar, &string)
+  fn purge_trash(&mut self, before: DateTime) -> Result<()> {
+    self.trash().purge(before)
   }
 }
 
+// the event itself or its occurrences that overlap with the interval
+pub(crate) fn instances_in_range(
+  event: Event,
+  from: DateTime,
+  to: DateTime,
+) -> Vec<Event> {
+  let instances = if event.is_recurring() {
+    recurrence::expand(&event, from, to)
+  } else {
+    vec![event]
+  };
+
+  instances
+    .into_iter()
+    .filter(|e| event_visible_in_range(e, from, to))
+    .collect()
+}
+
 fn event_visible_in_range(e: &Event, start: DateTime, end: DateTime) -> bool {
   e.start.max(start) <= e.end.min(end)
 }
 
+// UIDs may contain characters not allowed in file names (e.g. /), those
+// are percent-encoded
+fn safe_file_stem(uid: &str) -> String {
+  let mut stem = String::new();
+  for (i, b) in uid.bytes().enumerate() {
+    let safe = b.is_ascii_alphanumeric()
+      || matches!(b, b'-' | b'_' | b'@' | b'+' | b'=')
+      || (b == b'.' && i > 0);
+    if safe {
+      stem.push(b as char);
+    } else {
+      stem.push_str(&format!("%{b:02X}"));
+    }
+  }
+  stem
+}
+
 fn touch_dir(path: &Path) {
   let mtime = FileTime::now();
 
@@ -0,0 +1,183 @@
+use crate::{
+  backend::{Backend, CalendarInfo, TrashedEvent, Waker},
+  event::{Event, EventId},
+  recurrence::RecurrenceScope,
+  search::SearchQuery,
+  util::{anyhow, DateTime, Result},
+};
+
+// Several calendars merged into one view. Changes are routed to the
+// calendar the event belongs to.
+pub struct MultiCalendar {
+  calendars: Vec<(String, Box<dyn Backend>)>,
+}
+
+impl MultiCalendar {
+  pub fn new() -> Self {
+    Self { calendars: vec![] }
+  }
+
+  pub fn add_calendar(&mut self, name: &str, backend: impl Backend + 'static) {
+    self.calendars.push((name.to_string(), Box::new(backend)));
+  }
+
+  fn calendar_mut(
+    &mut self,
+    name: &str,
+  ) -> Result<&mut (dyn Backend + 'static)> {
+    self
+      .calendars
+      .iter_mut()
+      .find(|(n, _)| n == name)
+      .map(|(_, backend)| backend.as_mut())
+      .ok_or_else(|| anyhow!("unknown calendar {name}"))
+  }
+
+  // Run the operation on every calendar. A calendar failing is logged
+  // and skipped so that it doesn't hide the others, only when all of
+  // them fail is it an error.
+  fn each_calendar<T>(
+    &mut self,
+    what: &str,
+    mut operation: impl FnMut(&mut dyn Backend) -> Result<T>,
+  ) -> Result<Vec<T>> {
+    let mut results = vec![];
+    let mut error = None;
+    for (name, backend) in self.calendars.iter_mut() {
+      match operation(backend.as_mut()) {
+        Ok(result) => results.push(result),
+        Err(e) => {
+          log::error!("Failed {what} calendar {name}: {e:?}");
+          error = Some(e.context(format!("calendar {name}")));
+        }
+      }
+    }
+
+    match error {
+      Some(e) if results.is_empty() => Err(e),
+      _ => Ok(results),
+    }
+  }
+
+  // the calendar containing the event
+  fn calendar_of(
+    &mut self,
+    event_id: &EventId,
+  ) -> Result<&mut (dyn Backend + 'static)> {
+    self
+      .calendars
+      .iter_mut()
+      .map(|(_, backend)| backend.as_mut())
+      .find_map(|backend| {
+        backend.get_event(event_id).is_ok().then_some(backend)
+      })
+      .ok_or_else(|| anyhow!("event {event_id} not found in any calendar"))
+  }
+}
+
+impl Backend for MultiCalendar {
+  fn calendars(&self) -> Vec<CalendarInfo> {
+    self
+      .calendars
+      .iter()
+      .flat_map(|(_, backend)| backend.calendars())
+      .collect()
+  }
+
+  fn get_event(&mut self, event_id: &EventId) -> Result<Event> {
+    self.calendar_of(event_id)?.get_event(event_id)
+  }
+
+  fn get_events(&mut self, from: DateTime, to: DateTime) -> Result<Vec<Event>> {
+    let events = self.each_calendar("loading events from", |backend| {
+      backend.get_events(from, to)
+    })?;
+    Ok(events.into_iter().flatten().collect())
+  }
+
+  fn search(&mut self, query: &SearchQuery) -> Result<Vec<Event>> {
+    let mut hits: Vec<_> = self
+      .each_calendar("searching", |backend| backend.search(query))?
+      .into_iter()
+      .flatten()
+      .collect();
+    hits.sort_by_key(|e| e.start);
+    Ok(hits)
+  }
+
+  fn delete_event(&mut self, event: &Event) -> Result<()> {
+    self.calendar_mut(&event.calendar)?.delete_event(event)
+  }
+
+  fn update_event(&mut self, updated_event: &Event) -> Result<()> {
+    self
+      .calendar_mut(&updated_event.calendar)?
+      .update_event(updated_event)
+  }
+
+  fn update_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<Option<EventId>> {
+    self
+      .calendar_mut(&occurrence.calendar)?
+      .update_occurrences(occurrence, scope)
+  }
+
+  fn delete_occurrences(
+    &mut self,
+    occurrence: &Event,
+    scope: RecurrenceScope,
+  ) -> Result<()> {
+    self
+      .calendar_mut(&occurrence.calendar)?
+      .delete_occurrences(occurrence, scope)
+  }
+
+  fn create_event(&mut self, event: &Event) -> Result<()> {
+    self.calendar_mut(&event.calendar)?.create_event(event)
+  }
+
+  fn force_refresh(&mut self) -> Result<()> {
+    self.each_calendar("refreshing", |backend| backend.force_refresh())?;
+    Ok(())
+  }
+
+  fn trashed_events(&mut self) -> Result<Vec<TrashedEvent>> {
+    let mut trashed: Vec<_> = self
+      .each_calendar("listing the trash of", |backend| {
+        backend.trashed_events()
+      })?
+      .into_iter()
+      .flatten()
+      .collect();
+    trashed.sort_by_key(|t| std::cmp::Reverse(t.deleted_at));
+    Ok(trashed)
+  }
+
+  fn restore_event(&mut self, trashed: &TrashedEvent) -> Result<()> {
+    self
+      .calendar_mut(&trashed.event.calendar)?
+      .restore_event(trashed)
+  }
+
+  fn purge_trash(&mut self, before: DateTime) -> Result<()> {
+    self.each_calendar("purging the trash of", |backend| {
+      backend.purge_trash(before)
+    })?;
+    Ok(())
+  }
+
+  fn set_waker(&mut self, waker: Waker) {
+    for (_, backend) in self.calendars.iter_mut() {
+      backend.set_waker(waker.clone());
+    }
+  }
+
+  fn sync_changes(&mut self) -> Result<bool> {
+    let changed =
+      self.each_calendar("syncing", |backend| backend.sync_changes())?;
+    Ok(changed.contains(&true))
+  }
+}
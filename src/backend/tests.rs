@@ -0,0 +1,988 @@
+use std::{path::Path, sync::Arc, time::Duration as StdDuration};
+
+use chrono::{DateTime as ChronoDateTime, Duration};
+use tempfile::TempDir;
+
+use crate::{
+  backend::{
+    Backend, Conflict, InMemory, IndexedLocalDir, LocalDir, LocalDirBuilder,
+    MultiCalendar,
+  },
+  event::Event,
+  recurrence::RecurrenceScope,
+  search::SearchQuery,
+  util::DateTime,
+};
+
+// Both backends read the same directory and must agree on the events
+// in any interval.
+
+const CALENDAR: &str = "test";
+
+struct Fixture {
+  dir: TempDir,
+  local: LocalDir,
+  indexed: IndexedLocalDir,
+}
+
+impl Fixture {
+  fn new(files: &[(&str, &str)]) -> Self {
+    let dir = TempDir::new().unwrap();
+    let calendar_dir = dir.path().join("calendar");
+    std::fs::create_dir(&calendar_dir).unwrap();
+    for (name, content) in files {
+      write_ics(&calendar_dir.join(name), content);
+    }
+
+    let local = local_dir(&calendar_dir);
+    let indexed = IndexedLocalDir::new(
+      local_dir(&calendar_dir),
+      dir.path().join("index.db"),
+    )
+    .unwrap();
+
+    Self {
+      dir,
+      local,
+      indexed,
+    }
+  }
+
+  fn calendar_dir(&self) -> std::path::PathBuf {
+    self.dir.path().join("calendar")
+  }
+
+  // the events from both backends, which must be the same
+  fn events(&mut self, from: &str, to: &str) -> Vec<Event> {
+    let (from, to) = (time(from), time(to));
+    let local = sorted(self.local.get_events(from, to).unwrap());
+    let indexed = sorted(self.indexed.get_events(from, to).unwrap());
+    assert_eq!(local, indexed, "backends disagree on {from}..{to}");
+    local
+  }
+
+  fn event_ids(&mut self, from: &str, to: &str) -> Vec<String> {
+    self.events(from, to).into_iter().map(|e| e.id).collect()
+  }
+}
+
+fn local_dir(dir: &Path) -> LocalDir {
+  LocalDirBuilder::default()
+    .dir(dir)
+    .calendar(CALENDAR)
+    .build()
+    .unwrap()
+}
+
+fn write_ics(path: &Path, vevents: &str) {
+  let content = format!(
+    "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:test\r\n{}END:VCALENDAR\r\n",
+    vevents.trim_start().replace('\n', "\r\n")
+  );
+  std::fs::write(path, content).unwrap();
+}
+
+fn vevent(uid: &str, start: &str, end: &str, extra: &str) -> String {
+  format!(
+    "BEGIN:VEVENT
+UID:{uid}
+DTSTAMP:20240101T000000Z
+DTSTART:{start}
+DTEND:{end}
+SUMMARY:{uid}
+{extra}END:VEVENT
+"
+  )
+}
+
+fn time(s: &str) -> DateTime {
+  ChronoDateTime::parse_from_rfc3339(s).unwrap()
+}
+
+// the ical components are only read from the files when an event
+// is changed, so they are left out of the comparison
+fn sorted(mut events: Vec<Event>) -> Vec<Event> {
+  for event in events.iter_mut() {
+    event.raw = None;
+  }
+  events.sort_by(|a, b| (&a.id, a.start).cmp(&(&b.id, b.start)));
+  events
+}
+
+fn day(date: &str) -> (String, String) {
+  let start = time(&format!("{date}T00:00:00Z"));
+  let end = start + Duration::days(1);
+  (start.to_rfc3339(), end.to_rfc3339())
+}
+
+#[test]
+fn event_inside_range() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+
+  let (from, to) = day("2024-03-05");
+  assert_eq!(fixture.event_ids(&from, &to), ["a"]);
+  let (from, to) = day("2024-03-06");
+  assert!(fixture.event_ids(&from, &to).is_empty());
+}
+
+#[test]
+fn event_crossing_midnight() {
+  let mut fixture = Fixture::new(&[(
+    "late.ics",
+    &vevent("late", "20240305T230000Z", "20240306T010000Z", ""),
+  )]);
+
+  let (from, to) = day("2024-03-05");
+  assert_eq!(fixture.event_ids(&from, &to), ["late"]);
+  let (from, to) = day("2024-03-06");
+  assert_eq!(fixture.event_ids(&from, &to), ["late"]);
+  let (from, to) = day("2024-03-07");
+  assert!(fixture.event_ids(&from, &to).is_empty());
+}
+
+#[test]
+fn event_spanning_whole_range() {
+  let mut fixture = Fixture::new(&[(
+    "conference.ics",
+    &vevent("conference", "20240301T090000Z", "20240310T170000Z", ""),
+  )]);
+
+  let (from, to) = day("2024-03-05");
+  assert_eq!(fixture.event_ids(&from, &to), ["conference"]);
+  assert!(fixture
+    .event_ids("2024-02-01T00:00:00Z", "2024-02-28T00:00:00Z")
+    .is_empty());
+}
+
+#[test]
+fn all_day_events() {
+  let mut fixture = Fixture::new(&[(
+    "holiday.ics",
+    "BEGIN:VEVENT
+UID:holiday
+DTSTAMP:20240101T000000Z
+DTSTART;VALUE=DATE:20240304
+DTEND;VALUE=DATE:20240307
+SUMMARY:holiday
+END:VEVENT
+",
+  )]);
+
+  for date in ["2024-03-04", "2024-03-05", "2024-03-06"] {
+    let (from, to) = day(date);
+    assert_eq!(fixture.event_ids(&from, &to), ["holiday"], "{date}");
+  }
+  let (from, to) = day("2024-03-08");
+  assert!(fixture.event_ids(&from, &to).is_empty());
+}
+
+#[test]
+fn recurring_events() {
+  let mut fixture = Fixture::new(&[
+    (
+      "daily.ics",
+      &vevent(
+        "daily",
+        "20240301T230000Z",
+        "20240302T003000Z",
+        "RRULE:FREQ=DAILY;COUNT=5\n",
+      ),
+    ),
+    (
+      "weekly.ics",
+      &vevent(
+        "weekly",
+        "20240101T090000Z",
+        "20240101T100000Z",
+        "RRULE:FREQ=WEEKLY\n",
+      ),
+    ),
+  ]);
+
+  // monday, the daily event ended on the 6th
+  let (from, to) = day("2024-03-04");
+  assert_eq!(
+    fixture.event_ids(&from, &to),
+    [
+      "daily#20240303T230000Z",
+      "daily#20240304T230000Z",
+      "weekly#20240304T090000Z"
+    ]
+  );
+  let (from, to) = day("2024-03-11");
+  assert_eq!(fixture.event_ids(&from, &to), ["weekly#20240311T090000Z"]);
+
+  // far in the future
+  let (from, to) = day("2030-01-07");
+  assert_eq!(fixture.event_ids(&from, &to), ["weekly#20300107T090000Z"]);
+}
+
+#[test]
+fn overridden_occurrence_moved_into_range() {
+  let series = vevent(
+    "series",
+    "20240301T090000Z",
+    "20240301T100000Z",
+    "RRULE:FREQ=DAILY;COUNT=3\n",
+  );
+  let moved = "BEGIN:VEVENT
+UID:series
+DTSTAMP:20240101T000000Z
+RECURRENCE-ID:20240302T090000Z
+DTSTART:20240310T090000Z
+DTEND:20240310T100000Z
+SUMMARY:moved
+END:VEVENT
+";
+  let mut fixture =
+    Fixture::new(&[("series.ics", &format!("{series}{moved}"))]);
+
+  let (from, to) = day("2024-03-10");
+  let events = fixture.events(&from, &to);
+  assert_eq!(events.len(), 1);
+  assert_eq!(events[0].title, "moved");
+  let (from, to) = day("2024-03-02");
+  assert!(fixture.event_ids(&from, &to).is_empty());
+}
+
+#[test]
+fn several_events_in_one_file() {
+  let mut fixture = Fixture::new(&[(
+    "bundle.ics",
+    &[
+      vevent("first", "20240305T080000Z", "20240305T090000Z", ""),
+      vevent("second", "20240306T230000Z", "20240307T010000Z", ""),
+      vevent("third", "20240401T080000Z", "20240401T090000Z", ""),
+    ]
+    .concat(),
+  )]);
+
+  let (from, to) = day("2024-03-07");
+  assert_eq!(fixture.event_ids(&from, &to), ["second"]);
+  assert_eq!(
+    fixture.event_ids("2024-03-01T00:00:00Z", "2024-03-31T00:00:00Z"),
+    ["first", "second"]
+  );
+}
+
+#[test]
+fn events_in_other_timezones() {
+  let mut fixture = Fixture::new(&[(
+    "tokyo.ics",
+    "BEGIN:VEVENT
+UID:tokyo
+DTSTAMP:20240101T000000Z
+DTSTART;TZID=Asia/Tokyo:20240306T080000
+DTEND;TZID=Asia/Tokyo:20240306T100000
+SUMMARY:tokyo
+END:VEVENT
+",
+  )]);
+
+  // 23:00--01:00 UTC
+  let (from, to) = day("2024-03-05");
+  assert_eq!(fixture.event_ids(&from, &to), ["tokyo"]);
+  let (from, to) = day("2024-03-06");
+  assert_eq!(fixture.event_ids(&from, &to), ["tokyo"]);
+}
+
+#[test]
+fn durations_in_any_order() {
+  let mut fixture = Fixture::new(&[(
+    "berlin.ics",
+    "BEGIN:VEVENT
+UID:berlin
+DTSTAMP:20240101T000000Z
+DURATION:P1DT1H
+DTSTART;TZID=Europe/Berlin:20240330T100000
+SUMMARY:berlin
+END:VEVENT
+",
+  )]);
+
+  // a day on the wall clock, across the DST transition
+  let (from, to) = day("2024-03-30");
+  let events = fixture.events(&from, &to);
+  assert_eq!(events[0].start, time("2024-03-30T10:00:00+01:00"));
+  assert_eq!(events[0].end, time("2024-03-31T11:00:00+02:00"));
+}
+
+#[test]
+fn changes_made_through_the_index() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+
+  let (from, to) = day("2024-03-05");
+  let mut event = fixture.events(&from, &to).remove(0);
+  event.start = time("2024-03-05T23:00:00Z");
+  event.end = time("2024-03-06T02:00:00Z");
+  fixture.indexed.update_event(&event).unwrap();
+
+  let (from, to) = day("2024-03-06");
+  assert_eq!(fixture.event_ids(&from, &to), ["a"]);
+
+  fixture.indexed.delete_event(&event).unwrap();
+  let (from, to) = day("2024-03-05");
+  assert!(fixture.event_ids(&from, &to).is_empty());
+}
+
+#[test]
+fn files_changed_outside() {
+  let mut fixture = Fixture::new(&[]);
+  let (from, to) = day("2024-03-05");
+  assert!(fixture.event_ids(&from, &to).is_empty());
+
+  write_ics(
+    &fixture.calendar_dir().join("new.ics"),
+    &vevent("new", "20240304T200000Z", "20240305T020000Z", ""),
+  );
+  fixture.indexed.force_refresh().unwrap();
+  assert_eq!(fixture.event_ids(&from, &to), ["new"]);
+
+  std::fs::remove_file(fixture.calendar_dir().join("new.ics")).unwrap();
+  fixture.indexed.force_refresh().unwrap();
+  assert!(fixture.event_ids(&from, &to).is_empty());
+}
+
+#[test]
+fn files_changed_outside_are_watched() {
+  let mut fixture = Fixture::new(&[]);
+  let (wake, woken) = std::sync::mpsc::channel();
+  let wake = std::sync::Mutex::new(wake);
+  fixture
+    .indexed
+    .set_waker(Arc::new(move || wake.lock().unwrap().send(()).unwrap()));
+
+  // waits for the watcher to pick up the change
+  let sync = |fixture: &mut Fixture| loop {
+    woken.recv_timeout(StdDuration::from_secs(5)).unwrap();
+    if fixture.indexed.sync_changes().unwrap() {
+      break;
+    }
+  };
+
+  write_ics(
+    &fixture.calendar_dir().join("new.ics"),
+    &vevent("new", "20240305T100000Z", "20240305T110000Z", ""),
+  );
+  sync(&mut fixture);
+  let (from, to) = day("2024-03-05");
+  assert_eq!(fixture.event_ids(&from, &to), ["new"]);
+
+  std::fs::remove_file(fixture.calendar_dir().join("new.ics")).unwrap();
+  sync(&mut fixture);
+  assert!(fixture.event_ids(&from, &to).is_empty());
+
+  // changes made through the index are already indexed
+  write_ics(
+    &fixture.calendar_dir().join("own.ics"),
+    &vevent("own", "20240305T100000Z", "20240305T110000Z", ""),
+  );
+  sync(&mut fixture);
+  let mut event = fixture.events(&from, &to).remove(0);
+  event.title = "renamed".to_string();
+  fixture.indexed.update_event(&event).unwrap();
+  woken.recv_timeout(StdDuration::from_secs(5)).unwrap();
+  assert!(!fixture.indexed.sync_changes().unwrap());
+}
+
+#[test]
+fn cached_fields() {
+  let mut fixture = Fixture::new(&[
+    (
+      "details.ics",
+      &vevent(
+        "details",
+        "20240305T100000Z",
+        "20240305T110000Z",
+        "DESCRIPTION:line one\\nline two\nCOLOR:tomato\nCREATED:20240102T030405Z\n",
+      ),
+    ),
+    (
+      "floating.ics",
+      "BEGIN:VEVENT
+UID:floating
+DTSTAMP:20240101T000000Z
+DTSTART:20240305T120000
+DTEND:20240305T130000
+SUMMARY:floating
+END:VEVENT
+",
+    ),
+    (
+      "exceptions.ics",
+      &vevent(
+        "exceptions",
+        "20240301T080000Z",
+        "20240301T090000Z",
+        "RRULE:FREQ=DAILY;UNTIL=20240310T080000Z\n\
+         EXDATE:20240304T080000Z\nRDATE:20240305T200000Z\n",
+      ),
+    ),
+  ]);
+
+  let events = fixture.events("2024-03-03T00:00:00Z", "2024-03-06T00:00:00Z");
+  let ids: Vec<_> = events.iter().map(|e| e.id.as_str()).collect();
+  assert_eq!(
+    ids,
+    [
+      "details",
+      "exceptions#20240303T080000Z",
+      "exceptions#20240305T080000Z",
+      "exceptions#20240305T200000Z",
+      "floating"
+    ]
+  );
+  assert_eq!(events[0].description.as_deref(), Some("line one\nline two"));
+  assert!(events[0].color.is_some());
+}
+
+#[test]
+fn custom_timezones() {
+  let mut fixture = Fixture::new(&[(
+    "custom.ics",
+    "BEGIN:VTIMEZONE
+TZID:Custom Standard Time
+BEGIN:STANDARD
+DTSTART:16010101T030000
+TZOFFSETFROM:+0200
+TZOFFSETTO:+0100
+RRULE:FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU
+END:STANDARD
+BEGIN:DAYLIGHT
+DTSTART:16010101T020000
+TZOFFSETFROM:+0100
+TZOFFSETTO:+0200
+RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU
+END:DAYLIGHT
+END:VTIMEZONE
+BEGIN:VEVENT
+UID:custom
+DTSTAMP:20240101T000000Z
+DTSTART;TZID=Custom Standard Time:20240325T093000
+DTEND;TZID=Custom Standard Time:20240325T103000
+RRULE:FREQ=WEEKLY;COUNT=4
+SUMMARY:custom
+END:VEVENT
+",
+  )]);
+
+  let events = fixture.events("2024-03-18T00:00:00Z", "2024-04-08T00:00:00Z");
+  // summer time starts on the 31st
+  let starts: Vec<_> = events
+    .iter()
+    .map(|e| e.start.naive_utc().to_string())
+    .collect();
+  assert_eq!(starts, ["2024-03-25 08:30:00", "2024-04-01 07:30:00"]);
+}
+
+#[test]
+fn unknown_properties_kept_when_changed_through_the_index() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent(
+      "a",
+      "20240305T100000Z",
+      "20240305T110000Z",
+      "LOCATION:Room 1\n",
+    ),
+  )]);
+
+  let (from, to) = day("2024-03-05");
+  let mut event = fixture.indexed.get_events(time(&from), time(&to)).unwrap();
+  let mut event = event.remove(0);
+  assert!(event.raw.is_none());
+  event.title = "renamed".to_string();
+  fixture.indexed.update_event(&event).unwrap();
+
+  let content =
+    std::fs::read_to_string(fixture.calendar_dir().join("a.ics")).unwrap();
+  assert!(content.contains("LOCATION:Room 1"));
+  assert!(content.contains("SUMMARY:renamed"));
+  assert_eq!(fixture.events(&from, &to)[0].title, "renamed");
+}
+
+// Give the file the modification time, seconds from now, and index it
+// again with it. Returns the time.
+fn reindex_with_mtime(
+  fixture: &mut Fixture,
+  path: &Path,
+  seconds: i64,
+) -> filetime::FileTime {
+  let now = filetime::FileTime::now();
+  let mtime =
+    filetime::FileTime::from_unix_time(now.unix_seconds() + seconds, 0);
+  filetime::set_file_mtime(path, mtime).unwrap();
+  fixture.indexed.force_refresh().unwrap();
+  mtime
+}
+
+#[test]
+fn unchanged_files_are_not_read_again() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+  let path = fixture.calendar_dir().join("a.ics");
+  // last modified well before it was indexed
+  let mtime = reindex_with_mtime(&mut fixture, &path, -3600);
+
+  // same size and modification time, but no longer a valid ics file
+  let size = std::fs::metadata(&path).unwrap().len() as usize;
+  std::fs::write(&path, "x".repeat(size)).unwrap();
+  filetime::set_file_mtime(&path, mtime).unwrap();
+
+  fixture.indexed.force_refresh().unwrap();
+  let (from, to) = day("2024-03-05");
+  let events = fixture.indexed.get_events(time(&from), time(&to)).unwrap();
+  assert_eq!(events.len(), 1);
+  assert_eq!(events[0].title, "a");
+}
+
+#[test]
+fn files_changed_within_a_second_are_read_again() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+  let path = fixture.calendar_dir().join("a.ics");
+  // not before it was indexed, it may have changed since within the
+  // same second
+  let mtime = reindex_with_mtime(&mut fixture, &path, 3600);
+
+  // same size and modification time, but another title
+  let content = std::fs::read_to_string(&path).unwrap();
+  std::fs::write(&path, content.replace("SUMMARY:a", "SUMMARY:b")).unwrap();
+  filetime::set_file_mtime(&path, mtime).unwrap();
+
+  fixture.indexed.force_refresh().unwrap();
+  let (from, to) = day("2024-03-05");
+  assert_eq!(fixture.events(&from, &to)[0].title, "b");
+}
+
+#[test]
+fn bad_index_entries_are_skipped() {
+  let mut fixture = Fixture::new(&[
+    (
+      "a.ics",
+      &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+    ),
+    (
+      "b.ics",
+      &vevent("b", "20240305T120000Z", "20240305T130000Z", ""),
+    ),
+  ]);
+
+  let index =
+    rusqlite::Connection::open(fixture.dir.path().join("index.db")).unwrap();
+  index
+    .execute(
+      "UPDATE events SET recurring = 1, rrule = 'bad' WHERE event_id = 'b'",
+      [],
+    )
+    .unwrap();
+
+  let (from, to) = day("2024-03-05");
+  let events = fixture.indexed.get_events(time(&from), time(&to)).unwrap();
+  let ids: Vec<_> = events.iter().map(|e| e.id.as_str()).collect();
+  assert_eq!(ids, ["a"]);
+}
+
+#[test]
+fn changes_to_files_changed_outside_are_conflicts() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+  let (from, to) = day("2024-03-05");
+  let event = fixture.events(&from, &to).remove(0);
+
+  // changed through the backend itself first, which is no conflict
+  let mut renamed = event.clone();
+  renamed.title = "renamed".to_string();
+  fixture.indexed.update_event(&renamed).unwrap();
+  renamed.title = "renamed again".to_string();
+  fixture.indexed.update_event(&renamed).unwrap();
+
+  let path = fixture.calendar_dir().join("a.ics");
+  write_ics(
+    &path,
+    &vevent("a", "20240305T120000Z", "20240305T130000Z", ""),
+  );
+
+  for backend in [&mut fixture.local as &mut dyn Backend, &mut fixture.indexed]
+  {
+    let error = backend.update_event(&renamed).unwrap_err();
+    let conflict = error.downcast_ref::<Conflict>().unwrap();
+    assert_eq!(conflict.path, path);
+  }
+  assert!(!std::fs::read_to_string(&path).unwrap().contains("renamed"));
+
+  // events read again are up to date
+  fixture.indexed.force_refresh().unwrap();
+  let mut event = fixture.events(&from, &to).remove(0);
+  event.title = "renamed".to_string();
+  fixture.indexed.update_event(&event).unwrap();
+  assert_eq!(fixture.events(&from, &to)[0].title, "renamed");
+}
+
+#[test]
+fn deleting_events_changed_outside_is_a_conflict() {
+  let first = vevent("first", "20240305T080000Z", "20240305T090000Z", "");
+  let bundle = [
+    first.clone(),
+    vevent("second", "20240305T100000Z", "20240305T110000Z", ""),
+  ]
+  .concat();
+  let daily = vevent(
+    "daily",
+    "20240304T120000Z",
+    "20240304T130000Z",
+    "RRULE:FREQ=DAILY;COUNT=5\n",
+  );
+  let mut fixture =
+    Fixture::new(&[("bundle.ics", &bundle), ("daily.ics", &daily)]);
+  let (from, to) = day("2024-03-05");
+  let events = fixture.events(&from, &to);
+
+  let bundle_path = fixture.calendar_dir().join("bundle.ics");
+  let daily_path = fixture.calendar_dir().join("daily.ics");
+  let moved = vevent(
+    "second",
+    "20240305T103000Z",
+    "20240305T110000Z",
+    "LOCATION:moved\n",
+  );
+  write_ics(&bundle_path, &[first, moved].concat());
+  write_ics(&daily_path, &daily.replace("COUNT=5", "COUNT=10"));
+
+  for backend in [&mut fixture.local as &mut dyn Backend, &mut fixture.indexed]
+  {
+    let second = events.iter().find(|e| e.id == "second").unwrap();
+    let error = backend.delete_event(second).unwrap_err();
+    assert_eq!(error.downcast_ref::<Conflict>().unwrap().path, bundle_path);
+
+    let occurrence = events.iter().find(|e| e.uid() == "daily").unwrap();
+    for scope in [RecurrenceScope::ThisOnly, RecurrenceScope::All] {
+      let error = backend.delete_occurrences(occurrence, scope).unwrap_err();
+      assert_eq!(error.downcast_ref::<Conflict>().unwrap().path, daily_path);
+    }
+  }
+  assert_eq!(fixture.local.trashed_events().unwrap(), []);
+
+  // events read again are up to date
+  fixture.indexed.force_refresh().unwrap();
+  for event in fixture.events(&from, &to) {
+    if event.id != "first" {
+      fixture.indexed.delete_event(&event).unwrap();
+    }
+  }
+  assert_eq!(fixture.event_ids(&from, &to), ["first"]);
+}
+
+#[test]
+fn missing_calendar_dir_is_an_error() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+  std::fs::remove_dir_all(fixture.calendar_dir()).unwrap();
+
+  let (from, to) = day("2024-03-05");
+  let (from, to) = (time(&from), time(&to));
+  assert!(fixture.local.get_events(from, to).is_err());
+  assert!(fixture.indexed.force_refresh().is_err());
+}
+
+#[test]
+fn failing_calendars_leave_the_others_shown() {
+  let fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+  let mut backend = MultiCalendar::new();
+  backend.add_calendar(CALENDAR, local_dir(&fixture.calendar_dir()));
+  backend.add_calendar("missing", local_dir(&fixture.dir.path().join("none")));
+
+  let (from, to) = day("2024-03-05");
+  let (from, to) = (time(&from), time(&to));
+  let events = backend.get_events(from, to).unwrap();
+  assert_eq!(events.len(), 1);
+  let hits = backend.search(&SearchQuery::new("a")).unwrap();
+  assert_eq!(hits.len(), 1);
+
+  // only when none are left is it an error
+  std::fs::remove_dir_all(fixture.calendar_dir()).unwrap();
+  assert!(backend.get_events(from, to).is_err());
+}
+
+#[test]
+fn writes_leave_no_temporary_files() {
+  let mut fixture = Fixture::new(&[(
+    "a.ics",
+    &vevent("a", "20240305T100000Z", "20240305T110000Z", ""),
+  )]);
+  let (from, to) = day("2024-03-05");
+  let mut event = fixture.events(&from, &to).remove(0);
+  event.title = "renamed".to_string();
+  fixture.indexed.update_event(&event).unwrap();
+
+  let files: Vec<_> = std::fs::read_dir(fixture.calendar_dir())
+    .unwrap()
+    .map(|entry| entry.unwrap().file_name())
+    .collect();
+  assert_eq!(files, ["a.ics"]);
+}
+
+// the ids of the events found by both backends, which must agree,
+// ordered by id
+fn search(fixture: &mut Fixture, query: &SearchQuery) -> Vec<String> {
+  let local = sorted(fixture.local.search(query).unwrap());
+  let indexed = sorted(fixture.indexed.search(query).unwrap());
+  assert_eq!(local, indexed, "backends disagree on {query:?}");
+  local.into_iter().map(|e| e.id).collect()
+}
+
+#[test]
+fn search_events() {
+  let mut fixture = Fixture::new(&[
+    (
+      "standup.ics",
+      &[
+        vevent(
+          "standup",
+          "20240304T090000Z",
+          "20240304T091500Z",
+          "RRULE:FREQ=DAILY\nDESCRIPTION:Daily team meeting\n",
+        ),
+        "BEGIN:VEVENT
+UID:standup
+DTSTAMP:20240101T000000Z
+RECURRENCE-ID:20240307T090000Z
+DTSTART:20240307T100000Z
+DTEND:20240307T110000Z
+SUMMARY:Retro
+LOCATION:Cafe
+END:VEVENT
+"
+        .to_string(),
+      ]
+      .concat(),
+    ),
+    (
+      "review.ics",
+      &vevent(
+        "review",
+        "20240305T140000Z",
+        "20240305T150000Z",
+        "LOCATION:Meeting room 2\n",
+      ),
+    ),
+    (
+      "lunch.ics",
+      &vevent("lunch", "20240306T120000Z", "20240306T130000Z", ""),
+    ),
+  ]);
+
+  let query = |text: &str| SearchQuery::new(text);
+  assert_eq!(search(&mut fixture, &query("lunch")), ["lunch"]);
+  assert!(search(&mut fixture, &query("lun")).is_empty());
+  assert_eq!(search(&mut fixture, &query("LUN*")), ["lunch"]);
+  assert_eq!(
+    search(&mut fixture, &query("meeting")),
+    ["review", "standup#20240304T090000Z"]
+  );
+  assert_eq!(
+    search(&mut fixture, &query("\"team meeting\"")),
+    ["standup#20240304T090000Z"]
+  );
+  assert!(search(&mut fixture, &query("\"meeting team\"")).is_empty());
+  assert_eq!(search(&mut fixture, &query("meet* room")), ["review"]);
+  assert!(search(&mut fixture, &query("\"\" * AND")).is_empty());
+
+  // the first occurrence within the bounds
+  let mut bounded = query("meeting");
+  bounded.from = Some(time("2024-03-06T00:00:00Z"));
+  bounded.to = Some(time("2024-03-10T00:00:00Z"));
+  assert_eq!(search(&mut fixture, &bounded), ["standup#20240306T090000Z"]);
+
+  // overridden occurrences are found by their own text
+  let hits = fixture.indexed.search(&query("retro cafe")).unwrap();
+  assert_eq!(hits.len(), 1);
+  assert_eq!(hits[0].id, "standup#20240307T090000Z");
+  assert_eq!(hits[0].start, time("2024-03-07T10:00:00Z"));
+  assert_eq!(search(&mut fixture, &query("retro")), [hits[0].id.clone()]);
+  bounded.text = "retro".to_string();
+  bounded.from = Some(time("2024-03-08T00:00:00Z"));
+  assert!(search(&mut fixture, &bounded).is_empty());
+}
+
+// an in-memory copy of the fixture's calendar
+fn in_memory(fixture: &Fixture) -> InMemory {
+  let mut backend = InMemory::new(CALENDAR);
+  for event in fixture.local.all_events().unwrap() {
+    backend.create_event(&event).unwrap();
+  }
+  backend
+}
+
+// the events from the in-memory backend and the local dir, which must
+// be the same apart from what's read from the files
+fn in_memory_events(
+  fixture: &mut Fixture,
+  backend: &mut InMemory,
+  (from, to): (String, String),
+) -> Vec<String> {
+  let strip = |events: Vec<Event>| {
+    let mut events = sorted(events);
+    for event in events.iter_mut() {
+      event.fingerprint = None;
+    }
+    events
+  };
+
+  let (from, to) = (time(&from), time(&to));
+  let local = strip(fixture.local.get_events(from, to).unwrap());
+  let in_memory = strip(backend.get_events(from, to).unwrap());
+  assert_eq!(local, in_memory, "backends disagree on {from}..{to}");
+  local.into_iter().map(|e| e.id).collect()
+}
+
+#[test]
+fn in_memory_backend() {
+  let mut fixture = Fixture::new(&[
+    (
+      "late.ics",
+      &vevent("late", "20240305T230000Z", "20240306T010000Z", ""),
+    ),
+    (
+      "daily.ics",
+      &vevent(
+        "daily",
+        "20240304T090000Z",
+        "20240304T100000Z",
+        "RRULE:FREQ=DAILY;COUNT=5\n",
+      ),
+    ),
+  ]);
+  let mut backend = in_memory(&fixture);
+
+  assert_eq!(
+    in_memory_events(&mut fixture, &mut backend, day("2024-03-05")),
+    ["daily#20240305T090000Z", "late"]
+  );
+  assert_eq!(
+    in_memory_events(&mut fixture, &mut backend, day("2024-03-06")),
+    ["daily#20240306T090000Z", "late"]
+  );
+
+  // the same changes to both
+  let (from, to) = day("2024-03-06");
+  let mut moved = backend
+    .get_event(&"daily#20240306T090000Z".to_string())
+    .unwrap();
+  moved.start = time(&from) + Duration::hours(20);
+  moved.end = time(&from) + Duration::hours(21);
+  let mut renamed = backend
+    .get_event(&"daily#20240307T090000Z".to_string())
+    .unwrap();
+  renamed.title = "renamed".to_string();
+  let removed = backend
+    .get_event(&"daily#20240308T090000Z".to_string())
+    .unwrap();
+  let late = backend.get_event(&"late".to_string()).unwrap();
+
+  for backend in [&mut fixture.local as &mut dyn Backend, &mut backend] {
+    backend.update_event(&moved).unwrap();
+    backend
+      .update_occurrences(&renamed, RecurrenceScope::All)
+      .unwrap();
+    backend
+      .delete_occurrences(&removed, RecurrenceScope::ThisOnly)
+      .unwrap();
+    backend.delete_event(&late).unwrap();
+  }
+
+  assert_eq!(
+    in_memory_events(&mut fixture, &mut backend, (from, to)),
+    ["daily#20240306T090000Z"]
+  );
+  assert_eq!(
+    in_memory_events(
+      &mut fixture,
+      &mut backend,
+      ("2024-03-01T00:00:00Z".into(), "2024-03-31T00:00:00Z".into())
+    ),
+    [
+      "daily#20240304T090000Z",
+      "daily#20240305T090000Z",
+      "daily#20240306T090000Z",
+      "daily#20240307T090000Z"
+    ]
+  );
+  let query = SearchQuery::new("renamed");
+  assert_eq!(
+    sorted(backend.search(&query).unwrap())[0].id,
+    "daily#20240304T090000Z"
+  );
+}
+
+#[test]
+fn deleted_events_go_to_the_trash() {
+  let mut fixture = Fixture::new(&[
+    (
+      "bundle.ics",
+      &[
+        vevent("first", "20240305T080000Z", "20240305T090000Z", ""),
+        vevent("second", "20240305T100000Z", "20240305T110000Z", ""),
+      ]
+      .concat(),
+    ),
+    (
+      "lunch.ics",
+      &vevent("lunch", "20240305T120000Z", "20240305T130000Z", ""),
+    ),
+  ]);
+  let (from, to) = day("2024-03-05");
+
+  for event in fixture.events(&from, &to) {
+    if event.id != "first" {
+      fixture.indexed.delete_event(&event).unwrap();
+    }
+  }
+  assert_eq!(fixture.event_ids(&from, &to), ["first"]);
+
+  // out of sight of other programs reading the calendar
+  let files: Vec<_> = std::fs::read_dir(fixture.calendar_dir())
+    .unwrap()
+    .map(|entry| entry.unwrap().file_name())
+    .filter(|name| name.to_string_lossy().ends_with(".ics"))
+    .collect();
+  assert_eq!(files, ["bundle.ics"]);
+
+  let trashed = fixture.indexed.trashed_events().unwrap();
+  let ids: Vec<_> = trashed.iter().map(|t| t.event.id.as_str()).collect();
+  assert_eq!(ids.len(), 2);
+  assert!(ids.contains(&"second") && ids.contains(&"lunch"));
+
+  let lunch = trashed.iter().find(|t| t.event.id == "lunch").unwrap();
+  fixture.indexed.restore_event(lunch).unwrap();
+  assert_eq!(fixture.event_ids(&from, &to), ["first", "lunch"]);
+  assert!(fixture.indexed.restore_event(lunch).is_err());
+
+  // an event with the UID was created since
+  let second = trashed.iter().find(|t| t.event.id == "second").unwrap();
+  let mut recreated = second.event.clone();
+  recreated.raw = None;
+  fixture.indexed.create_event(&recreated).unwrap();
+  assert!(fixture.indexed.restore_event(second).is_err());
+
+  let later = second.deleted_at + Duration::seconds(1);
+  fixture.indexed.purge_trash(later).unwrap();
+  assert!(fixture.indexed.trashed_events().unwrap().is_empty());
+}
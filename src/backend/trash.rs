@@ -0,0 +1,105 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+  backend::TrashedEvent,
+  ical::ICal,
+  util::{anyhow, utc_now, DateTime, Result},
+};
+
+// vdirsyncer and khal only look at the ics files right in the calendar
+// directory, so those in a subdirectory are out of their sight
+const TRASH_DIR: &str = ".trash";
+
+// The events deleted from a calendar directory. Each is kept in a file
+// named after the time of deletion and the file it was in, e.g.
+// 1709640000000-lunch.ics.
+pub(crate) struct Trash {
+  dir: PathBuf,
+}
+
+impl Trash {
+  pub(crate) fn of(calendar_dir: &Path) -> Self {
+    Self {
+      dir: calendar_dir.join(TRASH_DIR),
+    }
+  }
+
+  // the file for an event deleted now
+  pub(crate) fn new_path(&self, stem: &str) -> Result<PathBuf> {
+    fs::create_dir_all(&self.dir)?;
+    let millis = utc_now().timestamp_millis();
+    Ok(self.dir.join(format!("{millis}-{stem}.ics")))
+  }
+
+  pub(crate) fn path(&self, trash_id: &str) -> Result<PathBuf> {
+    let path = self.dir.join(trash_id);
+    // trash ids are file names, never paths
+    let in_trash = path.parent() == Some(self.dir.as_path()) && path.is_file();
+    in_trash
+      .then_some(path)
+      .ok_or_else(|| anyhow!("{trash_id} is not in the trash"))
+  }
+
+  // the deleted events, the latest first
+  pub(crate) fn entries(&self, calendar: &str) -> Result<Vec<TrashedEvent>> {
+    if !self.dir.exists() {
+      return Ok(vec![]);
+    }
+
+    let mut trashed = vec![];
+    for entry in fs::read_dir(&self.dir)? {
+      let path = entry?.path();
+      let Some((trash_id, deleted_at)) = parse_file_name(&path) else {
+        continue;
+      };
+
+      let events = fs::read_to_string(&path)
+        .map_err(Into::into)
+        .and_then(|content| ICal.parse(calendar, &content));
+      match events.map(|events| events.into_iter().next()) {
+        Ok(Some(event)) => trashed.push(TrashedEvent {
+          trash_id,
+          event,
+          deleted_at,
+        }),
+        Ok(None) => (),
+        Err(e) => log::warn!("Ignoring unreadable {:?}: {e:?}", path),
+      }
+    }
+
+    trashed.sort_by_key(|t| std::cmp::Reverse(t.deleted_at));
+    Ok(trashed)
+  }
+
+  // remove the events deleted before the time for good
+  pub(crate) fn purge(&self, before: DateTime) -> Result<()> {
+    if !self.dir.exists() {
+      return Ok(());
+    }
+
+    for entry in fs::read_dir(&self.dir)? {
+      let path = entry?.path();
+      let expired = parse_file_name(&path)
+        .is_some_and(|(_, deleted_at)| deleted_at < before);
+      if expired {
+        log::debug!("Purging {:?} from the trash", path);
+        fs::remove_file(&path)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+// the trash id and the time of deletion
+fn parse_file_name(path: &Path) -> Option<(String, DateTime)> {
+  let name = path.file_name()?.to_str()?;
+  let (millis, _) = name.strip_suffix(".ics")?.split_once('-')?;
+  let deleted_at = Utc.timestamp_millis_opt(millis.parse().ok()?).single()?;
+  Some((name.to_string(), deleted_at.fixed_offset()))
+}
@@ -0,0 +1,74 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::{
+    mpsc::{channel, Receiver},
+    Arc, Mutex,
+  },
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{backend::Waker, util::Result};
+
+pub(crate) enum Change {
+  // the files created, modified or removed
+  Paths(Vec<PathBuf>),
+  // events were lost, the whole directory has to be scanned
+  Rescan,
+}
+
+// Watches a calendar directory for changes made by other programs
+// (e.g. vdirsyncer or khal). The changes are queued until taken, the
+// waker is called whenever there are new ones.
+pub(crate) struct DirWatcher {
+  // stops watching when dropped
+  _watcher: RecommendedWatcher,
+  changes: Receiver<Change>,
+  waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl DirWatcher {
+  pub(crate) fn new(dir: &Path) -> Result<Self> {
+    let (sender, changes) = channel();
+    let waker: Arc<Mutex<Option<Waker>>> = Arc::default();
+
+    let handler_waker = waker.clone();
+    let handler = move |event: notify::Result<notify::Event>| {
+      let change = match event {
+        Ok(event) if event.need_rescan() => Change::Rescan,
+        Ok(event) => Change::Paths(event.paths),
+        Err(e) => {
+          log::warn!("Failed watching calendar directory: {e:?}");
+          Change::Rescan
+        }
+      };
+
+      // the receiver is gone when the backend is dropped
+      if sender.send(change).is_err() {
+        return;
+      }
+
+      if let Some(wake) = handler_waker.lock().unwrap().as_ref() {
+        wake();
+      }
+    };
+
+    let mut watcher = notify::recommended_watcher(handler)?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    Ok(Self {
+      _watcher: watcher,
+      changes,
+      waker,
+    })
+  }
+
+  pub(crate) fn set_waker(&self, waker: Waker) {
+    *self.waker.lock().unwrap() = Some(waker);
+  }
+
+  // the changes queued since the last call
+  pub(crate) fn take_changes(&self) -> Vec<Change> {
+    self.changes.try_iter().collect()
+  }
+}
@@ -0,0 +1,59 @@
+use chrono::FixedOffset;
+
+use crate::{
+  backend::{self, Backend, MultiCalendar},
+  config::Config,
+  util::{anyhow, DateTime, Result},
+};
+
+const TRASH_USAGE: &str = "usage: malakal trash [restore <id>...]";
+
+// `malakal trash` lists the deleted events, `malakal trash restore`
+// puts the given ones back into their calendars
+pub fn trash(
+  config: &Config,
+  timezone: FixedOffset,
+  args: &[String],
+) -> Result<()> {
+  // the indexes pick up the restored files when the app starts
+  let mut calendars = MultiCalendar::new();
+  for calendar in config.calendars() {
+    let local_dir = backend::LocalDirBuilder::default()
+      .calendar(&calendar.name)
+      .dir(&calendar.location)
+      .build()?;
+    calendars.add_calendar(&calendar.name, local_dir);
+  }
+
+  let trashed = calendars.trashed_events()?;
+  let time =
+    |t: &DateTime| t.with_timezone(&timezone).format("%F %H:%M").to_string();
+
+  match args {
+    [] => {
+      for t in trashed.iter() {
+        println!(
+          "{}\t{}\tdeleted {}\t{}\t{}",
+          t.trash_id,
+          t.event.calendar,
+          time(&t.deleted_at),
+          time(&t.event.start),
+          t.event.title
+        );
+      }
+    }
+    [command, ids @ ..] if command == "restore" && !ids.is_empty() => {
+      for id in ids {
+        let t = trashed
+          .iter()
+          .find(|t| &t.trash_id == id)
+          .ok_or_else(|| anyhow!("{id} is not in the trash"))?;
+        calendars.restore_event(t)?;
+        println!("Restored {} ({})", t.event.title, time(&t.event.start));
+      }
+    }
+    _ => return Err(anyhow!(TRASH_USAGE)),
+  }
+
+  Ok(())
+}
@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use anyhow::{anyhow, Context};
-use chrono::Duration;
+use chrono::{Duration, Weekday};
 use serde::{Deserialize, Serialize};
 use serde_with::{formats::Flexible, serde_as};
 use toml::ser::to_string_pretty;
@@ -10,8 +10,10 @@ use toml::ser::to_string_pretty;
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(default)]
 pub struct Config {
+  // the calendar used when no calendars are listed
   pub calendar_name: String,
   pub calendar_location: String,
+  pub calendars: Vec<CalendarConfig>,
   pub timezone: Option<String>,
   pub notifier_switch: bool,
   pub notifier_blacklist_processes: Vec<String>,
@@ -21,6 +23,26 @@ pub struct Config {
   #[serde_as(as = "serde_with::DurationMilliSeconds<i64, Flexible>")]
   pub post_update_hook_delay: Duration,
   pub day_column_width: Option<f32>,
+  // the days shown at start around the current day, as many as fit
+  // in the window by default
+  pub day_count: Option<usize>,
+  // start with the week from the first weekday or the work week
+  // from Monday instead, "week" or "work_week"
+  pub day_view: Option<String>,
+  // the day the week starts on, e.g. "monday" or "sun"
+  pub first_weekday: String,
+  // deleted events are kept in the trash for this many days
+  pub trash_retention_days: u32,
+}
+
+// a vdir collection
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CalendarConfig {
+  pub name: String,
+  pub location: String,
+  // new events go to the default calendar
+  #[serde(default)]
+  pub default: bool,
 }
 
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
@@ -30,6 +52,7 @@ impl Default for Config {
     Self {
       calendar_name: "malakal".into(),
       calendar_location: format!("~/.calendar/{APP_NAME}"),
+      calendars: vec![],
       timezone: None,
       notifier_switch: true,
       notification_timeout: Duration::seconds(5),
@@ -37,18 +60,63 @@ impl Default for Config {
       post_update_hook: None,
       post_update_hook_delay: Duration::seconds(30),
       day_column_width: None,
+      day_count: None,
+      day_view: None,
+      first_weekday: "monday".into(),
+      trash_retention_days: 30,
     }
   }
 }
 
 impl Config {
   pub fn normalize(&mut self) -> anyhow::Result<()> {
-    self.calendar_location =
-      self.calendar_location.replace('~', &std::env::var("HOME")?);
+    let home = std::env::var("HOME")?;
+    self.calendar_location = self.calendar_location.replace('~', &home);
+    for calendar in self.calendars.iter_mut() {
+      calendar.location = calendar.location.replace('~', &home);
+    }
+
+    if self.first_weekday.parse::<Weekday>().is_err() {
+      return Err(anyhow!("Invalid first_weekday: {}", self.first_weekday));
+    }
+    if self.day_count == Some(0) {
+      return Err(anyhow!("day_count must be at least 1"));
+    }
+    match self.day_view.as_deref() {
+      None | Some("week" | "work_week") => (),
+      Some(day_view) => return Err(anyhow!("Invalid day_view: {day_view}")),
+    }
+    if self.day_view.is_some() && self.day_count.is_some() {
+      return Err(anyhow!("day_count and day_view can't be set together"));
+    }
 
     Ok(())
   }
 
+  // the listed calendars, or the single calendar otherwise
+  pub fn calendars(&self) -> Vec<CalendarConfig> {
+    if !self.calendars.is_empty() {
+      return self.calendars.clone();
+    }
+
+    vec![CalendarConfig {
+      name: self.calendar_name.clone(),
+      location: self.calendar_location.clone(),
+      default: true,
+    }]
+  }
+
+  // the calendar new events are created in
+  pub fn default_calendar(&self) -> CalendarConfig {
+    let calendars = self.calendars();
+    let default = calendars.iter().find(|c| c.default);
+    default.unwrap_or(&calendars[0]).clone()
+  }
+
+  pub fn first_weekday(&self) -> Weekday {
+    self.first_weekday.parse().unwrap_or(Weekday::Mon)
+  }
+
   pub fn read_or_initialize() -> anyhow::Result<Config> {
     let config_file = {
       let mut dir = dirs::config_dir()
@@ -78,10 +146,12 @@ impl Config {
     let mut config: Config = toml::from_str(config_str)?;
     config.normalize()?;
 
-    let calendar_location = PathBuf::from(config.calendar_location.as_str());
-    if !calendar_location.exists() {
-      log::info!("Creating calendar directory at {calendar_location:?}");
-      std::fs::create_dir_all(&calendar_location)?;
+    for calendar in config.calendars() {
+      let calendar_location = PathBuf::from(calendar.location.as_str());
+      if !calendar_location.exists() {
+        log::info!("Creating calendar directory at {calendar_location:?}");
+        std::fs::create_dir_all(&calendar_location)?;
+      }
     }
 
     Ok(config)
@@ -1,11 +1,27 @@
+use std::sync::Arc;
+
 use chrono::{FixedOffset, Offset, Timelike};
 use derive_builder::Builder;
 
+use crate::backend::Fingerprint;
+use crate::ical::RawEvent;
+use crate::recurrence::{parse_occurrence_id, Recurrence, RecurrenceScope};
+use crate::timezone::Zone;
 use crate::util::{now, utc_now, DateTime};
 
 const SECS_PER_DAY: u64 = 24 * 3600;
 pub type EventId = String;
 
+// the timezone the wall clock times of an event are given in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum EventTimezone {
+  #[default]
+  Utc,
+  // the same wall clock time in whatever timezone is in use
+  Floating,
+  Zoned(Zone),
+}
+
 #[derive(Builder, Clone, Debug, PartialEq)]
 #[builder(try_setter, setter(into))]
 pub struct Event {
@@ -16,6 +32,16 @@ pub struct Event {
   pub start: DateTime,
   pub end: DateTime,
 
+  // all-day events span whole dates: start and end (exclusive) are
+  // midnights in UTC, regardless of the timezone in use
+  #[builder(default)]
+  pub all_day: bool,
+
+  // how the start and end are written, and the timezone the
+  // recurrences are expanded in
+  #[builder(default)]
+  pub timezone: EventTimezone,
+
   // RFC 5545 DTSTAMP field
   #[builder(default = "utc_now()")]
   pub timestamp: DateTime,
@@ -29,14 +55,36 @@ pub struct Event {
   #[builder(default)]
   pub description: Option<String>,
 
-  #[builder(default = "[0.3; 3]")]
-  pub color: [f32; 3],
+  // in linear rgb, None for the default color
+  #[builder(default)]
+  pub color: Option<[f32; 3]>,
+
+  // RRULE/RDATE/EXDATE of a recurring event
+  #[builder(default)]
+  pub recurrence: Option<Recurrence>,
+
+  // RFC 5545 RECURRENCE-ID field, set on the occurrences of a
+  // recurring event
+  #[builder(default)]
+  pub recurrence_id: Option<DateTime>,
+
+  // the ical components the event was read from
+  #[builder(default)]
+  pub(crate) raw: Option<Arc<RawEvent>>,
+
+  // the content of the file the event was read from
+  #[builder(default)]
+  pub(crate) fingerprint: Option<Fingerprint>,
 
   #[builder(default = "false", setter(skip))]
   pub(crate) deleted: bool,
 
   #[builder(default = "false", setter(skip))]
   pub(crate) changed: bool,
+
+  // the occurrences a change or deletion of an occurrence applies to
+  #[builder(default, setter(skip))]
+  pub(crate) recurrence_scope: RecurrenceScope,
 }
 
 impl Event {
@@ -45,6 +93,42 @@ impl Event {
       .clamp(0.0, 1.0)
   }
 
+  // the UID of the underlying ical event, which differs from the id
+  // for occurrences of a recurring event
+  pub(crate) fn uid(&self) -> &str {
+    match self.recurrence_id {
+      Some(_) => parse_occurrence_id(&self.id).map_or(&self.id, |(uid, _)| uid),
+      None => &self.id,
+    }
+  }
+
+  pub(crate) fn zone(&self) -> Zone {
+    match &self.timezone {
+      EventTimezone::Utc => Zone::utc(),
+      EventTimezone::Floating => Zone::local(),
+      EventTimezone::Zoned(zone) => zone.clone(),
+    }
+  }
+
+  // the event and its overridden occurrences were read from the file
+  pub(crate) fn set_fingerprint(&mut self, fingerprint: Fingerprint) {
+    self.fingerprint = Some(fingerprint);
+    if let Some(recurrence) = self.recurrence.as_mut() {
+      for overridden in recurrence.overrides.iter_mut() {
+        overridden.fingerprint = Some(fingerprint);
+      }
+    }
+  }
+
+  // the LOCATION, only known for events read from ics files
+  pub(crate) fn location(&self) -> Option<String> {
+    self.raw.as_ref().and_then(|raw| raw.location())
+  }
+
+  pub(crate) fn is_recurring(&self) -> bool {
+    self.recurrence.is_some()
+  }
+
   pub(crate) fn mark_changed(&mut self) {
     self.modified_at = now(&self.modified_at.offset().fix());
     self.changed = true;
@@ -58,13 +142,21 @@ impl Event {
   pub(crate) fn reset_dirty_flags(&mut self) {
     self.deleted = false;
     self.changed = false;
+    self.recurrence_scope = RecurrenceScope::ThisOnly;
   }
 
   pub(crate) fn set_timezone(&mut self, tz: &FixedOffset) {
     self.created_at = self.created_at.with_timezone(tz);
     self.modified_at = self.modified_at.with_timezone(tz);
     self.timestamp = self.timestamp.with_timezone(tz);
+
+    // the dates of all-day events are the same in every timezone
+    if self.all_day {
+      return;
+    }
+
     self.start = self.start.with_timezone(tz);
     self.end = self.end.with_timezone(tz);
+    self.recurrence_id = self.recurrence_id.map(|t| t.with_timezone(tz));
   }
 }
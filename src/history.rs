@@ -0,0 +1,832 @@
+use std::{collections::HashMap, fs::create_dir_all, path::Path};
+
+use chrono::{TimeZone, Utc};
+use rusqlite::{params, Connection, Row};
+
+use crate::{
+  backend::Fingerprint,
+  event::{Event, EventId},
+  ical::ICal,
+  journal::{parse_scope, scope_name},
+  recurrence::RecurrenceScope,
+  util::{utc_now, DateTime, Result},
+};
+
+// the changes kept at most, the oldest are forgotten first
+const MAX_ENTRIES: usize = 1000;
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+  Added { new: Event },
+  Removed { old: Event },
+  Modified { old: Event, new: Event },
+}
+
+impl Change {
+  pub(crate) fn reverse(self) -> Self {
+    use Change::*;
+
+    match self {
+      Added { new } => Removed { old: new },
+      Removed { old } => Added { new: old },
+      Modified { old, new } => Modified { new: old, old: new },
+    }
+  }
+
+  pub(crate) fn new_removed(
+    events: &[Event],
+    event_id: &EventId,
+  ) -> Option<Self> {
+    events
+      .iter()
+      .find(|&e| &e.id == event_id)
+      .cloned()
+      .map(|old| Change::Removed { old })
+  }
+
+  pub(crate) fn new_changed(events: &[Event], changed_event: Event) -> Self {
+    if let Some(existing) =
+      events.iter().find(|&e| e.id == changed_event.id).cloned()
+    {
+      Change::Modified {
+        old: existing,
+        new: changed_event,
+      }
+    } else {
+      Change::Added { new: changed_event }
+    }
+  }
+
+  // a change to an occurrence of a recurring event
+  pub(crate) fn is_occurrence_change(&self) -> bool {
+    match self {
+      Change::Added { .. } => false,
+      Change::Removed { old } => old.recurrence_id.is_some(),
+      Change::Modified { old, new } => {
+        old.recurrence_id.is_some() && old != new
+      }
+    }
+  }
+
+  pub(crate) fn with_scope(mut self, scope: RecurrenceScope) -> Self {
+    match &mut self {
+      Change::Added { new } => new.recurrence_scope = scope,
+      Change::Removed { old } => old.recurrence_scope = scope,
+      Change::Modified { old, new } => {
+        old.recurrence_scope = scope;
+        new.recurrence_scope = scope;
+      }
+    }
+    self
+  }
+
+  // The events changed are added when not loaded, which is the case
+  // when undoing a change made to other days or in an earlier run.
+  pub(crate) fn apply(&self, events: &mut Vec<Event>) {
+    match self.clone() {
+      Change::Added { mut new } | Change::Modified { mut new, .. } => {
+        new.mark_changed();
+
+        match events.iter_mut().find(|e| e.id == new.id) {
+          Some(e) => *e = new,
+          None => events.push(new),
+        }
+      }
+      Change::Removed { mut old } => {
+        if let Some(e) = events.iter_mut().find(|e| e.id == old.id) {
+          e.recurrence_scope = old.recurrence_scope;
+          e.mark_deleted();
+        } else {
+          old.mark_deleted();
+          events.push(old);
+        }
+      }
+    }
+  }
+
+  // what the change did, for the history list
+  pub(crate) fn describe(&self) -> String {
+    match self {
+      Change::Added { new } => format!("Create \"{}\"", new.title),
+      Change::Removed { old } => format!("Delete \"{}\"", old.title),
+      Change::Modified { old, new } if old.title != new.title => {
+        format!("Rename \"{}\" to \"{}\"", old.title, new.title)
+      }
+      Change::Modified { old, new }
+        if old.start == new.start && old.end == new.end =>
+      {
+        format!("Change \"{}\"", new.title)
+      }
+      Change::Modified { old, new }
+        if old.end - old.start == new.end - new.start =>
+      {
+        format!("Move \"{}\"", new.title)
+      }
+      Change::Modified { new, .. } => format!("Resize \"{}\"", new.title),
+    }
+  }
+
+  // the id of the event changed
+  pub(crate) fn event_id(&self) -> &EventId {
+    match self {
+      Change::Added { new: event }
+      | Change::Removed { old: event }
+      | Change::Modified { new: event, .. } => &event.id,
+    }
+  }
+
+  fn old_event(&self) -> Option<&Event> {
+    match self {
+      Change::Added { .. } => None,
+      Change::Removed { old } | Change::Modified { old, .. } => Some(old),
+    }
+  }
+
+  fn new_event(&self) -> Option<&Event> {
+    match self {
+      Change::Removed { .. } => None,
+      Change::Added { new } | Change::Modified { new, .. } => Some(new),
+    }
+  }
+
+  fn scope(&self) -> RecurrenceScope {
+    self
+      .new_event()
+      .or(self.old_event())
+      .map(|e| e.recurrence_scope)
+      .unwrap_or(RecurrenceScope::ThisOnly)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+  pub change: Change,
+  // The changes the backend made to whole series for a change of
+  // occurrences, which are undone and redone instead: the series
+  // before and after, and the series split off from it. Empty until
+  // the change is saved.
+  pub series: Vec<Change>,
+  pub made_at: DateTime,
+}
+
+impl Entry {
+  fn changes(&self) -> Vec<Change> {
+    if self.series.is_empty() {
+      vec![self.change.clone()]
+    } else {
+      self.series.clone()
+    }
+  }
+
+  fn event_ids(&self) -> impl Iterator<Item = &EventId> {
+    [&self.change]
+      .into_iter()
+      .chain(self.series.iter())
+      .flat_map(|c| c.old_event().into_iter().chain(c.new_event()))
+      .map(|e| &e.id)
+  }
+}
+
+// the history changes to write to the store
+#[derive(Clone, Debug, PartialEq)]
+pub enum HistoryUpdate {
+  Saved(Box<Entry>),
+  Undone,
+  Redone,
+}
+
+// The changes made to the events, which can be undone and redone. The
+// changes are kept across scope changes, and across restarts by
+// storing the updates.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct History {
+  // the changes made, the latest last
+  done: Vec<Entry>,
+  // the changes undone, the latest undone last
+  undone: Vec<Entry>,
+  // the updates not stored yet
+  updates: Vec<HistoryUpdate>,
+}
+
+impl History {
+  pub(crate) fn save(&mut self, change: Change) {
+    let entry = Entry {
+      change,
+      series: vec![],
+      made_at: utc_now(),
+    };
+
+    // the changes undone can't be redone after a new change
+    self.undone.clear();
+    self.done.push(entry.clone());
+    if self.done.len() > MAX_ENTRIES {
+      self.done.remove(0);
+    }
+    self.updates.push(HistoryUpdate::Saved(Box::new(entry)));
+  }
+
+  // the changes to undo, which have to be reversed and applied in
+  // reverse order
+  pub(crate) fn undo(&mut self) -> Option<Vec<Change>> {
+    let entry = self.done.pop()?;
+    let changes = entry.changes();
+    self.undone.push(entry);
+    self.updates.push(HistoryUpdate::Undone);
+    Some(changes)
+  }
+
+  pub(crate) fn redo(&mut self) -> Option<Vec<Change>> {
+    let entry = self.undone.pop()?;
+    let changes = entry.changes();
+    self.done.push(entry);
+    self.updates.push(HistoryUpdate::Redone);
+    Some(changes)
+  }
+
+  // The changes made to whole series when saving the change of an
+  // occurrence, found by the occurrence id. They are stored by the
+  // worker along with the entry.
+  pub(crate) fn set_series(&mut self, event_id: &EventId, series: &[Change]) {
+    let entry = self
+      .done
+      .iter_mut()
+      .rev()
+      .chain(self.undone.iter_mut())
+      .find(|e| {
+        e.series.is_empty()
+          && e.change.is_occurrence_change()
+          && e.change.event_id() == event_id
+      });
+
+    if let Some(entry) = entry {
+      entry.series = series.to_vec();
+    }
+  }
+
+  // the changes made, the latest last
+  pub(crate) fn done(&self) -> &[Entry] {
+    &self.done
+  }
+
+  // the changes undone, the latest undone last
+  pub(crate) fn undone(&self) -> &[Entry] {
+    &self.undone
+  }
+
+  pub fn take_updates(&mut self) -> Vec<HistoryUpdate> {
+    std::mem::take(&mut self.updates)
+  }
+}
+
+// The history kept in a database in the data directory, so that
+// changes can be undone after a restart.
+//
+// The events are kept as the ics they would be written as. The files
+// of the events are remembered as they were last written by an undo,
+// a redo or the change itself, and the events read back take that
+// fingerprint, so that undoing a change after a restart doesn't
+// overwrite the changes made by other programs since.
+pub struct HistoryStore {
+  conn: Connection,
+}
+
+// an event of a change stored as its id, calendar and ics, missing for
+// the event before an addition and after a removal
+type StoredEvent = Option<(EventId, String, String)>;
+
+impl HistoryStore {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    if let Some(dir) = path.as_ref().parent() {
+      create_dir_all(dir)?;
+    }
+
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    conn.execute_batch(
+      "
+CREATE TABLE IF NOT EXISTS history (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  -- unix time in milliseconds
+  made_at INTEGER NOT NULL,
+  undone INTEGER NOT NULL,
+  scope TEXT NOT NULL,
+  -- the event before and after the change, missing for new and
+  -- deleted events respectively
+  old_id TEXT,
+  old_calendar TEXT,
+  old_ics TEXT,
+  new_id TEXT,
+  new_calendar TEXT,
+  new_ics TEXT
+);
+-- the changes made to whole series by a change of occurrences
+CREATE TABLE IF NOT EXISTS series (
+  history_id INTEGER NOT NULL REFERENCES history (id) ON DELETE CASCADE,
+  old_id TEXT,
+  old_calendar TEXT,
+  old_ics TEXT,
+  new_id TEXT,
+  new_calendar TEXT,
+  new_ics TEXT
+);
+CREATE TABLE IF NOT EXISTS written (
+  event_id TEXT PRIMARY KEY,
+  content_length INTEGER NOT NULL,
+  content_hash INTEGER NOT NULL
+);
+",
+    )?;
+
+    Ok(Self { conn })
+  }
+
+  pub fn load(&self) -> Result<History> {
+    let written = self.written()?;
+    let read_change = |old, new, scope| {
+      let event = |stored: StoredEvent| {
+        stored
+          .map(|(event_id, calendar, ics)| {
+            let mut event = ICal.parse_generated(&calendar, &ics)?;
+            // occurrences are read with the id of their series
+            event.fingerprint = written.get(&event_id).copied();
+            event.id = event_id;
+            event.recurrence_scope = scope;
+            Ok::<_, anyhow::Error>(event)
+          })
+          .transpose()
+      };
+
+      Ok::<_, anyhow::Error>(match (event(old)?, event(new)?) {
+        (None, Some(new)) => Some(Change::Added { new }),
+        (Some(old), None) => Some(Change::Removed { old }),
+        (Some(old), Some(new)) => Some(Change::Modified { old, new }),
+        (None, None) => None,
+      })
+    };
+
+    let mut series: HashMap<i64, Vec<(StoredEvent, StoredEvent)>> =
+      HashMap::new();
+    let mut stmt = self.conn.prepare(
+      "
+SELECT history_id,
+  old_id, old_calendar, old_ics, new_id, new_calendar, new_ics
+FROM series
+ORDER BY rowid
+",
+    )?;
+    let rows = stmt.query_map([], |row| {
+      Ok((row.get(0)?, stored_event(row, 1)?, stored_event(row, 4)?))
+    })?;
+    for row in rows {
+      let (id, old, new) = row?;
+      series.entry(id).or_default().push((old, new));
+    }
+
+    let mut stmt = self.conn.prepare(
+      "
+SELECT id, made_at, undone, scope,
+  old_id, old_calendar, old_ics, new_id, new_calendar, new_ics
+FROM history
+ORDER BY id
+",
+    )?;
+    let rows = stmt.query_map([], |row| {
+      Ok((
+        row.get::<_, i64>(0)?,
+        row.get::<_, i64>(1)?,
+        row.get::<_, bool>(2)?,
+        row.get::<_, String>(3)?,
+        stored_event(row, 4)?,
+        stored_event(row, 7)?,
+      ))
+    })?;
+
+    let mut history = History::default();
+    let mut unreadable = vec![];
+    for row in rows {
+      let (id, made_at, undone, scope, old, new) = row?;
+      let scope = parse_scope(&scope);
+      let series = series.remove(&id).unwrap_or_default();
+      let changes = read_change(old, new, scope).and_then(|change| {
+        let series = series
+          .into_iter()
+          .map(|(old, new)| read_change(old, new, RecurrenceScope::ThisOnly))
+          .collect::<Result<Vec<_>>>()?;
+        Ok(change.zip(series.into_iter().collect::<Option<Vec<_>>>()))
+      });
+
+      let (change, series) = match changes {
+        Ok(Some(changes)) => changes,
+        Ok(None) => {
+          unreadable.push(id);
+          continue;
+        }
+        Err(e) => {
+          log::warn!("Skipping unreadable history entry {id}: {e:?}");
+          unreadable.push(id);
+          continue;
+        }
+      };
+      let Some(made_at) = Utc.timestamp_millis_opt(made_at).single() else {
+        unreadable.push(id);
+        continue;
+      };
+
+      let entry = Entry {
+        change,
+        series,
+        made_at: made_at.fixed_offset(),
+      };
+      if undone {
+        history.undone.push(entry);
+      } else {
+        history.done.push(entry);
+      }
+    }
+
+    // the latest undone is the earliest made
+    history.undone.reverse();
+
+    // keep the rows in line with the history
+    for id in unreadable {
+      self
+        .conn
+        .execute("DELETE FROM history WHERE id = ?", [id])?;
+    }
+
+    Ok(history)
+  }
+
+  // the events changed by the updates are returned
+  pub fn update(&mut self, updates: &[HistoryUpdate]) -> Result<Vec<EventId>> {
+    let mut event_ids = vec![];
+    let tx = self.conn.transaction()?;
+    for update in updates {
+      match update {
+        HistoryUpdate::Saved(entry) => {
+          tx.execute("DELETE FROM history WHERE undone", [])?;
+
+          let [old_id, old_calendar, old_ics] =
+            to_stored(entry.change.old_event())?;
+          let [new_id, new_calendar, new_ics] =
+            to_stored(entry.change.new_event())?;
+          tx.execute(
+            "
+INSERT INTO history
+  (made_at, undone, scope,
+   old_id, old_calendar, old_ics, new_id, new_calendar, new_ics)
+VALUES (?, FALSE, ?, ?, ?, ?, ?, ?, ?)
+",
+            params![
+              entry.made_at.timestamp_millis(),
+              scope_name(entry.change.scope()),
+              old_id,
+              old_calendar,
+              old_ics,
+              new_id,
+              new_calendar,
+              new_ics,
+            ],
+          )?;
+
+          let history_id = tx.last_insert_rowid();
+          for change in entry.series.iter() {
+            let [old_id, old_calendar, old_ics] =
+              to_stored(change.old_event())?;
+            let [new_id, new_calendar, new_ics] =
+              to_stored(change.new_event())?;
+            tx.execute(
+              "
+INSERT INTO series
+  (history_id,
+   old_id, old_calendar, old_ics, new_id, new_calendar, new_ics)
+VALUES (?, ?, ?, ?, ?, ?, ?)
+",
+              params![
+                history_id,
+                old_id,
+                old_calendar,
+                old_ics,
+                new_id,
+                new_calendar,
+                new_ics,
+              ],
+            )?;
+          }
+          event_ids.extend(entry.event_ids().cloned());
+
+          tx.execute(
+            "
+DELETE FROM history WHERE id NOT IN (
+  SELECT id FROM history ORDER BY id DESC LIMIT ?
+)
+",
+            [MAX_ENTRIES],
+          )?;
+        }
+        HistoryUpdate::Undone => {
+          let row = "(SELECT max(id) FROM history WHERE NOT undone)";
+          event_ids.extend(entry_event_ids(&tx, row)?);
+          tx.execute(
+            &format!("UPDATE history SET undone = TRUE WHERE id = {row}"),
+            [],
+          )?;
+        }
+        HistoryUpdate::Redone => {
+          let row = "(SELECT min(id) FROM history WHERE undone)";
+          event_ids.extend(entry_event_ids(&tx, row)?);
+          tx.execute(
+            &format!("UPDATE history SET undone = FALSE WHERE id = {row}"),
+            [],
+          )?;
+        }
+      }
+    }
+    tx.commit()?;
+
+    event_ids.sort();
+    event_ids.dedup();
+    Ok(event_ids)
+  }
+
+  // the file of the event as it was written, None if it's deleted
+  pub fn record_written(
+    &self,
+    event_id: &EventId,
+    fingerprint: Option<Fingerprint>,
+  ) -> Result<()> {
+    match fingerprint {
+      Some(fingerprint) => self.conn.execute(
+        "
+INSERT OR REPLACE INTO written (event_id, content_length, content_hash)
+VALUES (?, ?, ?)
+",
+        params![event_id, fingerprint.size as i64, fingerprint.hash as i64],
+      )?,
+      None => self
+        .conn
+        .execute("DELETE FROM written WHERE event_id = ?", [event_id])?,
+    };
+
+    Ok(())
+  }
+
+  fn written(&self) -> Result<HashMap<EventId, Fingerprint>> {
+    let mut stmt = self
+      .conn
+      .prepare("SELECT event_id, content_length, content_hash FROM written")?;
+    let rows = stmt.query_map([], |row| {
+      let fingerprint = Fingerprint {
+        size: row.get::<_, i64>(1)? as u64,
+        hash: row.get::<_, i64>(2)? as u64,
+      };
+      Ok((row.get(0)?, fingerprint))
+    })?;
+
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+  }
+}
+
+// the id, calendar and ics columns starting at i
+fn stored_event(row: &Row, i: usize) -> rusqlite::Result<StoredEvent> {
+  let id: Option<String> = row.get(i)?;
+  let calendar: Option<String> = row.get(i + 1)?;
+  let ics: Option<String> = row.get(i + 2)?;
+  Ok(id.zip(calendar).zip(ics).map(|((a, b), c)| (a, b, c)))
+}
+
+fn to_stored(event: Option<&Event>) -> Result<[Option<String>; 3]> {
+  let Some(event) = event else {
+    return Ok([None, None, None]);
+  };
+  Ok([
+    Some(event.id.clone()),
+    Some(event.calendar.clone()),
+    Some(ICal.generate(event)?),
+  ])
+}
+
+// the events changed by the entry of the row selected
+fn entry_event_ids(conn: &Connection, row: &str) -> Result<Vec<EventId>> {
+  let mut stmt = conn.prepare(&format!(
+    "
+SELECT old_id, new_id FROM history WHERE id = {row}
+UNION ALL
+SELECT old_id, new_id FROM series WHERE history_id = {row}
+"
+  ))?;
+  let mut rows = stmt.query([])?;
+  let mut event_ids = vec![];
+  while let Some(row) = rows.next()? {
+    event_ids.extend(row.get::<_, Option<EventId>>(0)?);
+    event_ids.extend(row.get::<_, Option<EventId>>(1)?);
+  }
+  Ok(event_ids)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::DateTime as ChronoDateTime;
+  use tempfile::TempDir;
+
+  use super::*;
+  use crate::backend::{Backend, Conflict, LocalDir, LocalDirBuilder};
+
+  const LUNCH: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:test\r
+BEGIN:VEVENT\r
+UID:lunch\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20240305T120000Z\r
+DTEND:20240305T130000Z\r
+SUMMARY:lunch\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+  fn time(s: &str) -> DateTime {
+    ChronoDateTime::parse_from_rfc3339(s).unwrap()
+  }
+
+  fn calendar(dir: &TempDir) -> LocalDir {
+    let calendar_dir = dir.path().join("calendar");
+    std::fs::create_dir_all(&calendar_dir).unwrap();
+    LocalDirBuilder::default()
+      .dir(calendar_dir)
+      .calendar("test")
+      .build()
+      .unwrap()
+  }
+
+  fn describe(entries: &[Entry]) -> Vec<String> {
+    entries.iter().map(|e| e.change.describe()).collect()
+  }
+
+  #[test]
+  fn history_survives_restarts() {
+    let lunch = ICal.parse("test", LUNCH).unwrap().remove(0);
+    let mut moved = lunch.clone();
+    moved.start = time("2024-03-05T14:00:00Z");
+    moved.end = time("2024-03-05T15:00:00Z");
+    let mut renamed = moved.clone();
+    renamed.title = "late lunch".to_string();
+    let mut created = lunch.clone();
+    created.id = "coffee".to_string();
+    created.title = "coffee".to_string();
+
+    let mut history = History::default();
+    history.save(Change::Modified {
+      old: lunch.clone(),
+      new: moved.clone(),
+    });
+    history.save(Change::Modified {
+      old: moved.clone(),
+      new: renamed.clone(),
+    });
+    history.save(Change::Added { new: created });
+    history.undo().unwrap();
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("history.db");
+    let mut store = HistoryStore::open(&path).unwrap();
+    store.update(&history.take_updates()).unwrap();
+    drop(store);
+
+    let mut store = HistoryStore::open(&path).unwrap();
+    let mut history = store.load().unwrap();
+    assert_eq!(
+      describe(history.done()),
+      ["Move \"lunch\"", "Rename \"lunch\" to \"late lunch\""]
+    );
+    assert_eq!(describe(history.undone()), ["Create \"coffee\""]);
+
+    // undoing with none of the events loaded
+    let mut events = vec![];
+    history
+      .undo()
+      .unwrap()
+      .remove(0)
+      .reverse()
+      .apply(&mut events);
+    history
+      .undo()
+      .unwrap()
+      .remove(0)
+      .reverse()
+      .apply(&mut events);
+    assert_eq!(events.len(), 1);
+    assert!(events[0].changed);
+    assert_eq!(events[0].title, "lunch");
+    assert_eq!(events[0].start, lunch.start);
+
+    // a new change drops the changes undone
+    history.redo().unwrap();
+    history.save(Change::Removed { old: moved });
+    let event_ids = store.update(&history.take_updates()).unwrap();
+    assert_eq!(event_ids, ["lunch"]);
+    let history = store.load().unwrap();
+    assert_eq!(
+      describe(history.done()),
+      ["Move \"lunch\"", "Delete \"lunch\""]
+    );
+    assert!(history.undone().is_empty());
+  }
+
+  #[test]
+  fn series_changes_survive_restarts() {
+    let lunch = ICal.parse("test", LUNCH).unwrap().remove(0);
+    let mut occurrence = lunch.clone();
+    occurrence.id = "lunch#20240305T120000Z".to_string();
+    occurrence.recurrence_id = Some(lunch.start);
+    let mut renamed = occurrence.clone();
+    renamed.title = "brunch".to_string();
+    let mut split = lunch.clone();
+    split.id = "brunch".to_string();
+
+    let mut history = History::default();
+    history.save(Change::Modified {
+      old: occurrence.clone(),
+      new: renamed,
+    });
+    let series = [
+      Change::Modified {
+        old: lunch.clone(),
+        new: lunch.clone(),
+      },
+      Change::Added { new: split },
+    ];
+    history.set_series(&occurrence.id, &series);
+    let mut updates = history.take_updates();
+    if let HistoryUpdate::Saved(entry) = &mut updates[0] {
+      entry.series = series.to_vec();
+    }
+
+    let dir = TempDir::new().unwrap();
+    let mut store = HistoryStore::open(dir.path().join("history.db")).unwrap();
+    let event_ids = store.update(&updates).unwrap();
+    assert_eq!(event_ids, ["brunch", "lunch", "lunch#20240305T120000Z"]);
+
+    let mut history = store.load().unwrap();
+    let changes = history.undo().unwrap();
+    let ids: Vec<_> = changes.iter().map(|c| c.event_id().as_str()).collect();
+    assert_eq!(ids, ["lunch", "brunch"]);
+    assert_eq!(
+      describe(history.undone()),
+      ["Rename \"lunch\" to \"brunch\""]
+    );
+  }
+
+  #[test]
+  fn undoing_after_a_restart_keeps_changes_made_outside() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("history.db");
+    let mut backend = calendar(&dir);
+    let lunch = ICal.parse("test", LUNCH).unwrap().remove(0);
+    backend.create_event(&lunch).unwrap();
+    let lunch = backend.get_event(&lunch.id).unwrap();
+
+    // the change is saved before its history is stored
+    let mut moved = lunch.clone();
+    moved.start = time("2024-03-05T14:00:00Z");
+    moved.end = time("2024-03-05T15:00:00Z");
+    backend.update_event(&moved).unwrap();
+    let mut history = History::default();
+    history.save(Change::Modified {
+      old: lunch,
+      new: moved,
+    });
+    let mut store = HistoryStore::open(&path).unwrap();
+    for event_id in store.update(&history.take_updates()).unwrap() {
+      let written = backend.get_event(&event_id).unwrap().fingerprint;
+      store.record_written(&event_id, written).unwrap();
+    }
+    drop(store);
+
+    // another program renames the event after a restart
+    let mut backend = calendar(&dir);
+    let file = dir.path().join("calendar/lunch.ics");
+    let content = std::fs::read_to_string(&file).unwrap();
+    let mut history = HistoryStore::open(&path).unwrap().load().unwrap();
+    std::fs::write(&file, content.replace("SUMMARY:lunch", "SUMMARY:brunch"))
+      .unwrap();
+
+    let mut events = vec![];
+    history
+      .undo()
+      .unwrap()
+      .remove(0)
+      .reverse()
+      .apply(&mut events);
+    let error = backend.update_event(&events[0]).unwrap_err();
+    assert!(error.is::<Conflict>());
+
+    // undone as long as the file is as it was written
+    std::fs::write(&file, content).unwrap();
+    backend.update_event(&events[0]).unwrap();
+    let lunch = backend.get_event(&events[0].id).unwrap();
+    assert_eq!(lunch.start, time("2024-03-05T12:00:00Z"));
+  }
+}
@@ -1,139 +1,604 @@
+use std::sync::Arc;
+
 use anyhow::{bail, ensure};
-use chrono::{DateTime, Duration, NaiveDateTime, Utc};
-use ical::property::Property;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
+
+use crate::event::{Event, EventBuilder, EventTimezone};
+use crate::recurrence::{RRule, Recurrence};
+use crate::timezone::{CustomZone, Zone};
+use crate::util::{all_day_time, anyhow, Result};
 
-use crate::event::{Event, EventBuilder};
-use crate::util::{anyhow, Result};
+mod color;
+mod component;
+mod duration;
+mod timezone;
+
+use self::color::color_name;
+pub(crate) use self::color::{color_hex, parse_color};
+use self::component::{escape_text, unescape_text, Component, Property};
+use self::duration::{format_duration, parse_duration, NominalDuration};
+use self::timezone::{
+  custom_vtimezone, embedded_zones, generate_vtimezone, parse_vtimezone,
+};
 
 pub(crate) struct ICal;
 
+// The components an event was read from. Properties and components
+// malakal doesn't know about are written back from here.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawEvent {
+  // the VCALENDAR without its events, only kept on the main event
+  calendar: Option<Component>,
+  event: Component,
+}
+
+impl RawEvent {
+  pub fn location(&self) -> Option<String> {
+    let location = self.event.property("LOCATION")?;
+    Some(unescape_text(&location.value))
+  }
+}
+
 impl ICal {
+  // a new ics file for the event
   pub fn generate(&self, event: &Event) -> Result<String> {
-    use ics::{properties::*, *};
+    let mut calendar = event
+      .raw
+      .as_ref()
+      .and_then(|raw| raw.calendar.clone())
+      .unwrap_or_else(new_calendar);
 
-    let mut ical_cal = ICalendar::new("2.0", "malakal");
-    ical_cal.add_timezone(TimeZone::standard(
-      "UTC",
-      Standard::new("19700329T020000", "+0000", "+0000"),
-    ));
-    ical_cal.push(CalScale::new("GREGORIAN"));
+    put_event(&mut calendar, event);
+    Ok(calendar.to_string())
+  }
+
+  // replace the event in the ics file, leaving the other events as
+  // they are
+  pub fn update(&self, content: &str, event: &Event) -> Result<String> {
+    let mut calendar = parse_calendar(content)?;
+    put_event(&mut calendar, event);
+    Ok(calendar.to_string())
+  }
 
-    let mut ical_event =
-      ics::Event::new(&event.id, to_timestamp(event.timestamp));
-    ical_event.push(DtStart::new(to_timestamp(event.start)));
-    ical_event.push(DtEnd::new(to_timestamp(event.end)));
-    ical_event.push(LastModified::new(to_timestamp(event.modified_at)));
-    ical_event.push(Created::new(to_timestamp(event.created_at)));
+  // the ics file without the event, or None if no events are left
+  pub fn remove(&self, content: &str, uid: &str) -> Result<Option<String>> {
+    let mut calendar = parse_calendar(content)?;
+    calendar.components.retain(|c| !is_event_of(c, uid));
 
-    ical_event.push(Summary::new(&event.title));
-    if let Some(desc) = &event.description {
-      ical_event.push(Description::new(desc));
+    if !calendar.components.iter().any(|c| c.name == "VEVENT") {
+      return Ok(None);
     }
 
-    ical_cal.add_event(ical_event);
+    Ok(Some(calendar.to_string()))
+  }
 
-    Ok(ical_cal.to_string())
+  // an ics file with only the event and the timezones, as the other
+  // events are removed from the file
+  pub fn extract(&self, content: &str, uid: &str) -> Result<String> {
+    let mut calendar = parse_calendar(content)?;
+    calendar
+      .components
+      .retain(|c| c.name != "VEVENT" || is_event_of(c, uid));
+    Ok(calendar.to_string())
   }
 
-  pub fn parse(&self, calendar_name: &str, content: &str) -> Result<Event> {
-    use ical::parser::ical::IcalParser;
+  // all events in the ics file, modified occurrences are attached to
+  // their recurring events
+  pub fn parse(
+    &self,
+    calendar_name: &str,
+    content: &str,
+  ) -> Result<Vec<Event>> {
+    let mut calendar = parse_calendar(content)?;
 
-    let ical_cal = IcalParser::new(content.as_bytes())
-      .next()
-      .ok_or_else(|| anyhow!("ics file contains only no calendar"))??;
+    let (vevents, others): (Vec<_>, Vec<_>) =
+      std::mem::take(&mut calendar.components)
+        .into_iter()
+        .partition(|c| c.name == "VEVENT");
+    calendar.components = others;
+    let zones = embedded_zones(&calendar);
 
-    ensure!(!ical_cal.events.is_empty(), "ics file contains no events");
-    ensure!(
-      ical_cal.events.len() == 1,
-      "ics file contains more than one events"
-    );
+    ensure!(!vevents.is_empty(), "ics file contains no events");
 
-    let ical_event = ical_cal.events.into_iter().next().unwrap();
-    let mut event = EventBuilder::default();
+    let mut events: Vec<Event> = vec![];
+    let mut overrides = vec![];
+    for vevent in vevents {
+      if vevent.property("RECURRENCE-ID").is_some() {
+        overrides.push(parse_event(calendar_name, vevent, None, &zones)?);
+        continue;
+      }
 
-    let value = |p: Property| -> Result<String> {
-      p.value
-        .ok_or_else(|| anyhow!("property {} doesn't have value", &p.name))
-    };
-    let parse_time = |p: Property| -> Result<DateTime<Utc>> {
-      let s = value(p.clone())?;
-      let tzid = p.params.and_then(|params| {
-        params.into_iter().find_map(|(n, v)| {
-          (n == "TZID")
-            .then_some(())
-            .and_then(|_| v.into_iter().next())
-        })
-      });
-      from_timestamp(&s, tzid.as_deref())
-    };
+      let calendar = Some(calendar.clone());
+      let event = parse_event(calendar_name, vevent, calendar, &zones)?;
+      ensure!(
+        events.iter().all(|e| e.id != event.id),
+        "ics file contains more than one event with UID {}",
+        event.id
+      );
+      events.push(event);
+    }
 
-    event.calendar(calendar_name);
+    for overridden in overrides {
+      match events.iter_mut().find(|e| e.id == overridden.id) {
+        Some(master) => master
+          .recurrence
+          .get_or_insert_with(Default::default)
+          .overrides
+          .push(overridden),
+        None => log::warn!(
+          "Ignoring a modified occurrence of a missing event {}",
+          overridden.id
+        ),
+      }
+    }
 
-    let mut start = None;
+    Ok(events)
+  }
 
-    for p in ical_event.properties {
-      match p.name.as_str() {
-        "UID" => event.id(value(p)?),
-        "SUMMARY" => event.title(value(p)?),
-        "DTSTAMP" => event.created_at(parse_time(p)?),
-        "DTSTART" => {
-          start = Some(parse_time(p)?);
-          event.start(start.unwrap())
-        }
-        "DTEND" => event.end(parse_time(p)?),
-        "DURATION" => {
-          let value = value(p)?;
-          let start =
-            start.ok_or_else(|| anyhow!("duration: start not defined yet"))?;
-          let end = start + parse_duration(&value)?;
-          event.end(end)
-        }
-        "CREATED" => event.created_at(parse_time(p)?),
-        "LAST-MODIFIED" => event.modified_at(parse_time(p)?),
-        _ => &mut event,
-      };
+  // The event of an ics file made by generate. Unlike parse, this
+  // also reads a lone occurrence of a recurring event.
+  pub fn parse_generated(
+    &self,
+    calendar_name: &str,
+    content: &str,
+  ) -> Result<Event> {
+    let mut calendar = parse_calendar(content)?;
+    let zones = embedded_zones(&calendar);
+
+    let vevent = calendar
+      .components
+      .iter()
+      .find(|c| c.name == "VEVENT")
+      .cloned()
+      .ok_or_else(|| anyhow!("ics file contains no events"))?;
+    if vevent.property("RECURRENCE-ID").is_none() {
+      let events = self.parse(calendar_name, content)?;
+      return events
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("ics file contains no events"));
     }
 
-    Ok(event.build()?)
+    calendar.components.retain(|c| c.name != "VEVENT");
+    parse_event(calendar_name, vevent, Some(calendar), &zones)
   }
 }
 
-fn to_timestamp<Tz: chrono::TimeZone>(time: DateTime<Tz>) -> String {
-  time.naive_utc().format("%Y%m%dT%H%M%SZ").to_string()
+// the VTIMEZONE defining a custom zone, for storing it elsewhere
+pub(crate) fn format_zone_definition(zone: &CustomZone) -> String {
+  custom_vtimezone(zone).to_string()
 }
 
-fn from_timestamp(s: &str, tzid: Option<&str>) -> Result<DateTime<Utc>> {
-  use chrono_tz::Tz;
-  use std::str::FromStr;
+pub(crate) fn parse_zone_definition(content: &str) -> Result<Zone> {
+  let vtimezone = component::parse(content)?
+    .into_iter()
+    .find(|c| c.name == "VTIMEZONE")
+    .ok_or_else(|| anyhow!("no VTIMEZONE defined"))?;
+  Ok(Zone::Custom(Arc::new(parse_vtimezone(&vtimezone)?)))
+}
 
-  if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ") {
-    return Ok(t.and_utc());
+fn parse_calendar(content: &str) -> Result<Component> {
+  component::parse(content)?
+    .into_iter()
+    .find(|c| c.name == "VCALENDAR")
+    .ok_or_else(|| anyhow!("ics file contains no calendar"))
+}
+
+// the VEVENTs of the event and its modified occurrences
+fn is_event_of(component: &Component, uid: &str) -> bool {
+  component.name == "VEVENT"
+    && component.property("UID").is_some_and(|p| p.value == uid)
+}
+
+// replace the components of the event in the calendar, or append them
+fn put_event(calendar: &mut Component, event: &Event) {
+  let zones = embedded_zones(calendar);
+  add_vtimezone(calendar, event);
+
+  let uid = event.uid();
+  let components = &mut calendar.components;
+  let position = components
+    .iter()
+    .position(|c| is_event_of(c, uid))
+    .unwrap_or(components.len());
+  components.retain(|c| !is_event_of(c, uid));
+
+  let mut vevents = vec![generate_event(event, &zones)];
+  // modified occurrences share the UID of the recurring event
+  if let Some(recurrence) = &event.recurrence {
+    for overridden in recurrence.overrides.iter() {
+      vevents.push(generate_event(overridden, &zones));
+    }
+  }
+
+  let position = position.min(components.len());
+  components.splice(position..position, vevents);
+}
+
+fn new_calendar() -> Component {
+  let mut calendar = Component::new("VCALENDAR");
+  calendar.set(Property::new("VERSION", "2.0"));
+  calendar.set(Property::new("PRODID", "malakal"));
+  calendar.set(Property::new("CALSCALE", "GREGORIAN"));
+  calendar
+}
+
+// define the TZID used by the event, unless the calendar already does
+fn add_vtimezone(calendar: &mut Component, event: &Event) {
+  let EventTimezone::Zoned(zone) = &event.timezone else {
+    return;
+  };
+  if event.all_day {
+    return;
   }
 
-  if let Some(tz) = tzid.and_then(|tz| Tz::from_str(tz).ok()) {
-    if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S") {
-      return Ok(t.and_local_timezone(tz).unwrap().with_timezone(&Utc));
+  let defined = calendar.components.iter().any(|c| {
+    c.name == "VTIMEZONE"
+      && c.property("TZID").is_some_and(|p| p.value == zone.tzid())
+  });
+  // custom timezones are always defined in the calendar they are from
+  if let (false, Zone::Iana(tz)) = (defined, zone) {
+    let year = zone.to_local(&event.start).year();
+    // timezones go before the events using them
+    let position = calendar
+      .components
+      .iter()
+      .position(|c| c.name == "VEVENT")
+      .unwrap_or(calendar.components.len());
+    calendar
+      .components
+      .insert(position, generate_vtimezone(*tz, year));
+  }
+}
+
+// Only the properties that changed since the event was read are
+// written, the others are kept as they were.
+fn generate_event(event: &Event, zones: &[Zone]) -> Component {
+  type Time = crate::util::DateTime;
+
+  fn rule(e: &Event) -> Option<&RRule> {
+    e.recurrence.as_ref().and_then(|r| r.rule.as_ref())
+  }
+  fn rdates(e: &Event) -> &[Time] {
+    e.recurrence.as_ref().map_or(&[], |r| &r.rdates)
+  }
+  fn exdates(e: &Event) -> &[Time] {
+    e.recurrence.as_ref().map_or(&[], |r| &r.exdates)
+  }
+  // whether times are written the same way
+  fn same_format(a: &Event, b: &Event) -> bool {
+    a.all_day == b.all_day && a.timezone == b.timezone
+  }
+
+  let original = event.raw.as_ref().and_then(|raw| {
+    parse_event(&event.calendar, raw.event.clone(), None, zones).ok()
+  });
+  let mut vevent = match &event.raw {
+    Some(raw) => raw.event.clone(),
+    None => Component::new("VEVENT"),
+  };
+
+  let changed = |same: fn(&Event, &Event) -> bool| {
+    !original
+      .as_ref()
+      .is_some_and(|original| same(original, event))
+  };
+
+  // all-day events are written with DATE values, the others in the
+  // timezone of the event
+  let times = |name: &str, times: &[Time]| {
+    let stamps: Vec<_> = times
+      .iter()
+      .map(|&t| match (event.all_day, &event.timezone) {
+        (true, _) => to_datestamp(t),
+        (false, EventTimezone::Utc) => to_timestamp(t),
+        (false, EventTimezone::Floating) => {
+          to_local_timestamp(t, &event.zone())
+        }
+        (false, EventTimezone::Zoned(zone)) => to_local_timestamp(t, zone),
+      })
+      .collect();
+
+    let property = Property::new(name, stamps.join(","));
+    match (event.all_day, &event.timezone) {
+      (true, _) => property.with_param("VALUE", "DATE"),
+      (false, EventTimezone::Zoned(zone)) => {
+        property.with_param("TZID", zone.tzid())
+      }
+      (false, _) => property,
+    }
+  };
+
+  if changed(|a, b| a.uid() == b.uid()) {
+    vevent.set(Property::new("UID", event.uid()));
+  }
+  if changed(|a, b| a.timestamp == b.timestamp) {
+    vevent.set(Property::new("DTSTAMP", to_timestamp(event.timestamp)));
+  }
+  if changed(|a, b| same_format(a, b) && a.start == b.start) {
+    vevent.set(times("DTSTART", &[event.start]));
+  }
+  if vevent.property("DURATION").is_some() {
+    // keep using DURATION if the event was written with it
+    if changed(|a, b| {
+      a.all_day == b.all_day && a.end - a.start == b.end - b.start
+    }) {
+      let duration =
+        NominalDuration::between(event.start, event.end, &event.zone());
+      let duration = format_duration(duration);
+      vevent.set(Property::new("DURATION", duration));
     }
+  } else if changed(|a, b| same_format(a, b) && a.end == b.end) {
+    vevent.set(times("DTEND", &[event.end]));
+  }
+  if changed(|a, b| a.modified_at == b.modified_at) {
+    let modified_at = to_timestamp(event.modified_at);
+    vevent.set(Property::new("LAST-MODIFIED", modified_at));
+  }
+  if changed(|a, b| a.created_at == b.created_at) {
+    vevent.set(Property::new("CREATED", to_timestamp(event.created_at)));
   }
 
-  bail!("failed to parse timestamp {}", s)
+  if changed(|a, b| a.color == b.color) {
+    match event.color {
+      Some(color) => {
+        let value = color_name(color)
+          .map(|name| name.to_string())
+          .unwrap_or_else(|| color_hex(color));
+        vevent.set(Property::new("COLOR", value))
+      }
+      None => vevent.remove("COLOR"),
+    }
+  }
+
+  if changed(|a, b| a.title == b.title) {
+    vevent.set(Property::new("SUMMARY", escape_text(&event.title)));
+  }
+  if changed(|a, b| a.description == b.description) {
+    match &event.description {
+      Some(desc) => vevent.set(Property::new("DESCRIPTION", escape_text(desc))),
+      None => vevent.remove("DESCRIPTION"),
+    }
+  }
+
+  if changed(|a, b| same_format(a, b) && a.recurrence_id == b.recurrence_id) {
+    match event.recurrence_id {
+      Some(recurrence_id) => {
+        vevent.set(times("RECURRENCE-ID", &[recurrence_id]))
+      }
+      None => vevent.remove("RECURRENCE-ID"),
+    }
+  }
+  if changed(|a, b| a.all_day == b.all_day && rule(a) == rule(b)) {
+    match rule(event) {
+      Some(rule) if event.all_day => {
+        vevent.set(Property::new("RRULE", rule.to_date_string()))
+      }
+      Some(rule) => vevent.set(Property::new("RRULE", rule.to_string())),
+      None => vevent.remove("RRULE"),
+    }
+  }
+  if changed(|a, b| same_format(a, b) && rdates(a) == rdates(b)) {
+    vevent.remove("RDATE");
+    if !rdates(event).is_empty() {
+      vevent.set(times("RDATE", rdates(event)));
+    }
+  }
+  if changed(|a, b| same_format(a, b) && exdates(a) == exdates(b)) {
+    vevent.remove("EXDATE");
+    if !exdates(event).is_empty() {
+      vevent.set(times("EXDATE", exdates(event)));
+    }
+  }
+
+  vevent
+}
+
+fn parse_event(
+  calendar_name: &str,
+  vevent: Component,
+  calendar: Option<Component>,
+  zones: &[Zone],
+) -> Result<Event> {
+  let mut event = EventBuilder::default();
+
+  let parse_time = |p: &Property| -> Result<DateTime<Utc>> {
+    from_timestamp(&p.value, property_zone(p, zones)?.as_ref())
+  };
+  let parse_times = |p: &Property| -> Result<Vec<DateTime<Utc>>> {
+    let zone = property_zone(p, zones)?;
+    p.value
+      .split(',')
+      .map(|s| from_timestamp(s, zone.as_ref()))
+      .collect()
+  };
+
+  event.calendar(calendar_name);
+
+  let mut start = None;
+  // the timezone days of a DURATION are counted in
+  let mut zone = Zone::utc();
+  let mut duration = None;
+  let mut end_defined = false;
+  let mut all_day = false;
+  let mut stamp = None;
+  let mut created_defined = false;
+  let mut modified_defined = false;
+  let mut rule = None;
+  let mut recurrence = Recurrence::default();
+  let mut color = None;
+  let mut apple_color = None;
+
+  for p in vevent.properties.iter() {
+    match p.name.as_str() {
+      "UID" => event.id(p.value.as_str()),
+      "SUMMARY" => event.title(unescape_text(&p.value)),
+      "DESCRIPTION" => event.description(Some(unescape_text(&p.value))),
+      "DTSTAMP" => {
+        stamp = Some(parse_time(p)?);
+        event.timestamp(stamp.unwrap())
+      }
+      "DTSTART" => {
+        all_day = is_date(p);
+        start = Some(parse_time(p)?);
+        let timezone = match property_zone(p, zones)? {
+          Some(zone) => EventTimezone::Zoned(zone),
+          None if all_day || p.value.ends_with('Z') => EventTimezone::Utc,
+          None => EventTimezone::Floating,
+        };
+        zone = match &timezone {
+          EventTimezone::Utc => Zone::utc(),
+          EventTimezone::Floating => Zone::local(),
+          EventTimezone::Zoned(zone) => zone.clone(),
+        };
+        event.timezone(timezone);
+        event.start(start.unwrap())
+      }
+      "DTEND" => {
+        end_defined = true;
+        event.end(parse_time(p)?)
+      }
+      "DURATION" => {
+        end_defined = true;
+        duration = Some(parse_duration(&p.value)?);
+        &mut event
+      }
+      "CREATED" => {
+        created_defined = true;
+        event.created_at(parse_time(p)?)
+      }
+      "LAST-MODIFIED" => {
+        modified_defined = true;
+        event.modified_at(parse_time(p)?)
+      }
+      "RECURRENCE-ID" => event.recurrence_id(Some(parse_time(p)?.into())),
+      "COLOR" => {
+        color = parse_color(&p.value);
+        &mut event
+      }
+      "X-APPLE-CALENDAR-COLOR" => {
+        apple_color = parse_color(&p.value);
+        &mut event
+      }
+      "RRULE" => {
+        rule = Some(p.value.clone());
+        &mut event
+      }
+      "RDATE" => {
+        let times = parse_times(p)?;
+        recurrence
+          .rdates
+          .extend(times.into_iter().map(|t| t.fixed_offset()));
+        &mut event
+      }
+      "EXDATE" => {
+        let times = parse_times(p)?;
+        recurrence
+          .exdates
+          .extend(times.into_iter().map(|t| t.fixed_offset()));
+        &mut event
+      }
+      _ => &mut event,
+    };
+  }
+
+  // DTSTAMP is the best guess for missing CREATED and LAST-MODIFIED
+  if let Some(stamp) = stamp {
+    if !created_defined {
+      event.created_at(stamp);
+    }
+    if !modified_defined {
+      event.modified_at(stamp);
+    }
+  }
+
+  event.color(color.or(apple_color));
+
+  // DURATION may come before DTSTART
+  if let Some(duration) = duration {
+    let start = start.ok_or_else(|| anyhow!("DURATION without DTSTART"))?;
+    let end = duration
+      .after(start.fixed_offset(), &zone)
+      .ok_or_else(|| anyhow!("DURATION out of range"))?;
+    event.end(end);
+  }
+
+  event.all_day(all_day);
+  if let (true, false, Some(start)) = (all_day, end_defined, start) {
+    // an all-day event without an end lasts for the day
+    event.end(start + Duration::days(1));
+  }
+
+  if let Some(rule) = rule {
+    // show at least the first occurrence for rules we can't understand
+    match rule.parse() {
+      Ok(rule) => recurrence.rule = Some(rule),
+      Err(e) => log::warn!("Ignoring RRULE {rule}: {e}"),
+    }
+  }
+
+  if recurrence.rule.is_some() || !recurrence.rdates.is_empty() {
+    event.recurrence(Some(recurrence));
+  }
+
+  event.raw(Some(Arc::new(RawEvent {
+    calendar,
+    event: vevent,
+  })));
+
+  Ok(event.build()?)
 }
 
-fn parse_duration(s: &str) -> Result<Duration> {
-  let reg = regex::Regex::new(r"PT((?P<h>\d+)H)?((?P<m>\d+)M)?")?;
-  let cap = reg
-    .captures(s)
-    .ok_or_else(|| anyhow!("Invalid duration parsed {}", s))?;
+fn to_timestamp<Tz: chrono::TimeZone>(time: DateTime<Tz>) -> String {
+  time.naive_utc().format("%Y%m%dT%H%M%SZ").to_string()
+}
 
-  let mut dur = Duration::zero();
-  if let Some(m) = cap.name("h") {
-    let hours = m.as_str().parse::<i64>()?;
-    dur += Duration::hours(hours);
+fn to_datestamp<Tz: chrono::TimeZone>(time: DateTime<Tz>) -> String {
+  time.naive_utc().format("%Y%m%d").to_string()
+}
+
+// the wall clock time in the timezone, without the TZID
+fn to_local_timestamp<Tz: chrono::TimeZone>(
+  time: DateTime<Tz>,
+  zone: &Zone,
+) -> String {
+  let time = time.fixed_offset();
+  zone.to_local(&time).format("%Y%m%dT%H%M%S").to_string()
+}
+
+fn is_date(p: &Property) -> bool {
+  p.param("VALUE") == Some("DATE") || !p.value.contains('T')
+}
+
+// TZIDs known to the tz database are preferred over the definitions
+// in the calendar
+fn property_zone(p: &Property, zones: &[Zone]) -> Result<Option<Zone>> {
+  let Some(tzid) = p.param("TZID") else {
+    return Ok(None);
+  };
+
+  Zone::from_tzid(tzid)
+    .or_else(|| zones.iter().find(|z| z.tzid() == tzid).cloned())
+    .map(Some)
+    .ok_or_else(|| anyhow!("unknown TZID {}", tzid))
+}
+
+// local times without a timezone are floating, they are interpreted
+// in the local timezone
+fn from_timestamp(s: &str, zone: Option<&Zone>) -> Result<DateTime<Utc>> {
+  if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ") {
+    return Ok(t.and_utc());
   }
-  if let Some(m) = cap.name("m") {
-    let mins = m.as_str().parse::<i64>()?;
-    dur += Duration::minutes(mins);
+
+  // DATE values of all-day events
+  if let Ok(d) = NaiveDate::parse_from_str(s, "%Y%m%d") {
+    return Ok(all_day_time(d).into());
   }
 
-  Ok(dur)
+  if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S") {
+    let zone = zone.cloned().unwrap_or_else(Zone::local);
+    return Ok(zone.localize(&t).into());
+  }
+
+  bail!("failed to parse timestamp {}", s)
 }
@@ -0,0 +1,421 @@
+use std::fmt;
+
+use anyhow::{bail, ensure};
+
+use crate::util::{anyhow, Result};
+
+// A minimal model of the iCalendar format (RFC 5545 section 3.1 & 3.4)
+// which remembers the original content lines, so that anything
+// malakal doesn't understand is written back untouched.
+
+// content lines longer than this (in octets) are folded
+const MAX_LINE_LENGTH: usize = 75;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Component {
+  pub name: String,
+  pub properties: Vec<Property>,
+  pub components: Vec<Component>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Property {
+  pub name: String,
+  pub params: Vec<(String, Vec<String>)>,
+  pub value: String,
+  // the content line as it was read, still folded
+  raw: Option<String>,
+}
+
+impl Component {
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      properties: vec![],
+      components: vec![],
+    }
+  }
+
+  pub fn property(&self, name: &str) -> Option<&Property> {
+    self.properties.iter().find(|p| p.name == name)
+  }
+
+  // replace the first property with the same name in place, or append
+  // it if there is none
+  pub fn set(&mut self, property: Property) {
+    match self.properties.iter().position(|p| p.name == property.name) {
+      Some(i) => {
+        // drop the duplicates following the replaced one
+        let mut rest = self.properties.split_off(i + 1);
+        rest.retain(|p| p.name != property.name);
+        self.properties[i] = property;
+        self.properties.append(&mut rest);
+      }
+      None => self.properties.push(property),
+    }
+  }
+
+  pub fn remove(&mut self, name: &str) {
+    self.properties.retain(|p| p.name != name);
+  }
+}
+
+impl fmt::Display for Component {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "BEGIN:{}\r\n", self.name)?;
+    for property in self.properties.iter() {
+      write!(f, "{property}")?;
+    }
+    for component in self.components.iter() {
+      write!(f, "{component}")?;
+    }
+    write!(f, "END:{}\r\n", self.name)
+  }
+}
+
+impl Property {
+  pub fn new(name: &str, value: impl Into<String>) -> Self {
+    Self {
+      name: name.to_string(),
+      params: vec![],
+      value: value.into(),
+      raw: None,
+    }
+  }
+
+  pub fn with_param(mut self, name: &str, value: &str) -> Self {
+    self
+      .params
+      .push((name.to_string(), vec![value.to_string()]));
+    self
+  }
+
+  pub fn param(&self, name: &str) -> Option<&str> {
+    self
+      .params
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .and_then(|(_, values)| values.first())
+      .map(|v| v.as_str())
+  }
+
+  fn unfolded(&self) -> String {
+    let mut line = self.name.clone();
+    for (name, values) in self.params.iter() {
+      let values: Vec<_> = values.iter().map(|v| quote_param(v)).collect();
+      line.push_str(&format!(";{}={}", name, values.join(",")));
+    }
+    line.push(':');
+    line.push_str(&self.value);
+    line
+  }
+}
+
+impl fmt::Display for Property {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.raw {
+      Some(raw) => write!(f, "{raw}\r\n"),
+      None => write!(f, "{}", fold(&self.unfolded())),
+    }
+  }
+}
+
+// parse the top-level components (usually a single VCALENDAR)
+pub fn parse(content: &str) -> Result<Vec<Component>> {
+  let mut components = vec![];
+  let mut stack: Vec<Component> = vec![];
+
+  for (raw, line) in unfold(content) {
+    let property = parse_property(&line, raw)?;
+
+    match property.name.as_str() {
+      "BEGIN" => stack.push(Component::new(&property.value.to_uppercase())),
+      "END" => {
+        let component = stack
+          .pop()
+          .ok_or_else(|| anyhow!("unexpected END:{}", property.value))?;
+        ensure!(
+          component.name.eq_ignore_ascii_case(&property.value),
+          "END:{} doesn't match BEGIN:{}",
+          property.value,
+          component.name
+        );
+
+        match stack.last_mut() {
+          Some(parent) => parent.components.push(component),
+          None => components.push(component),
+        }
+      }
+      _ => match stack.last_mut() {
+        Some(component) => component.properties.push(property),
+        None => bail!("property {} outside of any component", property.name),
+      },
+    }
+  }
+
+  if let Some(component) = stack.pop() {
+    bail!("missing END:{}", component.name);
+  }
+
+  Ok(components)
+}
+
+// (folded line, unfolded line) pairs
+fn unfold(content: &str) -> Vec<(String, String)> {
+  let mut lines: Vec<(String, String)> = vec![];
+
+  for line in content.split('\n') {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+      (Some(continued), Some((raw, unfolded))) => {
+        raw.push_str("\r\n");
+        raw.push_str(line);
+        unfolded.push_str(continued);
+      }
+      _ if line.is_empty() => (),
+      _ => lines.push((line.to_string(), line.to_string())),
+    }
+  }
+
+  lines
+}
+
+fn parse_property(line: &str, raw: String) -> Result<Property> {
+  let invalid = || anyhow!("invalid content line: {}", line);
+
+  let name_end = line.find([';', ':']).ok_or_else(invalid)?;
+  let name = line[..name_end].to_ascii_uppercase();
+  ensure!(!name.is_empty(), invalid());
+
+  let mut params = vec![];
+  let mut rest = &line[name_end..];
+
+  while let Some(param) = rest.strip_prefix(';') {
+    let (param_name, mut values_str) =
+      param.split_once('=').ok_or_else(invalid)?;
+    let mut values = vec![];
+
+    loop {
+      let (value, remaining) = match values_str.strip_prefix('"') {
+        Some(quoted) => {
+          let end = quoted.find('"').ok_or_else(invalid)?;
+          (&quoted[..end], &quoted[end + 1..])
+        }
+        None => {
+          let end = values_str.find([',', ';', ':']).ok_or_else(invalid)?;
+          values_str.split_at(end)
+        }
+      };
+      values.push(value.to_string());
+
+      match remaining.strip_prefix(',') {
+        Some(remaining) => values_str = remaining,
+        None => {
+          rest = remaining;
+          break;
+        }
+      }
+    }
+
+    params.push((param_name.to_ascii_uppercase(), values));
+  }
+
+  let value = rest.strip_prefix(':').ok_or_else(invalid)?;
+
+  Ok(Property {
+    name,
+    params,
+    value: value.to_string(),
+    raw: Some(raw),
+  })
+}
+
+fn quote_param(value: &str) -> String {
+  if value.contains([':', ';', ',']) {
+    format!("\"{value}\"")
+  } else {
+    value.to_string()
+  }
+}
+
+fn fold(line: &str) -> String {
+  let mut folded = String::new();
+  let mut line_len = 0;
+
+  for c in line.chars() {
+    if line_len + c.len_utf8() > MAX_LINE_LENGTH {
+      folded.push_str("\r\n ");
+      line_len = 1;
+    }
+    folded.push(c);
+    line_len += c.len_utf8();
+  }
+
+  folded.push_str("\r\n");
+  folded
+}
+
+// TEXT values (RFC 5545 section 3.3.11)
+pub fn escape_text(s: &str) -> String {
+  let mut escaped = String::new();
+  for c in s.chars() {
+    match c {
+      '\\' | ';' | ',' => {
+        escaped.push('\\');
+        escaped.push(c);
+      }
+      '\n' => escaped.push_str("\\n"),
+      '\r' => (),
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+pub fn unescape_text(s: &str) -> String {
+  let mut unescaped = String::new();
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+
+    match chars.next() {
+      Some('n' | 'N') => unescaped.push('\n'),
+      Some(c) => unescaped.push(c),
+      None => unescaped.push('\\'),
+    }
+  }
+  unescaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const EVENT: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Other App//EN\r
+BEGIN:VEVENT\r
+UID:lunch\r
+SUMMARY:Lunch\r
+DESCRIPTION:a description long enough to be folded by the program tha\r
+ t wrote it\r
+ATTENDEE;CN=\"Doe, Jane\";ROLE=REQ-PARTICIPANT:mailto:jane@example.com\r
+X-UNKNOWN;X-PARAM=a,b:value\r
+BEGIN:VALARM\r
+ACTION:DISPLAY\r
+TRIGGER:-PT15M\r
+END:VALARM\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+  #[test]
+  fn content_is_written_back_untouched() {
+    let components = parse(EVENT).unwrap();
+    assert_eq!(components.len(), 1);
+    assert_eq!(components[0].to_string(), EVENT);
+
+    // bare newlines are read as well, and written with CRLF
+    let components = parse(&EVENT.replace('\r', "")).unwrap();
+    assert_eq!(components[0].to_string(), EVENT);
+  }
+
+  #[test]
+  fn properties_are_unfolded_and_parsed() {
+    let calendar = &parse(EVENT).unwrap()[0];
+    let event = &calendar.components[0];
+    assert_eq!(event.name, "VEVENT");
+    assert_eq!(event.components[0].name, "VALARM");
+
+    let description = event.property("DESCRIPTION").unwrap();
+    assert_eq!(
+      description.value,
+      "a description long enough to be folded by the program that wrote it"
+    );
+
+    let attendee = event.property("ATTENDEE").unwrap();
+    assert_eq!(attendee.param("cn"), Some("Doe, Jane"));
+    assert_eq!(attendee.value, "mailto:jane@example.com");
+
+    let unknown = event.property("X-UNKNOWN").unwrap();
+    assert_eq!(
+      unknown.params,
+      [(
+        "X-PARAM".to_string(),
+        vec!["a".to_string(), "b".to_string()]
+      )]
+    );
+  }
+
+  #[test]
+  fn only_patched_properties_are_rewritten() {
+    let mut calendar = parse(EVENT).unwrap().remove(0);
+    let event = &mut calendar.components[0];
+    event.set(Property::new("SUMMARY", "Late lunch"));
+    event.set(Property::new("LOCATION", "Canteen").with_param("ALTREP", "a:b"));
+    event.remove("DESCRIPTION");
+
+    let expected = EVENT
+      .replace("SUMMARY:Lunch", "SUMMARY:Late lunch")
+      .replace(
+        "DESCRIPTION:a description long enough to be folded by the program \
+         tha\r\n t wrote it\r\n",
+        "",
+      )
+      .replace(
+        "X-UNKNOWN;X-PARAM=a,b:value\r\n",
+        "X-UNKNOWN;X-PARAM=a,b:value\r\nLOCATION;ALTREP=\"a:b\":Canteen\r\n",
+      );
+    assert_eq!(calendar.to_string(), expected);
+  }
+
+  #[test]
+  fn duplicates_are_dropped_when_set() {
+    let mut component = Component::new("VEVENT");
+    component.properties.push(Property::new("CATEGORIES", "a"));
+    component.properties.push(Property::new("UID", "x"));
+    component.properties.push(Property::new("CATEGORIES", "b"));
+    component.set(Property::new("CATEGORIES", "c"));
+
+    let properties: Vec<_> = component
+      .properties
+      .iter()
+      .map(|p| (p.name.as_str(), p.value.as_str()))
+      .collect();
+    assert_eq!(properties, [("CATEGORIES", "c"), ("UID", "x")]);
+  }
+
+  #[test]
+  fn long_lines_are_folded() {
+    // folded between characters, not within one
+    let value = "é".repeat(50);
+    let written = Property::new("SUMMARY", value.as_str()).to_string();
+    assert!(written
+      .split("\r\n")
+      .all(|line| line.len() <= MAX_LINE_LENGTH));
+
+    let content = format!("BEGIN:VEVENT\r\n{written}END:VEVENT\r\n");
+    let component = &parse(&content).unwrap()[0];
+    assert_eq!(component.property("SUMMARY").unwrap().value, value);
+  }
+
+  #[test]
+  fn text_escaping() {
+    let text = "a, b; c\\d\nnext line";
+    assert_eq!(escape_text(text), r"a\, b\; c\\d\nnext line");
+    assert_eq!(unescape_text(&escape_text(text)), text);
+    assert_eq!(unescape_text(r"line\Nbreak"), "line\nbreak");
+  }
+
+  #[test]
+  fn invalid_content_is_rejected() {
+    assert!(parse("BEGIN:VEVENT\r\nUID:x\r\n").is_err());
+    assert!(parse("BEGIN:VEVENT\r\nEND:VTODO\r\n").is_err());
+    assert!(parse("UID:x\r\n").is_err());
+    assert!(parse("BEGIN:VEVENT\r\nno colon\r\nEND:VEVENT\r\n").is_err());
+  }
+}
@@ -0,0 +1,269 @@
+use anyhow::ensure;
+use chrono::Duration;
+
+use crate::timezone::Zone;
+use crate::util::{anyhow, DateTime, Result};
+
+// DURATION values (RFC 5545 section 3.3.6), e.g. P1W, P1DT2H, -PT15M
+
+const SECS_PER_MINUTE: i64 = 60;
+const SECS_PER_HOUR: i64 = 60 * SECS_PER_MINUTE;
+const DAYS_PER_WEEK: i64 = 7;
+
+// Weeks and days are nominal: a day moves the wall clock time to the
+// next date, and lasts 23 or 25 hours across a DST transition. Hours,
+// minutes and seconds are exact. Both have the sign of the duration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NominalDuration {
+  days: i64,
+  exact: Duration,
+}
+
+impl NominalDuration {
+  // the time the duration after start, with days counted in the zone
+  pub fn after(&self, start: DateTime, zone: &Zone) -> Option<DateTime> {
+    let local = zone
+      .to_local(&start)
+      .checked_add_signed(Duration::try_days(self.days)?)?;
+    zone.localize(&local).checked_add_signed(self.exact)
+  }
+
+  // the duration from start to end, in whole days on the wall clock
+  // where possible
+  pub fn between(start: DateTime, end: DateTime, zone: &Zone) -> Self {
+    let exact = Self {
+      days: 0,
+      exact: end - start,
+    };
+
+    let days = (zone.to_local(&end) - zone.to_local(&start)).num_days();
+    let nominal = Self {
+      days,
+      exact: Duration::zero(),
+    };
+    let Some(rest) = nominal.after(start, zone).map(|t| end - t) else {
+      return exact;
+    };
+
+    // the exact part can't go the other way
+    if days != 0
+      && rest != Duration::zero()
+      && (days < 0) != (rest < Duration::zero())
+    {
+      return exact;
+    }
+    Self { days, exact: rest }
+  }
+
+  fn is_negative(&self) -> bool {
+    self.days < 0 || self.exact < Duration::zero()
+  }
+}
+
+// days and weeks can't be combined: dur-date is days optionally
+// followed by time, dur-week is weeks alone
+pub fn parse_duration(s: &str) -> Result<NominalDuration> {
+  let invalid = || anyhow!("invalid duration {}", s);
+
+  let (negative, rest) = match s.trim().as_bytes().first() {
+    Some(b'-') => (true, &s.trim()[1..]),
+    Some(b'+') => (false, &s.trim()[1..]),
+    _ => (false, s.trim()),
+  };
+  let rest = rest.strip_prefix(['P', 'p']).ok_or_else(invalid)?;
+  let (date, time) = match rest.split_once(['T', 't']) {
+    Some((date, time)) => {
+      ensure!(!time.is_empty(), invalid());
+      (date, Some(time))
+    }
+    None => (rest, None),
+  };
+  ensure!(!date.is_empty() || time.is_some(), invalid());
+
+  let date = components(date).ok_or_else(invalid)?;
+  let time = match time {
+    Some(time) => components(time).ok_or_else(invalid)?,
+    None => vec![],
+  };
+  let weeks = matches!(date.as_slice(), [(_, 'W')]);
+  ensure!(
+    (weeks && time.is_empty())
+      || (in_order(&date, "D") && in_order(&time, "HMS")),
+    invalid()
+  );
+
+  let out_of_range = || anyhow!("duration out of range {}", s);
+  let mut days: i64 = 0;
+  let mut seconds: i64 = 0;
+  for (value, designator) in date.into_iter().chain(time) {
+    let (total, unit) = match designator {
+      'W' => (&mut days, DAYS_PER_WEEK),
+      'D' => (&mut days, 1),
+      'H' => (&mut seconds, SECS_PER_HOUR),
+      'M' => (&mut seconds, SECS_PER_MINUTE),
+      _ => (&mut seconds, 1),
+    };
+    *total = value
+      .checked_mul(unit)
+      .and_then(|v| v.checked_add(*total))
+      .ok_or_else(out_of_range)?;
+  }
+
+  let sign = if negative { -1 } else { 1 };
+  Ok(NominalDuration {
+    days: sign * days,
+    exact: Duration::try_seconds(sign * seconds).ok_or_else(out_of_range)?,
+  })
+}
+
+pub fn format_duration(duration: NominalDuration) -> String {
+  let sign = if duration.is_negative() { "-" } else { "" };
+  let days = duration.days.abs();
+  let seconds = duration.exact.num_seconds().abs();
+
+  if days > 0 && days % DAYS_PER_WEEK == 0 && seconds == 0 {
+    return format!("{sign}P{}W", days / DAYS_PER_WEEK);
+  }
+
+  let hours = seconds / SECS_PER_HOUR;
+  let minutes = seconds % SECS_PER_HOUR / SECS_PER_MINUTE;
+  let secs = seconds % SECS_PER_MINUTE;
+
+  let mut s = format!("{sign}P");
+  if days > 0 {
+    s.push_str(&format!("{days}D"));
+  }
+  if seconds > 0 || days == 0 {
+    s.push('T');
+  }
+  if hours > 0 {
+    s.push_str(&format!("{hours}H"));
+  }
+  if minutes > 0 {
+    s.push_str(&format!("{minutes}M"));
+  }
+  if secs > 0 || (seconds == 0 && days == 0) {
+    s.push_str(&format!("{secs}S"));
+  }
+
+  s
+}
+
+// "1D2H" => [(1, 'D'), (2, 'H')], None for malformed input
+fn components(s: &str) -> Option<Vec<(i64, char)>> {
+  let mut components = vec![];
+  let mut digits = String::new();
+
+  for c in s.chars() {
+    if c.is_ascii_digit() {
+      digits.push(c);
+      continue;
+    }
+
+    if digits.is_empty() {
+      return None;
+    }
+    components.push((digits.parse().ok()?, c.to_ascii_uppercase()));
+    digits.clear();
+  }
+
+  digits.is_empty().then_some(components)
+}
+
+// each designator appears at most once, in the given order
+fn in_order(components: &[(i64, char)], order: &str) -> bool {
+  let mut remaining = order.chars();
+  components.iter().all(|(_, d)| remaining.any(|o| o == *d))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn time(s: &str) -> DateTime {
+    chrono::DateTime::parse_from_rfc3339(s).unwrap()
+  }
+
+  fn berlin() -> Zone {
+    Zone::from_tzid("Europe/Berlin").unwrap()
+  }
+
+  fn duration(days: i64, seconds: i64) -> NominalDuration {
+    NominalDuration {
+      days,
+      exact: Duration::seconds(seconds),
+    }
+  }
+
+  #[test]
+  fn all_forms_are_read() {
+    let parsed = |s| parse_duration(s).unwrap();
+    assert_eq!(parsed("P2W"), duration(14, 0));
+    assert_eq!(parsed("P1D"), duration(1, 0));
+    assert_eq!(parsed("P1DT2H"), duration(1, 2 * 3600));
+    assert_eq!(parsed("PT1H30M15S"), duration(0, 5415));
+    assert_eq!(parsed("+PT45M"), duration(0, 45 * 60));
+    assert_eq!(parsed("-P1DT15M"), duration(-1, -15 * 60));
+    assert_eq!(parsed("pt5m"), duration(0, 300));
+  }
+
+  #[test]
+  fn invalid_forms_are_rejected() {
+    for s in [
+      "", "P", "PT", "1D", "P1", "P1H", "PT1D", "P1W2D", "P1WT1H", "P1D1W",
+      "PT1M1H", "PT1H1H", "P-1D", "PxD",
+    ] {
+      assert!(parse_duration(s).is_err(), "{s} was accepted");
+    }
+    assert!(parse_duration("P99999999999999999999D").is_err());
+    assert!(parse_duration("PT9999999999999999S").is_err());
+  }
+
+  #[test]
+  fn durations_are_written_back_as_read() {
+    for s in [
+      "P2W",
+      "P1D",
+      "P1DT2H",
+      "PT1H30M15S",
+      "-PT15M",
+      "PT0S",
+      "P8D",
+    ] {
+      assert_eq!(format_duration(parse_duration(s).unwrap()), s);
+    }
+  }
+
+  #[test]
+  fn days_are_nominal() {
+    let zone = berlin();
+    // the clocks go forward on 2024-03-31
+    let start = time("2024-03-30T10:00:00+01:00");
+
+    let end = parse_duration("P1D").unwrap().after(start, &zone);
+    assert_eq!(end, Some(time("2024-03-31T10:00:00+02:00")));
+    let end = parse_duration("PT24H").unwrap().after(start, &zone);
+    assert_eq!(end, Some(time("2024-03-31T11:00:00+02:00")));
+    let end = parse_duration("P1DT1H").unwrap().after(start, &zone);
+    assert_eq!(end, Some(time("2024-03-31T11:00:00+02:00")));
+
+    // exact days elsewhere
+    let end = parse_duration("P1D").unwrap().after(start, &Zone::utc());
+    assert_eq!(end, Some(time("2024-03-31T11:00:00+02:00")));
+  }
+
+  #[test]
+  fn durations_between_times() {
+    let zone = berlin();
+    let start = time("2024-03-30T10:00:00+01:00");
+    let between =
+      |end| format_duration(NominalDuration::between(start, time(end), &zone));
+
+    assert_eq!(between("2024-03-31T10:00:00+02:00"), "P1D");
+    assert_eq!(between("2024-04-06T10:00:00+02:00"), "P1W");
+    assert_eq!(between("2024-03-31T11:30:00+02:00"), "P1DT1H30M");
+    assert_eq!(between("2024-03-30T11:00:00+01:00"), "PT1H");
+    // less than a day on the wall clock
+    assert_eq!(between("2024-03-31T09:00:00+02:00"), "PT22H");
+  }
+}
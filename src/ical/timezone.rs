@@ -0,0 +1,322 @@
+use std::sync::Arc;
+
+use anyhow::ensure;
+use chrono::{
+  Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone,
+};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+
+use crate::recurrence::{Frequency, RRule, WeekdayNum};
+use crate::timezone::{CustomZone, Observance, Zone};
+use crate::util::{anyhow, end_of_month, Result};
+
+use super::component::{Component, Property};
+
+// VTIMEZONE components (RFC 5545 section 3.6.5), generated from the
+// tz database so that other clients can read TZID times written by
+// malakal, and parsed for the TZIDs the tz database doesn't know
+
+const DTSTART_FORMAT: &str = "%Y%m%dT%H%M%S";
+
+// the years checked when inferring a yearly rule from the transitions
+const RULE_CHECK_YEARS: i32 = 2;
+
+// Describe the timezone with the transitions happening in the given
+// year. Transitions following a yearly rule (e.g. the last Sunday of
+// March) are written as recurring from 1970 on.
+pub fn generate_vtimezone(tz: Tz, year: i32) -> Component {
+  let mut vtimezone = Component::new("VTIMEZONE");
+  vtimezone.set(Property::new("TZID", tz.name()));
+
+  let transitions = transitions(tz, year);
+  if transitions.is_empty() {
+    let offset = tz.offset_from_utc_datetime(&start_of_year(year));
+    let mut standard = Component::new("STANDARD");
+    standard.set(Property::new("DTSTART", "19700101T000000"));
+    standard.set(Property::new("TZOFFSETFROM", format_offset(offset.fix())));
+    standard.set(Property::new("TZOFFSETTO", format_offset(offset.fix())));
+    standard.set(Property::new("TZNAME", tzname(&offset)));
+    vtimezone.components.push(standard);
+    return vtimezone;
+  }
+
+  for transition in transitions.iter() {
+    vtimezone.components.push(observance(tz, transition, year));
+  }
+
+  vtimezone
+}
+
+// the timezones defined in the calendar
+pub fn embedded_zones(calendar: &Component) -> Vec<Zone> {
+  calendar
+    .components
+    .iter()
+    .filter(|c| c.name == "VTIMEZONE")
+    .filter_map(|c| match parse_vtimezone(c) {
+      Ok(zone) => Some(Zone::Custom(Arc::new(zone))),
+      Err(e) => {
+        log::warn!("Ignoring invalid VTIMEZONE: {e}");
+        None
+      }
+    })
+    .collect()
+}
+
+// The VTIMEZONE a custom zone was parsed from, minus the properties
+// malakal doesn't use
+pub fn custom_vtimezone(zone: &CustomZone) -> Component {
+  let mut vtimezone = Component::new("VTIMEZONE");
+  vtimezone.set(Property::new("TZID", zone.tzid.as_str()));
+
+  for o in zone.observances.iter() {
+    let is_dst =
+      o.offset_to.local_minus_utc() > o.offset_from.local_minus_utc();
+    let mut observance =
+      Component::new(if is_dst { "DAYLIGHT" } else { "STANDARD" });
+    let format_local = |t: &NaiveDateTime| t.format(DTSTART_FORMAT).to_string();
+
+    observance.set(Property::new("DTSTART", format_local(&o.start)));
+    observance.set(Property::new("TZOFFSETFROM", format_offset(o.offset_from)));
+    observance.set(Property::new("TZOFFSETTO", format_offset(o.offset_to)));
+    if let Some(rule) = &o.rule {
+      observance.set(Property::new("RRULE", rule.to_string()));
+    }
+    if !o.rdates.is_empty() {
+      let rdates: Vec<_> = o.rdates.iter().map(format_local).collect();
+      observance.set(Property::new("RDATE", rdates.join(",")));
+    }
+    vtimezone.components.push(observance);
+  }
+
+  vtimezone
+}
+
+pub fn parse_vtimezone(vtimezone: &Component) -> Result<CustomZone> {
+  let tzid = vtimezone
+    .property("TZID")
+    .ok_or_else(|| anyhow!("TZID not defined"))?
+    .value
+    .clone();
+
+  let observances = vtimezone
+    .components
+    .iter()
+    .filter(|c| c.name == "STANDARD" || c.name == "DAYLIGHT")
+    .map(parse_observance)
+    .collect::<Result<Vec<_>>>()?;
+  ensure!(!observances.is_empty(), "{} has no observances", tzid);
+
+  Ok(CustomZone { tzid, observances })
+}
+
+fn parse_observance(observance: &Component) -> Result<Observance> {
+  let property = |name: &str| {
+    observance
+      .property(name)
+      .ok_or_else(|| anyhow!("{} not defined in {}", name, observance.name))
+  };
+  let parse_local = |s: &str| {
+    NaiveDateTime::parse_from_str(s, DTSTART_FORMAT)
+      .map_err(|_| anyhow!("invalid local time {}", s))
+  };
+
+  let mut rdates = vec![];
+  for p in observance.properties.iter().filter(|p| p.name == "RDATE") {
+    for s in p.value.split(',') {
+      rdates.push(parse_local(s)?);
+    }
+  }
+
+  Ok(Observance {
+    start: parse_local(&property("DTSTART")?.value)?,
+    offset_from: parse_offset(&property("TZOFFSETFROM")?.value)?,
+    offset_to: parse_offset(&property("TZOFFSETTO")?.value)?,
+    rule: observance
+      .property("RRULE")
+      .map(|p| p.value.parse())
+      .transpose()?,
+    rdates,
+  })
+}
+
+struct Transition {
+  // the first instant with the new offset, in UTC
+  at: NaiveDateTime,
+  from: <Tz as TimeZone>::Offset,
+  to: <Tz as TimeZone>::Offset,
+}
+
+impl Transition {
+  // the wall clock time the transition happens at, before the change
+  fn local(&self) -> NaiveDateTime {
+    self.at + self.from.fix()
+  }
+}
+
+fn observance(tz: Tz, transition: &Transition, year: i32) -> Component {
+  let is_dst = transition.to.dst_offset() != Duration::zero();
+  let mut observance =
+    Component::new(if is_dst { "DAYLIGHT" } else { "STANDARD" });
+
+  let local = transition.local();
+  let rule = yearly_rule(tz, transition, year);
+  let dtstart = match &rule {
+    Some(rule) => nth_weekday(1970, rule.by_month[0], rule.by_day[0])
+      .map(|date| date.and_time(local.time()))
+      .unwrap_or(local),
+    None => local,
+  };
+
+  observance.set(Property::new(
+    "DTSTART",
+    dtstart.format(DTSTART_FORMAT).to_string(),
+  ));
+  observance.set(Property::new(
+    "TZOFFSETFROM",
+    format_offset(transition.from.fix()),
+  ));
+  observance.set(Property::new(
+    "TZOFFSETTO",
+    format_offset(transition.to.fix()),
+  ));
+  observance.set(Property::new("TZNAME", tzname(&transition.to)));
+  if let Some(rule) = rule {
+    observance.set(Property::new("RRULE", rule.to_string()));
+  }
+
+  observance
+}
+
+// the offset changes in the year, found by probing daily and
+// narrowing down to the second
+fn transitions(tz: Tz, year: i32) -> Vec<Transition> {
+  let offset_at = |t: &NaiveDateTime| tz.offset_from_utc_datetime(t);
+
+  let end = start_of_year(year + 1);
+  let mut t = start_of_year(year);
+  let mut transitions = vec![];
+
+  while t < end {
+    let next = (t + Duration::days(1)).min(end);
+    if offset_at(&t).fix() != offset_at(&next).fix() {
+      let (mut before, mut after) = (t, next);
+      while after - before > Duration::seconds(1) {
+        let middle = before + (after - before) / 2;
+        if offset_at(&middle).fix() == offset_at(&before).fix() {
+          before = middle;
+        } else {
+          after = middle;
+        }
+      }
+
+      transitions.push(Transition {
+        at: after,
+        from: offset_at(&before),
+        to: offset_at(&after),
+      });
+    }
+    t = next;
+  }
+
+  transitions
+}
+
+// A rule like FREQ=YEARLY;BYMONTH=3;BYDAY=-1SU matching the
+// transition in the following years as well
+fn yearly_rule(tz: Tz, transition: &Transition, year: i32) -> Option<RRule> {
+  let local = transition.local();
+  let date = local.date();
+  let weekday = date.weekday();
+
+  let nth = (date.day() as i32 - 1) / 7 + 1;
+  let is_last = date + Duration::days(7) > end_of_month(date);
+  let candidates = if is_last { vec![-1, nth] } else { vec![nth] };
+
+  let matches = |by_day: WeekdayNum| {
+    (year + 1..=year + RULE_CHECK_YEARS).all(|year| {
+      transitions(tz, year).iter().any(|t| {
+        t.from.fix() == transition.from.fix()
+          && t.to.fix() == transition.to.fix()
+          && Some(t.local())
+            == nth_weekday(year, date.month(), by_day)
+              .map(|date| date.and_time(local.time()))
+      })
+    })
+  };
+
+  candidates
+    .into_iter()
+    .map(|nth| WeekdayNum {
+      nth: Some(nth),
+      weekday,
+    })
+    .find(|&by_day| matches(by_day))
+    .map(|by_day| {
+      let mut rule = RRule::new(Frequency::Yearly);
+      rule.by_month = vec![date.month()];
+      rule.by_day = vec![by_day];
+      rule
+    })
+}
+
+// e.g. the last (-1) Sunday of March
+fn nth_weekday(year: i32, month: u32, by_day: WeekdayNum) -> Option<NaiveDate> {
+  let nth = by_day.nth?;
+  if nth > 0 {
+    return NaiveDate::from_weekday_of_month_opt(
+      year,
+      month,
+      by_day.weekday,
+      nth as u8,
+    );
+  }
+
+  let last = end_of_month(NaiveDate::from_ymd_opt(year, month, 1)?);
+  let days_back = (last.weekday().num_days_from_monday() + 7
+    - by_day.weekday.num_days_from_monday())
+    % 7;
+  Some(last - Duration::days(days_back as i64 + (-nth as i64 - 1) * 7))
+}
+
+fn start_of_year(year: i32) -> NaiveDateTime {
+  NaiveDate::from_ymd_opt(year, 1, 1)
+    .and_then(|date| date.and_hms_opt(0, 0, 0))
+    .expect("date overflow")
+}
+
+fn tzname(offset: &<Tz as TimeZone>::Offset) -> String {
+  offset.abbreviation().to_string()
+}
+
+// UTC-OFFSET values, e.g. +0530
+fn parse_offset(s: &str) -> Result<FixedOffset> {
+  let invalid = || anyhow!("invalid UTC offset {}", s);
+
+  let (sign, digits) = match s.trim().split_at_checked(1) {
+    Some(("+", digits)) => (1, digits),
+    Some(("-", digits)) => (-1, digits),
+    _ => return Err(invalid()),
+  };
+  ensure!(
+    matches!(digits.len(), 4 | 6) && digits.bytes().all(|b| b.is_ascii_digit()),
+    invalid()
+  );
+
+  let field =
+    |i: usize| digits.get(i..i + 2).map_or(0, |d| d.parse().unwrap_or(0));
+  let seconds: i32 = field(0) * 3600 + field(2) * 60 + field(4);
+  FixedOffset::east_opt(sign * seconds).ok_or_else(invalid)
+}
+
+fn format_offset(offset: FixedOffset) -> String {
+  let seconds = offset.local_minus_utc();
+  let sign = if seconds < 0 { '-' } else { '+' };
+  let seconds = seconds.abs();
+  let (hours, minutes) = (seconds / 3600, seconds % 3600 / 60);
+
+  match seconds % 60 {
+    0 => format!("{sign}{hours:02}{minutes:02}"),
+    secs => format!("{sign}{hours:02}{minutes:02}{secs:02}"),
+  }
+}
@@ -0,0 +1,239 @@
+use std::{fs::create_dir_all, path::Path};
+
+use rusqlite::{params, Connection};
+
+use crate::{
+  backend::Fingerprint,
+  event::{Event, EventId},
+  ical::ICal,
+  recurrence::RecurrenceScope,
+  util::Result,
+};
+
+// The changes sent to the backend but not saved yet, so that they
+// survive a crash or a power loss. Each change is written before it's
+// applied and removed once it's saved (or given up on because of a
+// conflict), what's left on startup wasn't saved.
+//
+// The events are kept as the ics they would be written as.
+pub struct Journal {
+  conn: Connection,
+}
+
+impl Journal {
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    if let Some(dir) = path.as_ref().parent() {
+      create_dir_all(dir)?;
+    }
+
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    // the point is to have the changes on disk
+    conn.pragma_update(None, "synchronous", "full")?;
+    conn.execute_batch(
+      "
+CREATE TABLE IF NOT EXISTS changes (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  event_id TEXT NOT NULL,
+  calendar TEXT NOT NULL,
+  deleted INTEGER NOT NULL,
+  scope TEXT NOT NULL,
+  content_length INTEGER,
+  content_hash INTEGER,
+  ics TEXT NOT NULL
+);
+",
+    )?;
+
+    Ok(Self { conn })
+  }
+
+  // a later change to an event replaces the one not saved yet
+  pub fn record(&mut self, events: &[Event]) -> Result<()> {
+    let tx = self.conn.transaction()?;
+    for event in events.iter() {
+      tx.execute("DELETE FROM changes WHERE event_id = ?", [&event.id])?;
+      tx.execute(
+        "
+INSERT INTO changes
+  (event_id, calendar, deleted, scope, content_length, content_hash, ics)
+VALUES (?, ?, ?, ?, ?, ?, ?)
+",
+        params![
+          event.id,
+          event.calendar,
+          event.deleted,
+          scope_name(event.recurrence_scope),
+          event.fingerprint.map(|f| f.size as i64),
+          event.fingerprint.map(|f| f.hash as i64),
+          ICal.generate(event)?,
+        ],
+      )?;
+    }
+    tx.commit()?;
+
+    Ok(())
+  }
+
+  pub fn remove(&self, event_id: &EventId) -> Result<()> {
+    self
+      .conn
+      .execute("DELETE FROM changes WHERE event_id = ?", [event_id])?;
+    Ok(())
+  }
+
+  // the changes not saved, in the order they were made
+  pub fn pending(&self) -> Result<Vec<Event>> {
+    let mut stmt = self.conn.prepare(
+      "
+SELECT event_id, calendar, deleted, scope, content_length, content_hash, ics
+FROM changes
+ORDER BY id
+",
+    )?;
+    let rows = stmt.query_map([], |row| {
+      let size: Option<i64> = row.get(4)?;
+      let hash: Option<i64> = row.get(5)?;
+      let fingerprint = size.zip(hash).map(|(size, hash)| Fingerprint {
+        size: size as u64,
+        hash: hash as u64,
+      });
+      Ok((
+        row.get::<_, String>(0)?,
+        row.get::<_, String>(1)?,
+        row.get::<_, bool>(2)?,
+        row.get::<_, String>(3)?,
+        fingerprint,
+        row.get::<_, String>(6)?,
+      ))
+    })?;
+
+    let mut events = vec![];
+    for row in rows {
+      let (event_id, calendar, deleted, scope, fingerprint, ics) = row?;
+      let mut event = match ICal.parse_generated(&calendar, &ics) {
+        Ok(event) => event,
+        Err(e) => {
+          log::warn!("Skipping unreadable change of {event_id}: {e:?}");
+          continue;
+        }
+      };
+
+      // occurrences are read with the id of their series
+      event.id = event_id;
+      event.fingerprint = fingerprint;
+      event.deleted = deleted;
+      event.changed = !deleted;
+      event.recurrence_scope = parse_scope(&scope);
+      events.push(event);
+    }
+
+    Ok(events)
+  }
+}
+
+pub(crate) fn scope_name(scope: RecurrenceScope) -> &'static str {
+  match scope {
+    RecurrenceScope::ThisOnly => "this",
+    RecurrenceScope::ThisAndFollowing => "following",
+    RecurrenceScope::All => "all",
+  }
+}
+
+pub(crate) fn parse_scope(name: &str) -> RecurrenceScope {
+  match name {
+    "following" => RecurrenceScope::ThisAndFollowing,
+    "all" => RecurrenceScope::All,
+    _ => RecurrenceScope::ThisOnly,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::DateTime as ChronoDateTime;
+  use tempfile::TempDir;
+
+  use super::*;
+  use crate::{recurrence::expand, util::DateTime};
+
+  const CONTENT: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:test\r
+BEGIN:VEVENT\r
+UID:daily\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20240304T090000Z\r
+DTEND:20240304T100000Z\r
+SUMMARY:daily\r
+RRULE:FREQ=DAILY\r
+LOCATION:Room 1\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:lunch\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20240305T120000Z\r
+DTEND:20240305T130000Z\r
+SUMMARY:lunch\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+  fn time(s: &str) -> DateTime {
+    ChronoDateTime::parse_from_rfc3339(s).unwrap()
+  }
+
+  #[test]
+  fn pending_changes_survive_restarts() {
+    let mut events = ICal.parse("test", CONTENT).unwrap();
+    for event in events.iter_mut() {
+      event.fingerprint = Some(Fingerprint { size: 1, hash: 2 });
+    }
+    let occurrence = expand(
+      &events[0],
+      time("2024-03-05T00:00:00Z"),
+      time("2024-03-06T00:00:00Z"),
+    )
+    .remove(0);
+
+    // moving one occurrence, renaming the whole series and deleting
+    let mut moved = occurrence.clone();
+    moved.start = time("2024-03-05T15:00:00Z");
+    moved.end = time("2024-03-05T16:00:00Z");
+    moved.mark_changed();
+    let mut renamed = occurrence;
+    renamed.title = "standup".to_string();
+    renamed.recurrence_scope = RecurrenceScope::All;
+    renamed.mark_changed();
+    let mut deleted = events[1].clone();
+    deleted.mark_deleted();
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("journal.db");
+    let mut journal = Journal::open(&path).unwrap();
+    journal.record(&[moved, deleted.clone()]).unwrap();
+    // later changes replace the earlier ones
+    journal.record(&[renamed.clone()]).unwrap();
+    drop(journal);
+
+    let journal = Journal::open(&path).unwrap();
+    let pending = journal.pending().unwrap();
+    let summary = |e: &Event| {
+      (
+        e.id.clone(),
+        e.title.clone(),
+        (e.start, e.end, e.recurrence_id),
+        (e.changed, e.deleted, e.recurrence_scope),
+        (e.fingerprint, e.location()),
+      )
+    };
+    assert_eq!(
+      pending.iter().map(summary).collect::<Vec<_>>(),
+      [&deleted, &renamed].map(summary)
+    );
+
+    journal.remove(&deleted.id).unwrap();
+    let pending = journal.pending().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].location().as_deref(), Some("Room 1"));
+  }
+}
@@ -8,13 +8,20 @@ use crate::config::{Config, APP_NAME};
 
 mod app;
 mod backend;
+mod cli;
 mod config;
 mod event;
+mod history;
 mod hook;
 mod ical;
+mod journal;
 mod notifier;
+mod recurrence;
+mod search;
+mod timezone;
 mod util;
 mod widget;
+mod worker;
 
 fn main() -> anyhow::Result<()> {
   // default to log info
@@ -27,29 +34,57 @@ fn main() -> anyhow::Result<()> {
   log::info!("Config loaded {:?}", &config);
 
   let timezone = if let Some(ref tz) = config.timezone {
-    chrono_tz::Tz::from_str(tz)
-      .map_err(|x| anyhow::anyhow!("{}", x))?
-      .offset_from_utc_datetime(&Utc::now().naive_utc())
-      .fix()
+    let tz =
+      chrono_tz::Tz::from_str(tz).map_err(|x| anyhow::anyhow!("{}", x))?;
+    timezone::set_local_timezone(tz);
+    tz.offset_from_utc_datetime(&Utc::now().naive_utc()).fix()
   } else {
     util::local_tz()
   };
 
-  let local_backend = backend::LocalDirBuilder::default()
-    .calendar(&config.calendar_name)
-    .dir(&config.calendar_location)
-    .build()?;
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if let Some(("trash", args)) = args
+    .split_first()
+    .map(|(command, args)| (command.as_str(), args))
+  {
+    return cli::trash(&config, timezone, args);
+  }
 
-  let db_path = {
+  let data_dir = {
     let mut path = dirs::data_dir()
       .with_context(|| "Cannot find a directory to store data")?;
-    path.push(format!("{APP_NAME}/{APP_NAME}.db"));
+    path.push(APP_NAME);
     path
   };
 
-  let backend = backend::IndexedLocalDir::new(local_backend, db_path)?;
+  // the demo mode shows sample events and leaves the calendars alone
+  let demo = args.iter().any(|arg| arg == "--demo");
 
-  let mut app = app::App::new(&config, 3, timezone, backend)?;
+  let mut app = if demo {
+    let calendar = config.default_calendar().name;
+    let backend = backend::InMemory::demo(&calendar, timezone);
+    app::App::new(&config, timezone, backend, None, None)
+  } else {
+    let mut backend = backend::MultiCalendar::new();
+    for calendar in config.calendars() {
+      let local_backend = backend::LocalDirBuilder::default()
+        .calendar(&calendar.name)
+        .dir(&calendar.location)
+        .build()?;
+
+      // each calendar has its own index, apart from the other databases
+      let db_path = data_dir
+        .join("index")
+        .join(format!("{}.db", safe_name(&calendar.name)));
+      let indexed = backend::IndexedLocalDir::new(local_backend, db_path)?;
+      backend.add_calendar(&calendar.name, indexed);
+    }
+
+    // the changes not saved yet, next to the indexes
+    let journal = journal::Journal::open(data_dir.join("journal.db"))?;
+    let history = history::HistoryStore::open(data_dir.join("history.db"))?;
+    app::App::new(&config, timezone, backend, Some(journal), Some(history))
+  };
 
   app.load_events();
 
@@ -78,3 +113,17 @@ fn main() -> anyhow::Result<()> {
 
   Ok(())
 }
+
+// the name percent-encoded, so that different names never share a
+// file
+fn safe_name(name: &str) -> String {
+  let mut safe = String::new();
+  for b in name.bytes() {
+    if b.is_ascii_alphanumeric() || b == b'-' {
+      safe.push(b as char);
+    } else {
+      safe.push_str(&format!("%{b:02X}"));
+    }
+  }
+  safe
+}
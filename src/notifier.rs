@@ -4,16 +4,13 @@ use notify_rust::Notification;
 use sysinfo::System;
 use timer::Timer;
 
-use crate::backend::Backend;
 use crate::event::Event;
-use crate::util::{self, shared, utc_now, Result, Shared};
+use crate::util::{shared, utc_now, Result, Shared};
 use crate::Config;
 
+// Notifies of events when they start. The events are given by the
+// backend worker, the notifier never reads the calendars itself.
 pub struct Notifier {
-  // this guard is only used to bind the lifetime of the guard to a
-  // notifier, not to be read.
-  #[allow(dead_code)]
-  reschedule_guard: timer::Guard,
   context: Shared<NotifierContext>,
 }
 
@@ -23,70 +20,16 @@ struct NotifierContext {
   switch: bool,
   blacklist_processes: Vec<String>,
   notification_timeout: Duration,
-  reschedule_interval: Duration,
-  backend: Shared<dyn Backend>,
 }
 
 impl NotifierContext {
-  fn new(config: &Config, backend: &Shared<dyn Backend>) -> Result<Self> {
-    Ok(Self {
+  fn new(config: &Config) -> Self {
+    Self {
       timer: Timer::new(),
       guards: vec![],
       switch: config.notifier_switch,
       blacklist_processes: config.notifier_blacklist_processes.clone(),
-      backend: backend.clone(),
       notification_timeout: config.notification_timeout,
-      reschedule_interval: Self::reschedule_interval(),
-    })
-  }
-
-  // should be const but chrono::from_std is not declared as const
-  fn reschedule_interval() -> Duration {
-    Duration::seconds(3600 * 24)
-  }
-
-  fn start_rescheduler(shared_context: Shared<Self>) -> timer::Guard {
-    let cloned_context = shared_context.clone();
-    let context_locked = shared_context.lock().unwrap();
-    context_locked
-      .timer
-      .schedule_repeating(context_locked.reschedule_interval, move || {
-        Self::reschedule_events(cloned_context.clone())
-      })
-  }
-
-  fn reschedule_events(shared_context: Shared<Self>) {
-    let mut context = shared_context.lock().unwrap();
-    let shared_backend = context.backend.clone();
-    let mut backend = shared_backend.lock().unwrap();
-
-    let now = util::utc_now();
-    let until = now + context.reschedule_interval;
-    let events: Vec<_> = backend
-      .get_events(now, until)
-      .into_iter()
-      .flatten()
-      .collect();
-    drop(backend);
-
-    context.guards.clear();
-    for event in events {
-      if event.start < utc_now() {
-        continue;
-      }
-
-      let notify_at = event.start;
-      let shared_context = shared_context.clone();
-      let guard =
-        context.timer.schedule_with_date(
-          notify_at,
-          move || match Self::notify(shared_context.clone(), event.clone()) {
-            Ok(_) => (),
-            Err(e) => warn!("failed sending notification {e:?}"),
-          },
-        );
-
-      context.guards.push(guard);
     }
   }
 
@@ -112,19 +55,39 @@ impl NotifierContext {
 }
 
 impl Notifier {
-  pub fn start(config: &Config, backend: &Shared<dyn Backend>) -> Result<Self> {
-    let context = shared(NotifierContext::new(config, backend)?);
-    let reschedule_guard = NotifierContext::start_rescheduler(context.clone());
-    let notifier = Self {
-      reschedule_guard,
-      context,
-    };
-    notifier.events_updated();
-    Ok(notifier)
+  pub fn new(config: &Config) -> Self {
+    Self {
+      context: shared(NotifierContext::new(config)),
+    }
+  }
+
+  // how far ahead events are scheduled, they have to be scheduled
+  // again before that
+  pub fn reschedule_interval() -> Duration {
+    Duration::seconds(3600 * 24)
   }
 
-  pub fn events_updated(&self) {
-    NotifierContext::reschedule_events(self.context.clone());
+  // notify of the events, instead of the ones scheduled before
+  pub fn schedule(&self, events: Vec<Event>) {
+    let mut context = self.context.lock().unwrap();
+    context.guards.clear();
+    for event in events {
+      // all-day events don't have a meaningful start time to notify at
+      if event.all_day || event.start < utc_now() {
+        continue;
+      }
+
+      let notify_at = event.start;
+      let shared_context = self.context.clone();
+      let guard = context.timer.schedule_with_date(notify_at, move || {
+        match NotifierContext::notify(shared_context.clone(), event.clone()) {
+          Ok(_) => (),
+          Err(e) => warn!("failed sending notification {e:?}"),
+        }
+      });
+
+      context.guards.push(guard);
+    }
   }
 }
 
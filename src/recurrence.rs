@@ -0,0 +1,1237 @@
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+use anyhow::{bail, ensure};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use uuid::Uuid;
+
+use crate::{
+  event::{Event, EventId},
+  timezone::Zone,
+  util::{anyhow, end_of_month, DateTime, Result},
+};
+
+// separates the UID from the recurrence id in an occurrence's EventId
+const OCCURRENCE_ID_SEPARATOR: char = '#';
+const OCCURRENCE_ID_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+// give up on rules that keep producing nothing (e.g. BYMONTHDAY=30
+// with BYMONTH=2)
+const MAX_EMPTY_PERIODS: usize = 1000;
+
+// upper bound of occurrences enumerated for bounded rules
+const MAX_OCCURRENCES: usize = 100_000;
+
+// which occurrences of a recurring event a change applies to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecurrenceScope {
+  #[default]
+  ThisOnly,
+  ThisAndFollowing,
+  All,
+}
+
+// RRULE, RDATE and EXDATE of a recurring event, along with the
+// occurrences modified individually (RECURRENCE-ID overrides).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recurrence {
+  pub rule: Option<RRule>,
+  pub rdates: Vec<DateTime>,
+  pub exdates: Vec<DateTime>,
+  pub overrides: Vec<Event>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frequency {
+  Daily,
+  Weekly,
+  Monthly,
+  Yearly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WeekdayNum {
+  // e.g. -1 for "the last" in -1FR
+  pub nth: Option<i32>,
+  pub weekday: Weekday,
+}
+
+// A subset of RFC 5545 recurrence rules: BYHOUR, BYMINUTE, BYSECOND,
+// BYWEEKNO and BYYEARDAY are retained but not used for expansion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RRule {
+  pub freq: Frequency,
+  pub interval: u32,
+  pub count: Option<u32>,
+  pub until: Option<DateTime>,
+  pub by_day: Vec<WeekdayNum>,
+  pub by_month_day: Vec<i32>,
+  pub by_month: Vec<u32>,
+  pub by_set_pos: Vec<i32>,
+  pub week_start: Weekday,
+  unsupported: Vec<(String, String)>,
+}
+
+impl RRule {
+  pub fn new(freq: Frequency) -> Self {
+    Self {
+      freq,
+      interval: 1,
+      count: None,
+      until: None,
+      by_day: vec![],
+      by_month_day: vec![],
+      by_month: vec![],
+      by_set_pos: vec![],
+      week_start: Weekday::Mon,
+      unsupported: vec![],
+    }
+  }
+
+  fn is_bounded(&self) -> bool {
+    self.count.is_some() || self.until.is_some()
+  }
+
+  // the naive start of the last occurrence at or before the given time
+  pub(crate) fn last_before(
+    &self,
+    dtstart: NaiveDateTime,
+    time: NaiveDateTime,
+  ) -> Option<NaiveDateTime> {
+    let last = |iter: RRuleIter<'_>| {
+      iter.take(MAX_OCCURRENCES).take_while(|t| *t <= time).last()
+    };
+
+    // start close to the time, unless that skips all occurrences
+    let mut iter = self.iter(dtstart);
+    iter.fast_forward((time - Duration::days(366)).date());
+    last(iter).or_else(|| last(self.iter(dtstart)))
+  }
+
+  // naive starts of the occurrences, in order, starting from dtstart.
+  fn iter(&self, dtstart: NaiveDateTime) -> RRuleIter<'_> {
+    RRuleIter {
+      rule: self,
+      dtstart,
+      period: 0,
+      buffer: VecDeque::from([dtstart]),
+      emitted: 0,
+    }
+  }
+
+  // the naive dates in a given period (day/week/month/year)
+  fn period_dates(&self, dtstart: NaiveDate, period: i64) -> Vec<NaiveDate> {
+    let step = period * self.interval as i64;
+    let mut dates = match self.freq {
+      Frequency::Daily => {
+        let date = dtstart + Duration::days(step);
+        let matched = self.month_matches(date)
+          && self.month_day_matches(date)
+          && self.by_day.iter().all(|d| d.nth.is_none())
+          && (self.by_day.is_empty()
+            || self.by_day.iter().any(|d| d.weekday == date.weekday()));
+        if matched {
+          vec![date]
+        } else {
+          vec![]
+        }
+      }
+      Frequency::Weekly => {
+        let week_start = self.week_start_of(dtstart) + Duration::weeks(step);
+        let weekdays: Vec<Weekday> = if self.by_day.is_empty() {
+          vec![dtstart.weekday()]
+        } else {
+          self.by_day.iter().map(|d| d.weekday).collect()
+        };
+        let mut dates: Vec<NaiveDate> = weekdays
+          .into_iter()
+          .map(|wd| week_start + Duration::days(self.days_from_wkst(wd)))
+          .filter(|d| self.month_matches(*d))
+          .collect();
+        dates.sort();
+        dates.dedup();
+        dates
+      }
+      Frequency::Monthly => {
+        let (year, month) = add_months(dtstart.year(), dtstart.month(), step);
+        if self.by_month.is_empty() || self.by_month.contains(&month) {
+          self.month_dates(year, month, dtstart.day())
+        } else {
+          vec![]
+        }
+      }
+      Frequency::Yearly => {
+        let year = dtstart.year() + step as i32;
+        self.year_dates(year, dtstart)
+      }
+    };
+
+    if !self.by_set_pos.is_empty() {
+      let len = dates.len() as i32;
+      let mut selected: Vec<NaiveDate> = self
+        .by_set_pos
+        .iter()
+        .filter_map(|&pos| match pos {
+          p if p > 0 && p <= len => Some(dates[p as usize - 1]),
+          p if p < 0 && -p <= len => Some(dates[(len + p) as usize]),
+          _ => None,
+        })
+        .collect();
+      selected.sort();
+      selected.dedup();
+      dates = selected;
+    }
+
+    dates
+  }
+
+  fn year_dates(&self, year: i32, dtstart: NaiveDate) -> Vec<NaiveDate> {
+    // weekdays relative to the whole year
+    if self.by_month.is_empty()
+      && self.by_month_day.is_empty()
+      && !self.by_day.is_empty()
+    {
+      let first = NaiveDate::from_ymd_opt(year, 1, 1);
+      let last = NaiveDate::from_ymd_opt(year, 12, 31);
+      let (Some(first), Some(last)) = (first, last) else {
+        return vec![];
+      };
+      return first
+        .iter_days()
+        .take_while(|d| *d <= last)
+        .filter(|d| self.weekday_matches(*d, first, last))
+        .collect();
+    }
+
+    let months = if self.by_month.is_empty() {
+      vec![dtstart.month()]
+    } else {
+      let mut months = self.by_month.clone();
+      months.sort();
+      months
+    };
+
+    months
+      .into_iter()
+      .flat_map(|month| self.month_dates(year, month, dtstart.day()))
+      .collect()
+  }
+
+  fn month_dates(&self, year: i32, month: u32, day: u32) -> Vec<NaiveDate> {
+    let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+      return vec![];
+    };
+    let last = end_of_month(first);
+
+    let mut dates: Vec<NaiveDate> = if !self.by_month_day.is_empty() {
+      let num_days = last.day() as i32;
+      self
+        .by_month_day
+        .iter()
+        .map(|&d| if d < 0 { num_days + d + 1 } else { d })
+        .filter(|&d| d >= 1 && d <= num_days)
+        .filter_map(|d| NaiveDate::from_ymd_opt(year, month, d as u32))
+        .collect()
+    } else if !self.by_day.is_empty() {
+      first.iter_days().take_while(|d| *d <= last).collect()
+    } else {
+      NaiveDate::from_ymd_opt(year, month, day)
+        .into_iter()
+        .collect()
+    };
+
+    if !self.by_day.is_empty() {
+      dates.retain(|d| self.weekday_matches(*d, first, last));
+    }
+
+    dates.sort();
+    dates.dedup();
+    dates
+  }
+
+  // check BYDAY against a date within the first..=last scope (a
+  // month or a year)
+  fn weekday_matches(
+    &self,
+    date: NaiveDate,
+    first: NaiveDate,
+    last: NaiveDate,
+  ) -> bool {
+    self.by_day.iter().any(|d| {
+      if d.weekday != date.weekday() {
+        return false;
+      }
+
+      match d.nth {
+        None => true,
+        Some(n) if n > 0 => (date - first).num_days() / 7 + 1 == n as i64,
+        Some(n) => (last - date).num_days() / 7 + 1 == -n as i64,
+      }
+    })
+  }
+
+  fn month_matches(&self, date: NaiveDate) -> bool {
+    self.by_month.is_empty() || self.by_month.contains(&date.month())
+  }
+
+  fn month_day_matches(&self, date: NaiveDate) -> bool {
+    if self.by_month_day.is_empty() {
+      return true;
+    }
+
+    let num_days = end_of_month(date).day() as i32;
+    self.by_month_day.iter().any(|&d| {
+      let d = if d < 0 { num_days + d + 1 } else { d };
+      d == date.day() as i32
+    })
+  }
+
+  fn days_from_wkst(&self, weekday: Weekday) -> i64 {
+    let wd = weekday.num_days_from_monday() as i64;
+    let wkst = self.week_start.num_days_from_monday() as i64;
+    (7 + wd - wkst) % 7
+  }
+
+  fn week_start_of(&self, date: NaiveDate) -> NaiveDate {
+    date - Duration::days(self.days_from_wkst(date.weekday()))
+  }
+
+  // the first period that may produce dates on or after the given
+  // date. Only meaningful for rules without COUNT.
+  fn period_near(&self, dtstart: NaiveDate, date: NaiveDate) -> i64 {
+    let interval = self.interval as i64;
+    let periods = match self.freq {
+      Frequency::Daily => (date - dtstart).num_days(),
+      Frequency::Weekly => (date - self.week_start_of(dtstart))
+        .num_days()
+        .div_euclid(7),
+      Frequency::Monthly => {
+        (date.year() - dtstart.year()) as i64 * 12 + date.month() as i64
+          - dtstart.month() as i64
+      }
+      Frequency::Yearly => (date.year() - dtstart.year()) as i64,
+    };
+
+    (periods.div_euclid(interval) - 1).max(0)
+  }
+}
+
+struct RRuleIter<'a> {
+  rule: &'a RRule,
+  dtstart: NaiveDateTime,
+  period: i64,
+  buffer: VecDeque<NaiveDateTime>,
+  emitted: u32,
+}
+
+impl<'a> RRuleIter<'a> {
+  // skip the periods that end before the given date. DTSTART is
+  // dropped as well if it's before the date.
+  fn fast_forward(&mut self, date: NaiveDate) {
+    if self.rule.count.is_some() {
+      // COUNT requires enumerating every preceding occurrence
+      return;
+    }
+
+    let period = self.rule.period_near(self.dtstart.date(), date);
+    if period > self.period {
+      self.period = period;
+      self.buffer.retain(|t| t.date() >= date);
+    }
+  }
+}
+
+impl<'a> Iterator for RRuleIter<'a> {
+  type Item = NaiveDateTime;
+
+  fn next(&mut self) -> Option<NaiveDateTime> {
+    if let Some(count) = self.rule.count {
+      if self.emitted >= count {
+        return None;
+      }
+    }
+
+    let mut empty_periods = 0;
+    while self.buffer.is_empty() {
+      if empty_periods > MAX_EMPTY_PERIODS {
+        return None;
+      }
+
+      let dates = self.rule.period_dates(self.dtstart.date(), self.period);
+      self.period += 1;
+
+      let time = self.dtstart.time();
+      self.buffer.extend(
+        dates
+          .into_iter()
+          .map(|d| d.and_time(time))
+          // DTSTART is always the first occurrence
+          .filter(|t| *t > self.dtstart),
+      );
+
+      if self.buffer.is_empty() {
+        empty_periods += 1;
+      }
+    }
+
+    self.emitted += 1;
+    self.buffer.pop_front()
+  }
+}
+
+impl Recurrence {
+  // the original starts (recurrence ids) of occurrences starting
+  // within from..=to
+  fn instants(
+    &self,
+    dtstart: DateTime,
+    zone: &Zone,
+    from: Option<DateTime>,
+    to: Option<DateTime>,
+  ) -> Vec<DateTime> {
+    let after_from = |t: &DateTime| from.is_none_or(|from| *t >= from);
+    let before_to = |t: &DateTime| to.is_none_or(|to| *t <= to);
+
+    let mut instants = vec![];
+
+    match &self.rule {
+      None => instants.push(dtstart),
+      Some(rule) => {
+        let mut iter = rule.iter(zone.to_local(&dtstart));
+        if let Some(from) = from {
+          iter.fast_forward(zone.to_local(&from).date());
+        }
+
+        for t in iter.take(MAX_OCCURRENCES).map(|t| zone.localize(&t)) {
+          if rule.until.is_some_and(|until| t > until) || !before_to(&t) {
+            break;
+          }
+
+          instants.push(t);
+        }
+      }
+    }
+
+    instants.extend(self.rdates.iter().cloned());
+    instants.retain(|t| after_from(t) && before_to(t));
+    instants.retain(|t| !self.exdates.contains(t));
+    instants.sort();
+    instants.dedup();
+    instants
+  }
+
+  pub fn is_excluded(&self, recurrence_id: &DateTime) -> bool {
+    self.exdates.contains(recurrence_id)
+  }
+
+  fn find_override(&self, recurrence_id: &DateTime) -> Option<&Event> {
+    self
+      .overrides
+      .iter()
+      .find(|e| e.recurrence_id.as_ref() == Some(recurrence_id))
+  }
+
+  // add or replace the override of a single occurrence
+  pub fn set_override(&mut self, occurrence: &Event) {
+    let Some(recurrence_id) = occurrence.recurrence_id else {
+      return;
+    };
+
+    let mut overridden = occurrence.clone();
+    overridden.id = occurrence.uid().to_string();
+    overridden.recurrence = None;
+    overridden.reset_dirty_flags();
+
+    self.exdates.retain(|t| t != &recurrence_id);
+    self
+      .overrides
+      .retain(|e| e.recurrence_id != Some(recurrence_id));
+    self.overrides.push(overridden);
+  }
+
+  // exclude a single occurrence
+  pub fn exclude(&mut self, recurrence_id: DateTime) {
+    self
+      .overrides
+      .retain(|e| e.recurrence_id != Some(recurrence_id));
+    if !self.exdates.contains(&recurrence_id) {
+      self.exdates.push(recurrence_id);
+    }
+  }
+
+  // move the identities of all occurrences when the series is moved
+  fn shift(&mut self, delta: Duration) {
+    for t in self.rdates.iter_mut().chain(self.exdates.iter_mut()) {
+      *t += delta;
+    }
+    for overridden in self.overrides.iter_mut() {
+      overridden.recurrence_id = overridden.recurrence_id.map(|t| t + delta);
+    }
+  }
+
+  // keep only the occurrences identified by recurrence ids matching
+  // the predicate
+  fn retain(&mut self, f: impl Fn(&DateTime) -> bool) {
+    self.rdates.retain(&f);
+    self.exdates.retain(&f);
+    self
+      .overrides
+      .retain(|e| e.recurrence_id.as_ref().is_some_and(&f));
+  }
+
+  // the number of instances generated by the rule before the given
+  // time, including the excluded ones. Used for adjusting COUNT.
+  fn rule_instants_before(
+    &self,
+    dtstart: DateTime,
+    zone: &Zone,
+    time: DateTime,
+  ) -> u32 {
+    let Some(rule) = &self.rule else {
+      return 0;
+    };
+
+    rule
+      .iter(zone.to_local(&dtstart))
+      .take(MAX_OCCURRENCES)
+      .map(|t| zone.localize(&t))
+      .take_while(|t| *t < time)
+      .count() as u32
+  }
+}
+
+// Apply the changes made to an occurrence to the whole series. The
+// rule carried by the occurrence replaces the one of the series.
+pub fn update_all(master: &mut Event, occurrence: &Event) {
+  let Some(recurrence_id) = occurrence.recurrence_id else {
+    return;
+  };
+
+  let delta = occurrence.start - recurrence_id;
+  master.start += delta;
+  master.end = master.start + (occurrence.end - occurrence.start);
+  master.title = occurrence.title.clone();
+  master.description = occurrence.description.clone();
+  master.color = occurrence.color;
+  master.modified_at = occurrence.modified_at;
+
+  let rule = occurrence.recurrence.as_ref().and_then(|r| r.rule.clone());
+  let recurrence = master.recurrence.get_or_insert_with(Default::default);
+  recurrence.shift(delta);
+  recurrence.rule = rule;
+
+  if recurrence.rule.is_none() && recurrence.rdates.is_empty() {
+    master.recurrence = None;
+  }
+}
+
+// end the series right before the given occurrence
+pub fn truncate(master: &mut Event, recurrence_id: DateTime) {
+  let dtstart = master.start;
+  let zone = master.zone();
+  let Some(recurrence) = master.recurrence.as_mut() else {
+    return;
+  };
+
+  let count = recurrence.rule_instants_before(dtstart, &zone, recurrence_id);
+  if let Some(rule) = recurrence.rule.as_mut() {
+    if rule.count.is_some() {
+      rule.count = Some(count);
+    } else {
+      rule.until = Some(recurrence_id - Duration::seconds(1));
+    }
+  }
+
+  recurrence.retain(|t| *t < recurrence_id);
+}
+
+// Split the series at the given occurrence. The master keeps the
+// preceding occurrences, the returned event is a new series with the
+// given uid for the occurrence and the following ones.
+pub fn split(master: &mut Event, occurrence: &Event, uid: EventId) -> Event {
+  let mut following = master.clone();
+  let Some(recurrence_id) = occurrence.recurrence_id else {
+    return following;
+  };
+
+  let zone = master.zone();
+  following.id = uid.clone();
+  following.created_at = occurrence.modified_at;
+  following.end = recurrence_id + (master.end - master.start);
+  following.start = recurrence_id;
+
+  if let Some(recurrence) = following.recurrence.as_mut() {
+    let preceding =
+      recurrence.rule_instants_before(master.start, &zone, recurrence_id);
+    if let Some(count) = recurrence.rule.as_mut().and_then(|r| r.count.as_mut())
+    {
+      *count = count.saturating_sub(preceding).max(1);
+    }
+
+    recurrence.retain(|t| *t >= recurrence_id);
+    for overridden in recurrence.overrides.iter_mut() {
+      overridden.id = uid.clone();
+    }
+  }
+
+  // the occurrence is now the first one of the new series
+  let mut occurrence = occurrence.clone();
+  if let (Some(rule), Some(following)) = (
+    occurrence.recurrence.as_mut().and_then(|r| r.rule.as_mut()),
+    following.recurrence.as_ref().and_then(|r| r.rule.as_ref()),
+  ) {
+    rule.count = following.count;
+  }
+  update_all(&mut following, &occurrence);
+
+  truncate(master, recurrence_id);
+  following
+}
+
+// Apply the change of an occurrence to the occurrences of the series
+// selected by the scope. Changing the following occurrences splits
+// them off into a new series, which is returned.
+pub fn apply_change(
+  master: &mut Event,
+  occurrence: &Event,
+  scope: RecurrenceScope,
+) -> Option<Event> {
+  let recurrence_id = occurrence.recurrence_id?;
+
+  match scope {
+    RecurrenceScope::ThisOnly => master
+      .recurrence
+      .get_or_insert_with(Default::default)
+      .set_override(occurrence),
+    RecurrenceScope::All => update_all(master, occurrence),
+    RecurrenceScope::ThisAndFollowing if recurrence_id <= master.start => {
+      update_all(master, occurrence)
+    }
+    RecurrenceScope::ThisAndFollowing => {
+      let uid = Uuid::new_v4().hyphenated().to_string();
+      return Some(split(master, occurrence, uid));
+    }
+  }
+
+  None
+}
+
+// Delete the occurrences of the series selected by the scope. Returns
+// false if nothing is left of the series, which is to be deleted.
+pub fn delete_occurrences(
+  master: &mut Event,
+  recurrence_id: DateTime,
+  scope: RecurrenceScope,
+) -> bool {
+  match scope {
+    RecurrenceScope::ThisOnly => master
+      .recurrence
+      .get_or_insert_with(Default::default)
+      .exclude(recurrence_id),
+    RecurrenceScope::ThisAndFollowing if recurrence_id > master.start => {
+      truncate(master, recurrence_id)
+    }
+    RecurrenceScope::ThisAndFollowing | RecurrenceScope::All => return false,
+  }
+
+  true
+}
+
+pub fn occurrence_id(uid: &str, recurrence_id: &DateTime) -> EventId {
+  let time = recurrence_id.naive_utc().format(OCCURRENCE_ID_FORMAT);
+  format!("{uid}{OCCURRENCE_ID_SEPARATOR}{time}")
+}
+
+// split an occurrence's EventId into the UID and the recurrence id
+pub fn parse_occurrence_id(id: &str) -> Option<(&str, DateTime)> {
+  let (uid, time) = id.rsplit_once(OCCURRENCE_ID_SEPARATOR)?;
+  let time = NaiveDateTime::parse_from_str(time, OCCURRENCE_ID_FORMAT).ok()?;
+  Some((uid, time.and_utc().fixed_offset()))
+}
+
+// the occurrence of a recurring event starting at the given
+// (original) time
+pub fn occurrence(master: &Event, recurrence_id: DateTime) -> Event {
+  let recurrence = master.recurrence.as_ref();
+  let mut event = match recurrence.and_then(|r| r.find_override(&recurrence_id))
+  {
+    Some(overridden) => overridden.clone(),
+    None => {
+      let mut event = master.clone();
+      event.end = recurrence_id + (master.end - master.start);
+      event.start = recurrence_id;
+      event
+    }
+  };
+
+  // keep the rule so that the occurrence is recognizable as a part of
+  // a series, but leave the overrides out
+  event.recurrence = recurrence.map(|r| Recurrence {
+    overrides: vec![],
+    ..r.clone()
+  });
+  event.id = occurrence_id(master.uid(), &recurrence_id);
+  event.recurrence_id = Some(recurrence_id);
+  event
+}
+
+// Expand a recurring event into the occurrences that may overlap with
+// the from..to interval. The caller is responsible for filtering out
+// the occurrences that don't actually overlap.
+pub fn expand(master: &Event, from: DateTime, to: DateTime) -> Vec<Event> {
+  let Some(recurrence) = master.recurrence.as_ref() else {
+    return vec![];
+  };
+
+  let duration = master.end - master.start;
+  let mut events: Vec<Event> = recurrence
+    .instants(
+      master.start,
+      &master.zone(),
+      Some(from - duration),
+      Some(to),
+    )
+    .into_iter()
+    .filter(|t| recurrence.find_override(t).is_none())
+    .map(|t| occurrence(master, t))
+    .collect();
+
+  // overridden occurrences may have been moved anywhere
+  for overridden in recurrence.overrides.iter() {
+    let Some(recurrence_id) = overridden.recurrence_id else {
+      continue;
+    };
+
+    if !recurrence.is_excluded(&recurrence_id) {
+      events.push(occurrence(master, recurrence_id));
+    }
+  }
+
+  events.sort_by_key(|e| e.start);
+  events
+}
+
+// the end time of the last occurrence, or None if the event recurs
+// forever
+pub fn last_end(master: &Event) -> Option<DateTime> {
+  let Some(recurrence) = master.recurrence.as_ref() else {
+    return Some(master.end);
+  };
+
+  if recurrence.rule.as_ref().is_some_and(|r| !r.is_bounded()) {
+    return None;
+  }
+
+  let duration = master.end - master.start;
+  let last_instant = recurrence
+    .instants(master.start, &master.zone(), None, None)
+    .into_iter()
+    .map(|t| t + duration)
+    .max();
+  let last_override = recurrence.overrides.iter().map(|e| e.end).max();
+
+  [Some(master.end), last_instant, last_override]
+    .into_iter()
+    .flatten()
+    .max()
+}
+
+// The first occurrence overlapping with the from..to interval. Series
+// without an end are looked through a year at a time, for at most a
+// century.
+pub fn first_occurrence(
+  master: &Event,
+  from: DateTime,
+  to: Option<DateTime>,
+) -> Option<Event> {
+  let end = to.or_else(|| last_end(master));
+  let mut window_start = from;
+  for _ in 0..100 {
+    let window_end = window_start + Duration::days(366);
+    let window_end = end.map_or(window_end, |end| end.min(window_end));
+    let first = expand(master, window_start, window_end)
+      .into_iter()
+      .filter(|e| e.start.max(from) <= e.end.min(window_end))
+      .min_by_key(|e| e.start);
+
+    if first.is_some() || end.is_some_and(|end| window_end >= end) {
+      return first;
+    }
+    window_start = window_end;
+  }
+
+  None
+}
+
+fn add_months(year: i32, month: u32, months: i64) -> (i32, u32) {
+  let total = year as i64 * 12 + month as i64 - 1 + months;
+  (total.div_euclid(12) as i32, total.rem_euclid(12) as u32 + 1)
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday> {
+  Ok(match s {
+    "MO" => Weekday::Mon,
+    "TU" => Weekday::Tue,
+    "WE" => Weekday::Wed,
+    "TH" => Weekday::Thu,
+    "FR" => Weekday::Fri,
+    "SA" => Weekday::Sat,
+    "SU" => Weekday::Sun,
+    _ => bail!("invalid weekday {}", s),
+  })
+}
+
+fn format_weekday(weekday: Weekday) -> &'static str {
+  match weekday {
+    Weekday::Mon => "MO",
+    Weekday::Tue => "TU",
+    Weekday::Wed => "WE",
+    Weekday::Thu => "TH",
+    Weekday::Fri => "FR",
+    Weekday::Sat => "SA",
+    Weekday::Sun => "SU",
+  }
+}
+
+fn parse_list<T: FromStr>(s: &str) -> Result<Vec<T>>
+where
+  T::Err: Into<anyhow::Error>,
+{
+  s.split(',')
+    .map(|x| x.trim().parse::<T>().map_err(Into::into))
+    .collect()
+}
+
+fn parse_until(s: &str) -> Result<DateTime> {
+  if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%SZ") {
+    return Ok(t.and_utc().fixed_offset());
+  }
+
+  // floating UNTIL values are taken as UTC
+  if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S") {
+    return Ok(t.and_utc().fixed_offset());
+  }
+
+  // an UNTIL date includes the whole day
+  if let Ok(d) = NaiveDate::parse_from_str(s, "%Y%m%d") {
+    let t = d.and_hms_opt(23, 59, 59).expect("date overflow");
+    return Ok(t.and_utc().fixed_offset());
+  }
+
+  bail!("invalid UNTIL value {}", s)
+}
+
+impl FromStr for WeekdayNum {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let s = s.trim();
+    ensure!(s.len() >= 2, "invalid BYDAY value {}", s);
+    let (nth, weekday) = s.split_at(s.len() - 2);
+    let nth = match nth {
+      "" => None,
+      n => Some(n.trim_start_matches('+').parse::<i32>()?),
+    };
+
+    Ok(Self {
+      nth,
+      weekday: parse_weekday(weekday)?,
+    })
+  }
+}
+
+impl fmt::Display for WeekdayNum {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(nth) = self.nth {
+      write!(f, "{nth}")?;
+    }
+    write!(f, "{}", format_weekday(self.weekday))
+  }
+}
+
+impl FromStr for RRule {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let mut freq = None;
+    let mut rule = RRule::new(Frequency::Daily);
+
+    for part in s.split(';').filter(|p| !p.is_empty()) {
+      let (key, value) = part
+        .split_once('=')
+        .ok_or_else(|| anyhow!("invalid RRULE part {}", part))?;
+
+      match key.to_ascii_uppercase().as_str() {
+        "FREQ" => {
+          freq = Some(match value {
+            "DAILY" => Frequency::Daily,
+            "WEEKLY" => Frequency::Weekly,
+            "MONTHLY" => Frequency::Monthly,
+            "YEARLY" => Frequency::Yearly,
+            _ => bail!("unsupported FREQ {}", value),
+          })
+        }
+        "INTERVAL" => {
+          rule.interval = value.parse()?;
+          ensure!(rule.interval > 0, "INTERVAL must be positive");
+        }
+        "COUNT" => rule.count = Some(value.parse()?),
+        "UNTIL" => rule.until = Some(parse_until(value)?),
+        "BYDAY" => rule.by_day = parse_list(value)?,
+        "BYMONTHDAY" => rule.by_month_day = parse_list(value)?,
+        "BYMONTH" => rule.by_month = parse_list(value)?,
+        "BYSETPOS" => rule.by_set_pos = parse_list(value)?,
+        "WKST" => rule.week_start = parse_weekday(value)?,
+        _ => {
+          log::debug!("RRULE part {part} is not used for expansion");
+          rule.unsupported.push((key.to_string(), value.to_string()));
+        }
+      }
+    }
+
+    rule.freq = freq.ok_or_else(|| anyhow!("RRULE without FREQ: {}", s))?;
+    Ok(rule)
+  }
+}
+
+impl RRule {
+  // all-day events need their UNTIL written as a date
+  pub fn to_date_string(&self) -> String {
+    DateRRule(self).to_string()
+  }
+
+  fn fmt_with(
+    &self,
+    f: &mut fmt::Formatter<'_>,
+    until_format: &str,
+  ) -> fmt::Result {
+    let join = |items: Vec<String>| items.join(",");
+
+    let freq = match self.freq {
+      Frequency::Daily => "DAILY",
+      Frequency::Weekly => "WEEKLY",
+      Frequency::Monthly => "MONTHLY",
+      Frequency::Yearly => "YEARLY",
+    };
+    write!(f, "FREQ={freq}")?;
+
+    if self.interval != 1 {
+      write!(f, ";INTERVAL={}", self.interval)?;
+    }
+    if let Some(count) = self.count {
+      write!(f, ";COUNT={count}")?;
+    }
+    if let Some(until) = self.until {
+      write!(f, ";UNTIL={}", until.naive_utc().format(until_format))?;
+    }
+    if !self.by_day.is_empty() {
+      let days = self.by_day.iter().map(|d| d.to_string()).collect();
+      write!(f, ";BYDAY={}", join(days))?;
+    }
+    if !self.by_month_day.is_empty() {
+      let days = self.by_month_day.iter().map(|d| d.to_string()).collect();
+      write!(f, ";BYMONTHDAY={}", join(days))?;
+    }
+    if !self.by_month.is_empty() {
+      let months = self.by_month.iter().map(|m| m.to_string()).collect();
+      write!(f, ";BYMONTH={}", join(months))?;
+    }
+    if !self.by_set_pos.is_empty() {
+      let pos = self.by_set_pos.iter().map(|p| p.to_string()).collect();
+      write!(f, ";BYSETPOS={}", join(pos))?;
+    }
+    if self.week_start != Weekday::Mon {
+      write!(f, ";WKST={}", format_weekday(self.week_start))?;
+    }
+    for (key, value) in self.unsupported.iter() {
+      write!(f, ";{key}={value}")?;
+    }
+
+    Ok(())
+  }
+}
+
+impl fmt::Display for RRule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.fmt_with(f, "%Y%m%dT%H%M%SZ")
+  }
+}
+
+struct DateRRule<'a>(&'a RRule);
+
+impl fmt::Display for DateRRule<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.0.fmt_with(f, "%Y%m%d")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::DateTime as ChronoDateTime;
+
+  use super::*;
+  use crate::event::{EventBuilder, EventTimezone};
+
+  fn time(s: &str) -> DateTime {
+    ChronoDateTime::parse_from_rfc3339(s).unwrap()
+  }
+
+  // an hour long series in UTC
+  fn series(start: &str, rule: &str) -> Event {
+    let start = time(start);
+    EventBuilder::default()
+      .id("series")
+      .calendar("test")
+      .title("series")
+      .start(start)
+      .end(start + Duration::hours(1))
+      .recurrence(Some(Recurrence {
+        rule: Some(rule.parse().unwrap()),
+        ..Default::default()
+      }))
+      .build()
+      .unwrap()
+  }
+
+  fn starts(master: &Event, from: &str, to: &str) -> Vec<String> {
+    expand(master, time(from), time(to))
+      .iter()
+      .map(|e| e.start.naive_utc().format("%F %H:%M").to_string())
+      .collect()
+  }
+
+  #[test]
+  fn rules_are_written_back_as_read() {
+    let rule = "FREQ=MONTHLY;INTERVAL=2;COUNT=5;BYDAY=-1FR,2MO;BYSETPOS=1;\
+                WKST=SU;BYHOUR=9";
+    assert_eq!(rule.parse::<RRule>().unwrap().to_string(), rule);
+
+    let rule: RRule = "FREQ=DAILY;UNTIL=20240307T235959Z".parse().unwrap();
+    assert_eq!(rule.to_date_string(), "FREQ=DAILY;UNTIL=20240307");
+
+    assert!("INTERVAL=2".parse::<RRule>().is_err());
+    assert!("FREQ=DAILY;INTERVAL=0".parse::<RRule>().is_err());
+    assert!("FREQ=WEEKLY;BYDAY=XX".parse::<RRule>().is_err());
+  }
+
+  #[test]
+  fn weekly_by_day() {
+    let master = series("2024-03-04T09:00:00Z", "FREQ=WEEKLY;BYDAY=MO,WE,FR");
+    assert_eq!(
+      starts(&master, "2024-03-04T00:00:00Z", "2024-03-10T23:59:59Z"),
+      ["2024-03-04 09:00", "2024-03-06 09:00", "2024-03-08 09:00"]
+    );
+
+    let master =
+      series("2024-03-04T09:00:00Z", "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU");
+    assert_eq!(
+      starts(&master, "2024-03-05T00:00:00Z", "2024-03-31T00:00:00Z"),
+      ["2024-03-05 09:00", "2024-03-19 09:00"]
+    );
+  }
+
+  #[test]
+  fn monthly_nth_weekday_with_count() {
+    let master =
+      series("2024-01-26T09:00:00Z", "FREQ=MONTHLY;BYDAY=-1FR;COUNT=3");
+    assert_eq!(
+      starts(&master, "2024-01-01T00:00:00Z", "2024-12-31T00:00:00Z"),
+      ["2024-01-26 09:00", "2024-02-23 09:00", "2024-03-29 09:00"]
+    );
+    assert_eq!(last_end(&master), Some(time("2024-03-29T10:00:00Z")));
+
+    let master = series("2024-01-08T09:00:00Z", "FREQ=MONTHLY;BYDAY=2MO");
+    assert_eq!(
+      starts(&master, "2024-02-01T00:00:00Z", "2024-03-31T00:00:00Z"),
+      ["2024-02-12 09:00", "2024-03-11 09:00"]
+    );
+    assert_eq!(last_end(&master), None);
+  }
+
+  #[test]
+  fn last_workday_of_the_month() {
+    let master = series(
+      "2024-01-31T09:00:00Z",
+      "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+    );
+    assert_eq!(
+      starts(&master, "2024-01-01T00:00:00Z", "2024-04-30T23:00:00Z"),
+      [
+        "2024-01-31 09:00",
+        "2024-02-29 09:00",
+        "2024-03-29 09:00",
+        "2024-04-30 09:00"
+      ]
+    );
+  }
+
+  #[test]
+  fn yearly_by_month_and_month_day() {
+    let master = series(
+      "2024-02-29T09:00:00Z",
+      "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1",
+    );
+    assert_eq!(
+      starts(&master, "2025-01-01T00:00:00Z", "2026-12-31T00:00:00Z"),
+      ["2025-02-28 09:00", "2026-02-28 09:00"]
+    );
+  }
+
+  #[test]
+  fn until_exdates_and_rdates() {
+    let mut master =
+      series("2024-03-04T09:00:00Z", "FREQ=DAILY;UNTIL=20240307T090000Z");
+    let recurrence = master.recurrence.as_mut().unwrap();
+    recurrence.exdates.push(time("2024-03-05T09:00:00Z"));
+    recurrence.rdates.push(time("2024-03-10T12:00:00Z"));
+
+    assert_eq!(
+      starts(&master, "2024-03-01T00:00:00Z", "2024-03-31T00:00:00Z"),
+      [
+        "2024-03-04 09:00",
+        "2024-03-06 09:00",
+        "2024-03-07 09:00",
+        "2024-03-10 12:00"
+      ]
+    );
+    assert_eq!(last_end(&master), Some(time("2024-03-10T13:00:00Z")));
+  }
+
+  #[test]
+  fn wall_clock_time_is_kept_across_dst() {
+    let mut master = series("2024-03-29T08:00:00Z", "FREQ=DAILY;COUNT=4");
+    let zone = Zone::from_tzid("Europe/Berlin").unwrap();
+    master.timezone = EventTimezone::Zoned(zone);
+
+    // 09:00 in Berlin, before and after the switch to summer time
+    assert_eq!(
+      starts(&master, "2024-03-29T00:00:00Z", "2024-04-05T00:00:00Z"),
+      [
+        "2024-03-29 08:00",
+        "2024-03-30 08:00",
+        "2024-03-31 07:00",
+        "2024-04-01 07:00"
+      ]
+    );
+  }
+
+  #[test]
+  fn occurrence_ids() {
+    let master = series("2024-03-04T09:00:00Z", "FREQ=DAILY");
+    let occurrence = occurrence(&master, time("2024-03-06T09:00:00Z"));
+    assert_eq!(occurrence.id, "series#20240306T090000Z");
+    assert_eq!(occurrence.uid(), "series");
+    assert_eq!(
+      parse_occurrence_id(&occurrence.id),
+      Some(("series", time("2024-03-06T09:00:00Z")))
+    );
+    assert_eq!(parse_occurrence_id("series"), None);
+  }
+
+  #[test]
+  fn overrides_replace_occurrences() {
+    let mut master = series("2024-03-04T09:00:00Z", "FREQ=DAILY;COUNT=3");
+    let mut moved = occurrence(&master, time("2024-03-05T09:00:00Z"));
+    moved.start = time("2024-03-05T14:00:00Z");
+    moved.end = time("2024-03-05T15:00:00Z");
+    assert!(
+      apply_change(&mut master, &moved, RecurrenceScope::ThisOnly).is_none()
+    );
+
+    let occurrences = expand(
+      &master,
+      time("2024-03-01T00:00:00Z"),
+      time("2024-03-31T00:00:00Z"),
+    );
+    assert_eq!(occurrences.len(), 3);
+    assert_eq!(occurrences[1].start, time("2024-03-05T14:00:00Z"));
+    assert_eq!(occurrences[1].id, "series#20240305T090000Z");
+
+    assert!(delete_occurrences(
+      &mut master,
+      time("2024-03-05T09:00:00Z"),
+      RecurrenceScope::ThisOnly
+    ));
+    assert_eq!(
+      starts(&master, "2024-03-01T00:00:00Z", "2024-03-31T00:00:00Z"),
+      ["2024-03-04 09:00", "2024-03-06 09:00"]
+    );
+  }
+
+  #[test]
+  fn changing_all_occurrences() {
+    let mut master = series("2024-03-04T09:00:00Z", "FREQ=DAILY");
+    master
+      .recurrence
+      .as_mut()
+      .unwrap()
+      .exdates
+      .push(time("2024-03-05T09:00:00Z"));
+
+    let mut moved = occurrence(&master, time("2024-03-06T09:00:00Z"));
+    moved.start = time("2024-03-06T11:00:00Z");
+    moved.end = time("2024-03-06T11:30:00Z");
+    moved.title = "moved".to_string();
+    apply_change(&mut master, &moved, RecurrenceScope::All);
+
+    assert_eq!(master.start, time("2024-03-04T11:00:00Z"));
+    assert_eq!(master.end, time("2024-03-04T11:30:00Z"));
+    assert_eq!(master.title, "moved");
+    // the excluded occurrence moves along
+    assert_eq!(
+      starts(&master, "2024-03-04T00:00:00Z", "2024-03-06T23:00:00Z"),
+      ["2024-03-04 11:00", "2024-03-06 11:00"]
+    );
+  }
+
+  #[test]
+  fn splitting_the_following_occurrences() {
+    let mut master = series("2024-03-01T09:00:00Z", "FREQ=DAILY;COUNT=10");
+    let mut moved = occurrence(&master, time("2024-03-05T09:00:00Z"));
+    moved.start = time("2024-03-05T10:00:00Z");
+    moved.end = time("2024-03-05T11:00:00Z");
+
+    let following = split(&mut master, &moved, "following".to_string());
+    let rule = |e: &Event| e.recurrence.as_ref()?.rule.clone();
+    assert_eq!(rule(&master).unwrap().count, Some(4));
+    assert_eq!(rule(&following).unwrap().count, Some(6));
+    assert_eq!(following.id, "following");
+    assert_eq!(following.start, time("2024-03-05T10:00:00Z"));
+    assert_eq!(last_end(&master), Some(time("2024-03-04T10:00:00Z")));
+    assert_eq!(last_end(&following), Some(time("2024-03-10T11:00:00Z")));
+
+    // without COUNT the series ends with UNTIL
+    let mut master = series("2024-03-01T09:00:00Z", "FREQ=DAILY");
+    let moved = occurrence(&master, time("2024-03-05T09:00:00Z"));
+    let following =
+      apply_change(&mut master, &moved, RecurrenceScope::ThisAndFollowing)
+        .unwrap();
+    assert_eq!(
+      rule(&master).unwrap().until,
+      Some(time("2024-03-05T08:59:59Z"))
+    );
+    assert_eq!(rule(&following).unwrap().until, None);
+    assert_eq!(
+      starts(&master, "2024-03-04T00:00:00Z", "2024-03-31T00:00:00Z"),
+      ["2024-03-04 09:00"]
+    );
+  }
+
+  #[test]
+  fn deleting_the_following_occurrences() {
+    let mut master = series("2024-03-01T09:00:00Z", "FREQ=DAILY");
+    let following = RecurrenceScope::ThisAndFollowing;
+    assert!(delete_occurrences(
+      &mut master,
+      time("2024-03-03T09:00:00Z"),
+      following
+    ));
+    assert_eq!(last_end(&master), Some(time("2024-03-02T10:00:00Z")));
+
+    // nothing is left when deleting from the first occurrence
+    assert!(!delete_occurrences(
+      &mut master,
+      time("2024-03-01T09:00:00Z"),
+      following
+    ));
+  }
+}
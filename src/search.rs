@@ -0,0 +1,183 @@
+use crate::{event::Event, recurrence, util::DateTime};
+
+// A full-text search in the title, description and location of events,
+// optionally limited to those overlapping the from..to interval.
+//
+// The text is made of words and "quoted phrases", all of which have to
+// be found. A word followed by * also matches the words starting with
+// it (e.g. meet* matches meeting).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchQuery {
+  pub text: String,
+  pub from: Option<DateTime>,
+  pub to: Option<DateTime>,
+}
+
+// a word or a phrase of the query, the last word is matched as a
+// prefix if followed by *
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Term {
+  words: Vec<String>,
+  prefix: bool,
+}
+
+impl SearchQuery {
+  pub fn new(text: impl Into<String>) -> Self {
+    Self {
+      text: text.into(),
+      ..Default::default()
+    }
+  }
+
+  pub(crate) fn terms(&self) -> Vec<Term> {
+    let mut terms = vec![];
+    let mut chars = self.text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+      if c.is_whitespace() {
+        continue;
+      }
+
+      let mut text = String::new();
+      if c == '"' {
+        // unterminated phrases run until the end
+        text.extend(chars.by_ref().take_while(|c| *c != '"'));
+      } else {
+        text.push(c);
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '"') {
+          text.push(c);
+        }
+      }
+
+      let prefix = text.ends_with('*') || chars.next_if_eq(&'*').is_some();
+      let words = tokenize(&text);
+      if !words.is_empty() {
+        terms.push(Term { words, prefix });
+      }
+    }
+
+    terms
+  }
+
+  // the query in the FTS5 syntax, every term is quoted so that it's
+  // never taken for an operator
+  pub(crate) fn fts_query(&self) -> Option<String> {
+    let terms = self.terms();
+    if terms.is_empty() {
+      return None;
+    }
+
+    let terms: Vec<_> = terms
+      .iter()
+      .map(|term| {
+        let star = if term.prefix { "*" } else { "" };
+        format!("\"{}\"{star}", term.words.join(" "))
+      })
+      .collect();
+    Some(terms.join(" "))
+  }
+
+  // whether the title, description or location of the event, or of
+  // one of its overridden occurrences, contains all the terms
+  pub(crate) fn matches(&self, event: &Event) -> bool {
+    self.matches_fields(event)
+      || overrides(event).iter().any(|e| self.matches_fields(e))
+  }
+
+  // whether the title, description or location of the event itself
+  // contains all the terms
+  fn matches_fields(&self, event: &Event) -> bool {
+    let location = event.location();
+    let fields: Vec<Vec<String>> = [
+      Some(event.title.as_str()),
+      event.description.as_deref(),
+      location.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(tokenize)
+    .collect();
+
+    let terms = self.terms();
+    !terms.is_empty()
+      && terms
+        .iter()
+        .all(|term| fields.iter().any(|field| term.found_in(field)))
+  }
+
+  // The event matched if it overlaps with the interval. For recurring
+  // events, that's their first occurrence in it, or the first of the
+  // overridden occurrences matching when only those do.
+  pub(crate) fn hit(&self, event: Event) -> Option<Event> {
+    let from = self.from.unwrap_or_else(|| span_start(&event));
+    let to = self.to;
+    let overlaps =
+      |e: &Event| e.end >= from && to.is_none_or(|to| e.start <= to);
+
+    if event.is_recurring() && !self.matches_fields(&event) {
+      let recurrence_id = overrides(&event)
+        .iter()
+        .filter(|e| self.matches_fields(e) && overlaps(e))
+        .min_by_key(|e| e.start)?
+        .recurrence_id?;
+      return Some(recurrence::occurrence(&event, recurrence_id));
+    }
+
+    if event.is_recurring() {
+      return recurrence::first_occurrence(&event, from, to);
+    }
+
+    overlaps(&event).then_some(event)
+  }
+
+  // the hits of the matched events, ordered by time
+  pub(crate) fn hits(
+    &self,
+    events: impl IntoIterator<Item = Event>,
+  ) -> Vec<Event> {
+    let mut hits: Vec<_> =
+      events.into_iter().filter_map(|e| self.hit(e)).collect();
+    hits.sort_by_key(|e| e.start);
+    hits
+  }
+}
+
+impl Term {
+  fn found_in(&self, field: &[String]) -> bool {
+    let n = self.words.len();
+    field.windows(n).any(|window| {
+      window
+        .iter()
+        .zip(self.words.iter())
+        .enumerate()
+        .all(|(i, (a, b))| {
+          if self.prefix && i == n - 1 {
+            a.starts_with(b.as_str())
+          } else {
+            a == b
+          }
+        })
+    })
+  }
+}
+
+// the words as split by the unicode61 tokenizer of FTS5
+fn tokenize(text: &str) -> Vec<String> {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(|word| word.to_lowercase())
+    .collect()
+}
+
+fn overrides(event: &Event) -> &[Event] {
+  event.recurrence.as_ref().map_or(&[][..], |r| &r.overrides)
+}
+
+// overridden occurrences may have been moved before the series start
+fn span_start(event: &Event) -> DateTime {
+  overrides(event)
+    .iter()
+    .map(|e| e.start)
+    .fold(event.start, DateTime::min)
+}
@@ -0,0 +1,163 @@
+use std::{
+  str::FromStr,
+  sync::{Arc, OnceLock},
+};
+
+use chrono::{Duration, FixedOffset, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::recurrence::RRule;
+use crate::util::DateTime;
+
+mod windows;
+
+// the configured timezone, used for new events and floating times
+static LOCAL_TIMEZONE: OnceLock<Tz> = OnceLock::new();
+
+// A timezone the wall clock times of events are given in, as named by
+// a TZID
+#[derive(Clone, Debug, PartialEq)]
+pub enum Zone {
+  Iana(Tz),
+  // defined by a VTIMEZONE in the ics file
+  Custom(Arc<CustomZone>),
+}
+
+// The STANDARD and DAYLIGHT observances of a VTIMEZONE component
+// (RFC 5545 section 3.6.5)
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomZone {
+  pub tzid: String,
+  pub observances: Vec<Observance>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observance {
+  // the first onset, in the local time before it
+  pub start: NaiveDateTime,
+  pub offset_from: FixedOffset,
+  pub offset_to: FixedOffset,
+  pub rule: Option<RRule>,
+  pub rdates: Vec<NaiveDateTime>,
+}
+
+pub fn set_local_timezone(tz: Tz) {
+  if LOCAL_TIMEZONE.set(tz).is_err() {
+    log::warn!("Local timezone is already set");
+  }
+}
+
+impl Zone {
+  pub fn utc() -> Self {
+    Self::Iana(Tz::UTC)
+  }
+
+  // the configured timezone, or the one of the system
+  pub fn local() -> Self {
+    Self::Iana(*LOCAL_TIMEZONE.get_or_init(system_timezone))
+  }
+
+  // IANA names, or the Windows names used by Outlook
+  pub fn from_tzid(tzid: &str) -> Option<Self> {
+    let name = windows::iana_name(tzid).unwrap_or(tzid);
+    Tz::from_str(name).ok().map(Self::Iana)
+  }
+
+  pub fn tzid(&self) -> &str {
+    match self {
+      Self::Iana(tz) => tz.name(),
+      Self::Custom(zone) => &zone.tzid,
+    }
+  }
+
+  pub fn offset_at(&self, utc: &NaiveDateTime) -> FixedOffset {
+    match self {
+      Self::Iana(tz) => tz.offset_from_utc_datetime(utc).fix(),
+      Self::Custom(zone) => zone.offset_at(utc),
+    }
+  }
+
+  // the wall clock time in this timezone
+  pub fn to_local(&self, time: &DateTime) -> NaiveDateTime {
+    let utc = time.naive_utc();
+    utc + self.offset_at(&utc)
+  }
+
+  // Ambiguous times are taken as the first occurrence, and times
+  // skipped by a DST transition are interpreted with the offset before
+  // the transition (RFC 5545 section 3.3.5).
+  pub fn localize(&self, local: &NaiveDateTime) -> DateTime {
+    let earliest = match self {
+      Self::Iana(tz) => tz
+        .from_local_datetime(local)
+        .earliest()
+        .map(|t| t.fixed_offset()),
+      Self::Custom(zone) => zone.localize(local),
+    };
+
+    if let Some(time) = earliest {
+      return time;
+    }
+
+    let offset = self.offset_at(&(*local - Duration::days(1)));
+    offset.from_utc_datetime(&(*local - offset))
+  }
+}
+
+impl CustomZone {
+  // the offset set by the latest onset, or the one before the first
+  // onset
+  fn offset_at(&self, utc: &NaiveDateTime) -> FixedOffset {
+    let latest = self
+      .observances
+      .iter()
+      .filter_map(|o| o.last_onset(utc).map(|onset| (onset, o.offset_to)))
+      .max_by_key(|(onset, _)| *onset);
+    let first = self
+      .observances
+      .iter()
+      .min_by_key(|o| o.start - o.offset_from);
+
+    match (latest, first) {
+      (Some((_, offset)), _) => offset,
+      (None, Some(first)) => first.offset_from,
+      (None, None) => Utc.fix(),
+    }
+  }
+
+  fn localize(&self, local: &NaiveDateTime) -> Option<DateTime> {
+    self
+      .observances
+      .iter()
+      .flat_map(|o| [o.offset_from, o.offset_to])
+      .filter(|offset| self.offset_at(&(*local - *offset)) == *offset)
+      .map(|offset| offset.from_utc_datetime(&(*local - offset)))
+      .min()
+  }
+}
+
+impl Observance {
+  // the latest onset at or before the given time, in UTC
+  fn last_onset(&self, utc: &NaiveDateTime) -> Option<NaiveDateTime> {
+    let local = *utc + self.offset_from;
+    let ruled = self.rule.as_ref().and_then(|rule| {
+      let until = rule.until.map(|t| t.naive_utc() + self.offset_from);
+      let time = until.map_or(local, |until| until.min(local));
+      rule.last_before(self.start, time)
+    });
+
+    std::iter::once(self.start)
+      .chain(ruled)
+      .chain(self.rdates.iter().cloned())
+      .filter(|t| *t <= local)
+      .max()
+      .map(|t| t - self.offset_from)
+  }
+}
+
+fn system_timezone() -> Tz {
+  iana_time_zone::get_timezone()
+    .ok()
+    .and_then(|name| Tz::from_str(&name).ok())
+    .unwrap_or(Tz::UTC)
+}
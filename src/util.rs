@@ -33,6 +33,12 @@ pub(crate) fn local_now() -> DateTime {
   now.with_timezone(now.offset())
 }
 
+// the time all-day events on the date are anchored to
+pub fn all_day_time(date: Date) -> DateTime {
+  let midnight = date.and_hms_opt(0, 0, 0).expect("date overflow");
+  midnight.and_utc().fixed_offset()
+}
+
 // return if the times were been swapped
 pub fn reorder_times(t1: &mut DateTime, t2: &mut DateTime) -> bool {
   if t1 < t2 {
@@ -1,7 +1,12 @@
+mod history;
 mod interaction;
 mod layout;
+mod recurrence;
+mod search;
 
-use chrono::{Duration, FixedOffset, NaiveDateTime, NaiveTime, Timelike};
+use chrono::{
+  Datelike, Duration, FixedOffset, NaiveDateTime, NaiveTime, Timelike, Weekday,
+};
 use derive_builder::Builder;
 use eframe::egui::{
   self, pos2, vec2, Color32, Pos2, Rect, Response, Sense, Ui, Vec2,
@@ -9,12 +14,15 @@ use eframe::egui::{
 use uuid::Uuid;
 
 use self::{
-  interaction::History,
   layout::{Layout, LayoutAlgorithm},
+  search::SearchPanel,
 };
 
 use crate::{
-  event::{Event, EventBuilder},
+  backend::CalendarInfo,
+  event::{Event, EventBuilder, EventTimezone},
+  history::History,
+  timezone::Zone,
   util::{now, on_the_same_day, today, Date, DateTime},
   widget::CalendarBuilder,
 };
@@ -26,6 +34,11 @@ use super::Calendar;
 pub struct ScheduleUi {
   #[builder(default = "3")]
   day_count: usize,
+  #[builder(default = "DayView::Fit")]
+  day_view: DayView,
+  // the day the week views start on
+  #[builder(default = "Weekday::Mon")]
+  first_weekday: Weekday,
   #[builder(default = "260.0")]
   day_width: f32,
   #[builder(default = "100.0")]
@@ -40,6 +53,8 @@ pub struct ScheduleUi {
   time_marker_margin_width: f32,
   #[builder(default = "60.0")]
   day_header_margin_height: f32,
+  #[builder(default = "24.0")]
+  all_day_row_height: f32,
   #[builder(default = "\"%H:%M\"")]
   time_marker_format: &'static str,
   #[builder(default = "\"%F %a\"")]
@@ -59,6 +74,10 @@ pub struct ScheduleUi {
   #[builder(default = "std::time::Instant::now()", setter(skip))]
   last_update: std::time::Instant,
 
+  // rows in the all-day strip, updated on every frame
+  #[builder(default = "1", setter(skip))]
+  all_day_rows: usize,
+
   #[builder(default = "5.0")]
   resizer_height: f32,
   #[builder(default = "20.0")]
@@ -80,29 +99,109 @@ pub struct ScheduleUi {
 
   new_event_calendar: String,
 
+  // display names and colors of the calendars
+  #[builder(default)]
+  calendars: Vec<CalendarInfo>,
+
   #[builder(default = "false")]
   pub scope_updated: bool,
 
   #[builder(default = "false")]
   pub refresh_requested: bool,
 
+  // the day count changed, the days are to be fit into the window
+  #[builder(default = "false")]
+  pub refit_requested: bool,
+
+  // the trash panel is to be opened
+  #[builder(default = "false")]
+  pub trash_requested: bool,
+
+  // the events of the shown days are being loaded
+  #[builder(default = "false")]
+  pub loading: bool,
+
   #[builder(default = "vec![]")]
   events: Vec<Event>,
 
-  #[builder(default, setter(skip))]
+  // the changes made, kept across scope changes
+  #[builder(default)]
   history: History,
 
+  // the history window is open
+  #[builder(default, setter(skip))]
+  history_open: bool,
+
+  // the search panel, when open
+  #[builder(default, setter(skip))]
+  search: Option<SearchPanel>,
+
+  // the search result to focus once its day is shown
+  #[builder(default, setter(skip))]
+  search_hit: Option<EventId>,
+
   #[builder(default)]
   calendar: Option<Calendar>,
 }
 
 type EventId = String;
 
+// the days shown
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DayView {
+  // as many days as fit in the window, around the current day
+  Fit,
+  // the number of days around the current day
+  Days(usize),
+  // the five days from Monday to Friday
+  WorkWeek,
+  // the seven days from the first weekday
+  Week,
+}
+
+impl DayView {
+  pub fn day_count(&self) -> Option<usize> {
+    match self {
+      DayView::Fit => None,
+      DayView::Days(n) => Some(*n),
+      DayView::WorkWeek => Some(5),
+      DayView::Week => Some(7),
+    }
+  }
+
+  fn is_week(&self) -> bool {
+    matches!(self, DayView::WorkWeek | DayView::Week)
+  }
+
+  // the first day shown of the week of the date for the week views,
+  // the work week is Monday to Friday of the week whichever day the
+  // week starts on
+  fn week_start(&self, date: Date, first_weekday: Weekday) -> Option<Date> {
+    if !self.is_week() {
+      return None;
+    }
+
+    let week_start = date - days_since(date.weekday(), first_weekday);
+    match self {
+      DayView::WorkWeek => {
+        Some(week_start + days_since(Weekday::Mon, first_weekday))
+      }
+      _ => Some(week_start),
+    }
+  }
+}
+
+// the days from the last weekday `from` until the weekday
+fn days_since(weekday: Weekday, from: Weekday) -> Duration {
+  let days = 7 + weekday.num_days_from_sunday() - from.num_days_from_sunday();
+  Duration::days((days % 7).into())
+}
+
 #[derive(Debug)]
 enum EventLayoutType {
   // start, end
   Single(f32, f32),
-  #[allow(unused)]
+  // start, end (exclusive)
   AllDay([Date; 2]),
 }
 
@@ -133,6 +232,23 @@ impl ScheduleUi {
       layout.merge(layout::MarkusAlgorithm::compute(events))
     }
 
+    let last_day = self.first_day + Duration::days(self.day_count as i64);
+    let all_day_events: Vec<layout::Ev> = events
+      .iter()
+      .filter(|&e| !e.deleted)
+      .filter_map(|e| match self.layout_type(e) {
+        EventLayoutType::AllDay([start, end])
+          if start < last_day && end > self.first_day =>
+        {
+          let start = (start - self.first_day).num_days();
+          let end = (end - self.first_day).num_days();
+          Some((&e.id, start, end).into())
+        }
+        _ => None,
+      })
+      .collect();
+    layout.merge(layout::stack_rows(all_day_events));
+
     layout
   }
 
@@ -153,7 +269,28 @@ impl ScheduleUi {
 
         Some(rect.shrink(margin))
       }
-      _ => unimplemented!(),
+      EventLayoutType::AllDay([start, end]) => {
+        let row = layout.query_row(&event.id)?;
+        let first = (start - self.first_day).num_days().max(0);
+        let last = (end - self.first_day).num_days().min(self.day_count as i64);
+        if first >= last {
+          return None;
+        }
+
+        let region = self.all_day_region(ui);
+        let min = region.left_top()
+          + vec2(
+            first as f32 * self.day_width,
+            row as f32 * self.all_day_row_height,
+          );
+        let size = vec2(
+          (last - first) as f32 * self.day_width,
+          self.all_day_row_height,
+        );
+        let margin = ui.style().visuals.clip_rect_margin / 2.0;
+
+        Some(Rect::from_min_size(min, size).shrink(margin))
+      }
     }
   }
 
@@ -275,6 +412,27 @@ impl ScheduleUi {
     [upper_resizer, lower_resizer]
   }
 
+  // only the horizontal position is taken into account
+  fn pointer_pos_to_date(&self, ui: &Ui, pos: Pos2) -> Option<Date> {
+    let region = self.all_day_region(ui);
+    let day = ((pos.x - region.left()) / self.day_width).floor() as i64;
+    if !(day >= 0 && day < self.day_count as i64) {
+      return None;
+    }
+
+    Some(self.first_day + Duration::days(day))
+  }
+
+  // the date in the all-day strip under the pointer
+  fn pointer_to_all_day_date(&self, ui: &Ui) -> Option<Date> {
+    let pos = ui.input(|input| input.pointer.interact_pos())?;
+    if !self.all_day_region(ui).contains(pos) {
+      return None;
+    }
+
+    self.pointer_pos_to_date(ui, pos)
+  }
+
   fn date_to_day(&self, date: Date) -> Option<usize> {
     let diff_days = (date - self.first_day).num_days();
     if diff_days < 0 || diff_days >= self.day_count as i64 {
@@ -320,6 +478,39 @@ impl ScheduleUi {
     self.mark_scope_updated();
   }
 
+  // the days paged by at once, a whole week for the week views
+  fn page_days(&self) -> i64 {
+    match self.day_view.is_week() {
+      true => 7,
+      false => self.day_count as i64,
+    }
+  }
+
+  // show the days around the date, or its week for the week views
+  pub fn show_date(&mut self, date: Date) {
+    self.first_day = match self.day_view.week_start(date, self.first_weekday) {
+      Some(week_start) => week_start,
+      None => date - Duration::days(self.day_count as i64 / 2),
+    };
+    self.mark_scope_updated();
+  }
+
+  pub fn set_day_view(&mut self, day_view: DayView) {
+    // keep showing today, or the day in the middle otherwise
+    let today = today(&self.timezone);
+    let date = match self.visible_dates().contains(&today) {
+      true => today,
+      false => self.first_day + Duration::days(self.day_count as i64 / 2),
+    };
+
+    self.day_view = day_view;
+    if let Some(day_count) = day_view.day_count() {
+      self.day_count = day_count;
+    }
+    self.refit_requested = true;
+    self.show_date(date);
+  }
+
   fn draw_current_time_indicator(&self, ui: &mut Ui, rect: Rect, alpha: f32) {
     let widget_visuals = ui.style().noninteractive();
     let painter = ui.painter_at(rect);
@@ -340,7 +531,7 @@ impl ScheduleUi {
 
   fn time_mark_region(&self) -> Rect {
     Rect::from_min_size(
-      pos2(0.0, self.day_header_margin_height),
+      pos2(0.0, self.header_height()),
       vec2(
         self.time_marker_margin_width,
         self.segment_height * self.segment_count as f32,
@@ -392,6 +583,20 @@ impl ScheduleUi {
     }
   }
 
+  // a spinner in the corner above the time marks
+  fn draw_loading_indicator(&self, ui: &mut Ui, rect: Rect) {
+    if !self.loading {
+      return;
+    }
+
+    let corner = Rect::from_min_size(
+      rect.left_top().max(ui.clip_rect().left_top()),
+      vec2(self.time_marker_margin_width, self.day_header_margin_height),
+    );
+    ui.put(corner, egui::Spinner::new())
+      .on_hover_text("Loading events...");
+  }
+
   fn day_mark_region(&self) -> Rect {
     Rect::from_min_size(
       pos2(self.time_marker_margin_width, 0.0),
@@ -460,6 +665,52 @@ impl ScheduleUi {
     }
   }
 
+  // the strip for all-day events right below the day headers, which
+  // floats along with them
+  fn all_day_region(&self, ui: &Ui) -> Rect {
+    let day_mark_region = self
+      .day_mark_region()
+      .translate(ui.max_rect().left_top().to_vec2());
+
+    let top = if day_mark_region.center().y <= ui.clip_rect().top() {
+      ui.clip_rect().top() + self.day_header_margin_height
+    } else {
+      day_mark_region.bottom()
+    };
+
+    Rect::from_min_size(
+      pos2(day_mark_region.left(), top),
+      vec2(day_mark_region.width(), self.all_day_strip_height()),
+    )
+  }
+
+  fn draw_all_day_strip(&self, ui: &mut Ui, rect: Rect) {
+    let visuals = ui.style().visuals.clone();
+    let widget_visuals = ui.style().noninteractive();
+    let painter = ui.painter_at(rect);
+    let region = self.all_day_region(ui);
+
+    painter.rect_filled(
+      region.shrink(visuals.clip_rect_margin),
+      widget_visuals.rounding.ne,
+      widget_visuals.bg_fill,
+    );
+
+    for day in 0..=self.day_count {
+      let x = region.left() + self.day_width * day as f32;
+      let ends = [pos2(x, region.top()), pos2(x, region.bottom())];
+      painter.line_segment(ends, widget_visuals.bg_stroke);
+    }
+  }
+
+  fn all_day_strip_height(&self) -> f32 {
+    self.all_day_rows as f32 * self.all_day_row_height
+  }
+
+  fn header_height(&self) -> f32 {
+    self.day_header_margin_height + self.all_day_strip_height()
+  }
+
   fn content_height(&self) -> f32 {
     self.segment_height * self.segment_count as f32
   }
@@ -474,7 +725,7 @@ impl ScheduleUi {
   }
 
   fn content_offset0(&self) -> Vec2 {
-    vec2(self.time_marker_margin_width, self.day_header_margin_height)
+    vec2(self.time_marker_margin_width, self.header_height())
   }
 
   fn day_column(&self, day: usize) -> Rect {
@@ -539,7 +790,7 @@ impl ScheduleUi {
       self.time_marker_margin_width
         + self.day_width * self.day_count as f32
         + clip_margin,
-      self.day_header_margin_height
+      self.header_height()
         + self.segment_height * self.segment_count as f32
         + text_safe_margin
         + clip_margin,
@@ -559,10 +810,6 @@ impl ScheduleUi {
       Sense::click_and_drag(),
     );
 
-    // background: ticks and current time indicator
-    self.draw_ticks(ui, rect);
-    self.draw_current_time_indicator(ui, rect, 1.0);
-
     let layout = self.layout_events(
       combined_events
         .iter()
@@ -571,29 +818,57 @@ impl ScheduleUi {
         .as_slice(),
     );
 
+    // leave an empty row for creating new all-day events
+    self.all_day_rows = layout.row_count() + 1;
+
+    // background: ticks and current time indicator
+    self.draw_ticks(ui, rect);
+    self.draw_current_time_indicator(ui, rect, 1.0);
+
+    let (all_day_events, events): (Vec<_>, Vec<_>) =
+      combined_events.into_iter().partition(|x| x.event().all_day);
+
     // main: event buttons
-    for combined_event in combined_events {
-      match combined_event {
-        CombinedEvent::ExistingEvent(event) => {
-          self.put_non_interacting_event_block(ui, &layout, &event);
-        }
-        CombinedEvent::InteractingEvent(_event) => {
-          self.put_interacting_event_block(ui, &layout);
-        }
-      }
-    }
+    self.put_event_blocks(ui, &layout, events);
 
-    // floating: time and day headers
+    // floating: day headers, all-day events and time headers
     self.draw_day_marks(ui, rect);
+    self.draw_all_day_strip(ui, rect);
+    self.put_event_blocks(ui, &layout, all_day_events);
     self.draw_time_marks(ui, rect);
+    self.draw_loading_indicator(ui, rect);
 
     // interact with blank area for context menu and new event creation
     self.handle_new_event(ui, &response_on_empty_area);
     self.handle_context_menu(&response_on_empty_area);
 
+    self.handle_search(ui);
+    self.focus_search_hit(ui);
     self.refocus_edited_event(ui);
-    self.handle_hotkeys(ui);
+    if !self.is_searching() {
+      self.handle_hotkeys(ui);
+    }
     self.handle_undo(ui);
+    self.show_history(ui);
+    self.ask_recurrence_scope(ui);
+  }
+
+  fn put_event_blocks(
+    &self,
+    ui: &mut Ui,
+    layout: &Layout,
+    events: Vec<CombinedEvent>,
+  ) {
+    for combined_event in events {
+      match combined_event {
+        CombinedEvent::ExistingEvent(event) => {
+          self.put_non_interacting_event_block(ui, layout, &event);
+        }
+        CombinedEvent::InteractingEvent(_event) => {
+          self.put_interacting_event_block(ui, layout);
+        }
+      }
+    }
   }
 
   pub(crate) fn show(&mut self, ui: &mut Ui) {
@@ -639,11 +914,24 @@ impl ScheduleUi {
   }
 
   pub fn load_events(&mut self, events: Vec<Event>) {
-    // avoid new events interfering with history
-    self.history.clear();
     self.events = events;
   }
 
+  pub fn load_calendars(&mut self, calendars: Vec<CalendarInfo>) {
+    self.calendars = calendars;
+  }
+
+  fn calendar_info(&self, name: &str) -> Option<&CalendarInfo> {
+    self.calendars.iter().find(|c| c.name == name)
+  }
+
+  // the event color, falling back to the color of its calendar
+  fn event_color(&self, event: &Event) -> Option<[f32; 3]> {
+    event
+      .color
+      .or_else(|| self.calendar_info(&event.calendar).and_then(|c| c.color))
+  }
+
   pub fn events_mut(&mut self) -> &mut Vec<Event> {
     &mut self.events
   }
@@ -663,25 +951,45 @@ impl ScheduleUi {
         ui.label("Refreshing events...");
         ui.close_menu();
       }
+      if ui.button("History").clicked() {
+        self.history_open = true;
+        ui.close_menu();
+      }
+      if ui.button("Trash").clicked() {
+        self.trash_requested = true;
+        ui.close_menu();
+      }
       ui.separator();
 
       ui.horizontal(|ui| {
         if ui.button("<<").clicked() {
-          self.scroll_horizontally(-(self.day_count as i64));
+          self.scroll_horizontally(-self.page_days());
         }
         if ui.button("<").clicked() {
           self.scroll_horizontally(-1);
         }
         if ui.button("Today").clicked() {
-          self.first_day =
-            today(&self.timezone) - Duration::days(self.day_count as i64 / 2);
-          self.mark_scope_updated();
+          self.show_date(today(&self.timezone));
         }
         if ui.button(">").clicked() {
           self.scroll_horizontally(1);
         }
         if ui.button(">>").clicked() {
-          self.scroll_horizontally(self.day_count as i64);
+          self.scroll_horizontally(self.page_days());
+        }
+      });
+      ui.horizontal(|ui| {
+        let views = [
+          (DayView::Fit, "Fit"),
+          (DayView::Days(1), "Day"),
+          (DayView::Days(3), "3 days"),
+          (DayView::WorkWeek, "Work week"),
+          (DayView::Week, "Week"),
+        ];
+        for (view, label) in views {
+          if ui.selectable_label(self.day_view == view, label).clicked() {
+            self.set_day_view(view);
+          }
         }
       });
       ui.separator();
@@ -705,7 +1013,7 @@ impl ScheduleUi {
       CalendarBuilder::default()
         .date(self.first_day + Duration::days(self.day_count as i64 / 2))
         .current_date(default_date)
-        .weekday_offset(1)
+        .weekday_offset(self.first_weekday.num_days_from_sunday() as usize)
         .highlight_dates(visible_dates)
         .build()
         .unwrap()
@@ -713,15 +1021,19 @@ impl ScheduleUi {
 
     match calendar.show_ui(ui) {
       None => (),
-      Some(DateClicked(date)) => {
-        self.first_day = date - Duration::days(self.day_count as i64 / 2);
-        self.mark_scope_updated();
-      }
+      Some(DateClicked(date)) => self.show_date(date),
     }
   }
 
   fn new_event(&self) -> Event {
-    let color = egui::Rgba::from(self.new_event_color);
+    // events in a colored calendar take the calendar color
+    let color = match self.calendar_info(&self.new_event_calendar) {
+      Some(CalendarInfo { color: Some(_), .. }) => None,
+      _ => {
+        let color = egui::Rgba::from(self.new_event_color);
+        Some([color.r(), color.g(), color.b()])
+      }
+    };
     let start = self
       .first_day
       .and_time(Default::default())
@@ -739,7 +1051,8 @@ impl ScheduleUi {
       .timestamp(now(&self.timezone))
       .created_at(now(&self.timezone))
       .modified_at(now(&self.timezone))
-      .color([color.r(), color.g(), color.b()])
+      .color(color)
+      .timezone(EventTimezone::Zoned(Zone::local()))
       .build()
       .unwrap();
 
@@ -754,6 +1067,9 @@ impl ScheduleUi {
   fn clone_to_new_event(&self, event: &Event) -> Event {
     let mut new_event = event.clone();
     new_event.id = new_event_id();
+    // cloning an occurrence gives a standalone event
+    new_event.recurrence = None;
+    new_event.recurrence_id = None;
     new_event.mark_changed();
     new_event
   }
@@ -806,8 +1122,8 @@ impl ScheduleUi {
     let x = (time.date_naive() - self.first_day).num_days() as f32
       / self.day_width
       + self.time_marker_margin_width;
-    let y = self.day_progress(time) * self.content_height()
-      + self.day_header_margin_height;
+    let y =
+      self.day_progress(time) * self.content_height() + self.header_height();
     pos2(x, y)
   }
 
@@ -827,6 +1143,12 @@ impl ScheduleUi {
   }
 
   fn layout_type(&self, event: &Event) -> EventLayoutType {
+    if event.all_day {
+      let start = event.start.date_naive();
+      let end = event.end.date_naive().max(start + Duration::days(1));
+      return EventLayoutType::AllDay([start, end]);
+    }
+
     let start = self.to_normalized_time(&event.start);
     let end = self.to_normalized_time(&event.end);
     EventLayoutType::Single(start, end)
@@ -846,9 +1168,10 @@ impl ScheduleUi {
     let optimal_day_count =
       ((day_count_max + day_count_min) / 2.0).round() as usize;
 
-    match optimal_day_count {
-      0 => self.day_count = 1,
-      n => self.day_count = n,
+    match (self.day_view.day_count(), optimal_day_count) {
+      (Some(n), _) => self.day_count = n,
+      (None, 0) => self.day_count = 1,
+      (None, n) => self.day_count = n,
     }
 
     self.day_width = match day_space_width / self.day_count as f32 {
@@ -960,6 +1283,16 @@ fn move_event(event: &mut Event, new_start: DateTime) {
   }
 }
 
+fn move_all_day_event(event: &mut Event, new_date: Date) {
+  let delta = new_date - event.start.date_naive();
+
+  if delta != Duration::zero() {
+    event.mark_changed();
+    event.start += delta;
+    event.end += delta;
+  }
+}
+
 fn remove_empty_events(events: &mut [Event]) {
   for event in events.iter_mut() {
     if event.title.is_empty() {
@@ -967,3 +1300,34 @@ fn remove_empty_events(events: &mut [Event]) {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn date(s: &str) -> Date {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn work_week_starts_on_monday_when_weeks_start_on_sunday() {
+    let wednesday = date("2024-03-06");
+    let sunday = date("2024-03-03");
+    let monday = date("2024-03-04");
+
+    let week = DayView::Week.week_start(wednesday, Weekday::Sun);
+    assert_eq!(week, Some(sunday));
+
+    let work_week = DayView::WorkWeek.week_start(wednesday, Weekday::Sun);
+    assert_eq!(work_week, Some(monday));
+
+    // the Sunday starts the week, so its work week follows it
+    let work_week = DayView::WorkWeek.week_start(sunday, Weekday::Sun);
+    assert_eq!(work_week, Some(monday));
+
+    let work_week = DayView::WorkWeek.week_start(sunday, Weekday::Mon);
+    assert_eq!(work_week, Some(date("2024-02-26")));
+
+    assert_eq!(DayView::Days(3).week_start(wednesday, Weekday::Sun), None);
+  }
+}
@@ -0,0 +1,128 @@
+use eframe::egui::{self, Key, Modifiers, Ui};
+
+use crate::{
+  event::EventId,
+  history::{Change, Entry, HistoryUpdate},
+};
+
+use super::ScheduleUi;
+
+// Undoing and redoing the changes with Ctrl+Z and Ctrl+Shift+Z (or
+// Ctrl+Y), or from the history window listing them all. The changes
+// don't have to be on the days shown.
+
+// the entries listed at most
+const MAX_LISTED: usize = 200;
+
+impl ScheduleUi {
+  // the history changes to be stored by the app
+  pub fn take_history_updates(&mut self) -> Vec<HistoryUpdate> {
+    self.history.take_updates()
+  }
+
+  // the changes made to whole series for the occurrences changed,
+  // which are undone instead
+  pub fn set_series_changes(&mut self, series: &[(EventId, Vec<Change>)]) {
+    for (event_id, changes) in series {
+      self.history.set_series(event_id, changes);
+    }
+  }
+
+  pub(super) fn handle_undo(&mut self, ui: &mut Ui) {
+    // the more specific shortcut first, extra shift is ignored otherwise
+    let (redo, undo) = ui.input_mut(|input| {
+      let redo = input.consume_key(Modifiers::CTRL | Modifiers::SHIFT, Key::Z)
+        || input.consume_key(Modifiers::CTRL, Key::Y);
+      (redo, input.consume_key(Modifiers::CTRL, Key::Z))
+    });
+
+    if redo {
+      self.redo();
+    }
+    if undo {
+      self.undo();
+    }
+  }
+
+  fn undo(&mut self) {
+    for change in self.history.undo().into_iter().flatten().rev() {
+      change.reverse().apply(&mut self.events)
+    }
+  }
+
+  fn redo(&mut self) {
+    for change in self.history.redo().into_iter().flatten() {
+      change.apply(&mut self.events)
+    }
+  }
+
+  pub(super) fn show_history(&mut self, ui: &Ui) {
+    if !self.history_open {
+      return;
+    }
+
+    let done = self.history.done();
+    let undone = self.history.undone();
+    // the changes undone are grayed out
+    let entry_labels = |ui: &mut Ui, entry: &Entry, enabled: bool| {
+      let made_at = entry.made_at.with_timezone(&self.timezone);
+      let made_at = made_at.format("%F %H:%M").to_string();
+      ui.add_enabled(enabled, egui::Label::new(made_at));
+      ui.add_enabled(enabled, egui::Label::new(entry.change.describe()));
+    };
+
+    // the number of changes to undo or redo
+    let mut undo = 0;
+    let mut redo = 0;
+    let mut close = false;
+    egui::Window::new("History")
+      .collapsible(false)
+      .resizable(false)
+      .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+      .show(ui.ctx(), |ui| {
+        if done.is_empty() && undone.is_empty() {
+          ui.label("No changes made");
+        }
+
+        egui::ScrollArea::vertical()
+          .max_height(300.0)
+          .show(ui, |ui| {
+            egui::Grid::new("history").striped(true).show(ui, |ui| {
+              // the latest change first
+              for (i, entry) in undone.iter().enumerate().take(MAX_LISTED) {
+                entry_labels(ui, entry, false);
+                if ui.button("Redo").clicked() {
+                  redo = undone.len() - i;
+                }
+                ui.end_row();
+              }
+
+              let listed = MAX_LISTED.saturating_sub(undone.len());
+              for (i, entry) in done.iter().rev().enumerate().take(listed) {
+                entry_labels(ui, entry, true);
+                if ui.button("Undo").clicked() {
+                  undo = i + 1;
+                }
+                ui.end_row();
+              }
+            });
+          });
+
+        if ui.button("Close").clicked()
+          || ui.input(|input| input.key_pressed(Key::Escape))
+        {
+          close = true;
+        }
+      });
+
+    for _ in 0..undo {
+      self.undo();
+    }
+    for _ in 0..redo {
+      self.redo();
+    }
+    if close {
+      self.history_open = false;
+    }
+  }
+}
@@ -3,20 +3,26 @@ use std::{collections::HashMap, sync::Arc};
 use bimap::BiMap;
 use chrono::{Duration, Timelike};
 use eframe::egui::{
-  self, text::LayoutJob, CursorIcon, EventFilter, Key, KeyboardShortcut, Label,
-  LayerId, Modifiers, Rect, Response, Sense, Ui,
+  self, text::LayoutJob, Color32, CursorIcon, EventFilter, Key,
+  KeyboardShortcut, Label, LayerId, Modifiers, Rect, Response, Rgba, Sense, Ui,
 };
 use egui_autocomplete::AutoCompleteTextEdit;
 use humantime;
 
 use crate::{
   event::Event,
-  util::{local_now, on_the_same_day, reorder_times, today, DateTime},
+  history::Change,
+  recurrence::RecurrenceScope,
+  util::{
+    all_day_time, local_now, on_the_same_day, reorder_times, today, DateTime,
+  },
 };
 
 use super::{
-  layout::Layout, move_event, move_event_end, move_event_start, EventId,
-  ScheduleUi,
+  layout::Layout,
+  move_all_day_event, move_event, move_event_end, move_event_start,
+  recurrence::{recurrence_menu, RecurrenceEdit},
+  DayView, EventId, ScheduleUi,
 };
 
 #[derive(Clone, Copy, Debug)]
@@ -30,66 +36,8 @@ enum Direction {
 #[derive(Clone, Copy, Debug)]
 struct DraggingEventYOffset(f32);
 
-#[derive(Clone, Debug, PartialEq)]
-enum Change {
-  Added { new: Event },
-  Removed { old: Event },
-  Modified { old: Event, new: Event },
-}
-
-impl Change {
-  fn reverse(self) -> Self {
-    use Change::*;
-
-    match self {
-      Added { new } => Removed { old: new },
-      Removed { old } => Added { new: old },
-      Modified { old, new } => Modified { new: old, old: new },
-    }
-  }
-
-  fn new_removed(events: &[Event], event_id: &EventId) -> Option<Self> {
-    events
-      .iter()
-      .find(|&e| &e.id == event_id)
-      .cloned()
-      .map(|old| Change::Removed { old })
-  }
-
-  fn new_changed(events: &[Event], changed_event: Event) -> Self {
-    if let Some(existing) =
-      events.iter().find(|&e| e.id == changed_event.id).cloned()
-    {
-      Change::Modified {
-        old: existing,
-        new: changed_event,
-      }
-    } else {
-      Change::Added { new: changed_event }
-    }
-  }
-
-  fn apply(&self, events: &mut Vec<Event>) {
-    match self.clone() {
-      Change::Added { mut new } => {
-        new.mark_changed();
-        events.push(new)
-      }
-      Change::Removed { old } => {
-        if let Some(e) = events.iter_mut().find(|e| e.id == old.id) {
-          e.mark_deleted();
-        }
-      }
-      Change::Modified { old, mut new } => {
-        new.mark_changed();
-
-        if let Some(e) = events.iter_mut().find(|e| e.id == old.id) {
-          *e = new;
-        }
-      }
-    }
-  }
-}
+#[derive(Clone, Copy, Debug)]
+struct DraggingEventXOffset(f32);
 
 #[derive(Clone, Debug, Default)]
 struct EventFocusRegistry {
@@ -125,27 +73,8 @@ impl EventFocusRegistry {
   }
 }
 
-#[derive(Clone, Debug, Default, PartialEq)]
-pub(super) struct History {
-  changes: Vec<Change>,
-}
-
-impl History {
-  pub(super) fn clear(&mut self) {
-    self.changes.clear()
-  }
-
-  fn save(&mut self, change: Change) {
-    self.changes.push(change);
-  }
-
-  fn pop(&mut self) -> Option<Change> {
-    self.changes.pop()
-  }
-}
-
 #[derive(Clone, Debug)]
-struct InteractingEvent {
+pub(super) struct InteractingEvent {
   event: Event,
   state: FocusedEventState,
 }
@@ -191,7 +120,7 @@ impl InteractingEvent {
     Self::get(ui).map(|v| v.event)
   }
 
-  fn is_interacting(ui: &Ui) -> bool {
+  pub(super) fn is_interacting(ui: &Ui) -> bool {
     InteractingEvent::get(ui).is_some()
   }
 }
@@ -263,6 +192,30 @@ impl DeletedEvent {
   }
 }
 
+// a change to an occurrence waiting for the user to pick the
+// occurrences it applies to
+#[derive(Clone, Debug)]
+struct PendingOccurrenceChange(Change);
+
+impl PendingOccurrenceChange {
+  fn id() -> egui::Id {
+    egui::Id::new("pending_occurrence_change")
+  }
+
+  fn set(ui: &Ui, change: Change) {
+    ui.memory_mut(|mem| mem.data.insert_temp(Self::id(), Self(change)));
+  }
+
+  fn get(ui: &Ui) -> Option<Change> {
+    ui.memory(|mem| mem.data.get_temp(Self::id()))
+      .map(|x: Self| x.0)
+  }
+
+  fn discard(ui: &Ui) {
+    ui.memory_mut(|mem| mem.data.remove::<Self>(Self::id()))
+  }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FocusedEventState {
   Editing,
@@ -296,10 +249,15 @@ impl ScheduleUi {
     &self,
     ui: &mut Ui,
     resp: &Response,
+    all_day: bool,
   ) -> Option<FocusedEventState> {
     use FocusedEventState::*;
     let event_rect = resp.rect;
-    let [upper, lower] = self.event_resizer_regions(event_rect);
+    // all-day events can only be moved around
+    let [upper, lower] = match all_day {
+      true => [Rect::NOTHING; 2],
+      false => self.event_resizer_regions(event_rect),
+    };
 
     let _lmb = egui::PointerButton::Primary;
 
@@ -332,6 +290,8 @@ impl ScheduleUi {
 
         let offset = DraggingEventYOffset(event_rect.top() - origin.y);
         ui.memory_mut(|mem| mem.data.insert_temp(egui::Id::NULL, offset));
+        let offset = DraggingEventXOffset(event_rect.left() - origin.x);
+        ui.memory_mut(|mem| mem.data.insert_temp(egui::Id::NULL, offset));
         if ui.input(|input| input.modifiers.ctrl) {
           Some(EventCloning)
         } else {
@@ -353,6 +313,9 @@ impl ScheduleUi {
 
     let resp = self.place_event_button(ui, event_rect, event);
     let commit = match state {
+      _ if event.all_day => {
+        self.handle_all_day_event_dragging(ui, state, event)
+      }
       FocusedEventState::DraggingEventStart => {
         self.handle_event_resizing(ui, upper, |time| {
           move_event_start(event, time, self.min_event_duration);
@@ -428,6 +391,39 @@ impl ScheduleUi {
     None
   }
 
+  // all-day events are moved by whole days, their span can only be
+  // chosen when creating them
+  fn handle_all_day_event_dragging(
+    &self,
+    ui: &mut Ui,
+    state: FocusedEventState,
+    event: &mut Event,
+  ) -> Option<bool> {
+    if !ui.memory(|mem| mem.is_anything_being_dragged()) {
+      return Some(true);
+    }
+
+    if !matches!(state, FocusedEventState::Dragging) {
+      return None;
+    }
+
+    ui.output_mut(|out| out.cursor_icon = CursorIcon::Grabbing);
+
+    let mut pointer_pos = ui.input(|input| input.pointer.interact_pos())?;
+    if let Some(offset_x) =
+      ui.memory(|mem| mem.data.get_temp::<DraggingEventXOffset>(egui::Id::NULL))
+    {
+      pointer_pos.x += offset_x.0;
+    }
+
+    // snap to the nearest day
+    pointer_pos.x += self.day_width / 2.0;
+    let date = self.pointer_pos_to_date(ui, pointer_pos)?;
+    move_all_day_event(event, date);
+
+    None
+  }
+
   pub(super) fn put_non_interacting_event_block(
     &self,
     ui: &mut Ui,
@@ -442,7 +438,7 @@ impl ScheduleUi {
 
     let interaction = self
       .interact_event_region_keyboard(ui, &resp)
-      .or_else(|| self.interact_event_region(ui, &resp));
+      .or_else(|| self.interact_event_region(ui, &resp, event.all_day));
 
     match interaction {
       None => (),
@@ -495,6 +491,7 @@ impl ScheduleUi {
     self.handle_keyboard_focus_move(ui);
     self.handle_keyboard_new_event(ui);
     self.handle_keyboard_delete_event(ui);
+    self.handle_keyboard_day_view(ui);
   }
 
   fn key_direction_input(
@@ -525,6 +522,40 @@ impl ScheduleUi {
     }
   }
 
+  fn handle_keyboard_day_view(&mut self, ui: &Ui) -> Option<()> {
+    if InteractingEvent::is_interacting(ui) {
+      return None;
+    }
+
+    // the digits typed into other widgets, e.g. the repeat count of
+    // the recurrence editor, are not for the schedule
+    let focus = ui.memory(|mem| mem.focus());
+    if focus
+      .is_some_and(|id| EventFocusRegistry::get_event_id(ui, id).is_none())
+    {
+      return None;
+    }
+
+    let pressed =
+      |k| ui.input_mut(|input| input.consume_key(Modifiers::NONE, k));
+    let day_view = if pressed(Key::Num0) {
+      DayView::Fit
+    } else if pressed(Key::Num1) {
+      DayView::Days(1)
+    } else if pressed(Key::Num3) {
+      DayView::Days(3)
+    } else if pressed(Key::Num5) {
+      DayView::WorkWeek
+    } else if pressed(Key::Num7) {
+      DayView::Week
+    } else {
+      return None;
+    };
+
+    self.set_day_view(day_view);
+    Some(())
+  }
+
   fn handle_keyboard_new_event(&mut self, ui: &Ui) -> Option<()> {
     if InteractingEvent::is_interacting(ui) {
       return None;
@@ -540,18 +571,24 @@ impl ScheduleUi {
       .memory(|mem| mem.focus())
       .and_then(|id| EventFocusRegistry::get_event_id(ui, id))
       .and_then(|id| self.events.iter().find(|x| x.id == id))
+      .filter(|x| !x.all_day)
       .map(|x| x.end);
 
     let last_event_end_in_today = self
       .events
       .iter()
+      .filter(|x| !x.all_day)
       .filter(|x| x.end.date_naive() == today)
       .filter(|x| x.end.num_seconds_from_midnight() > 0)
       .max_by_key(|x| x.end)
       .map(|x| x.end);
 
-    let last_event_end =
-      self.events.iter().max_by_key(|x| x.end).map(|x| x.end);
+    let last_event_end = self
+      .events
+      .iter()
+      .filter(|x| !x.all_day)
+      .max_by_key(|x| x.end)
+      .map(|x| x.end);
     let nearest_snapping = {
       let t = self.snap_to_nearest(&local_now());
       self.is_visible(&t).then_some(t)
@@ -629,15 +666,26 @@ impl ScheduleUi {
     let ev_id = EventFocusRegistry::get_event_id(ui, focused_id)?;
     let dir = self.key_direction_input(ui, Modifiers::CTRL)?;
 
-    let event = self.events.iter_mut().find(|x| x.id == ev_id)?;
+    let mut event = self.events.iter().find(|x| x.id == ev_id)?.clone();
+    let start = event.start;
 
-    match dir {
-      Left => super::move_event(event, event.start + Duration::days(-1)),
-      Right => super::move_event(event, event.start + Duration::days(1)),
-      Up => super::move_event(event, event.start - self.min_event_duration),
-      Down => super::move_event(event, event.start + self.min_event_duration),
+    if event.all_day {
+      let date = start.date_naive();
+      match dir {
+        Left => move_all_day_event(&mut event, date - Duration::days(1)),
+        Right => move_all_day_event(&mut event, date + Duration::days(1)),
+        Up | Down => (),
+      }
+    } else {
+      match dir {
+        Left => super::move_event(&mut event, start + Duration::days(-1)),
+        Right => super::move_event(&mut event, start + Duration::days(1)),
+        Up => super::move_event(&mut event, start - self.min_event_duration),
+        Down => super::move_event(&mut event, start + self.min_event_duration),
+      }
     }
 
+    self.apply_keyboard_change(ui, event);
     Some(())
   }
 
@@ -648,31 +696,47 @@ impl ScheduleUi {
     let ev_id = EventFocusRegistry::get_event_id(ui, focused_id)?;
     let dir = self.key_direction_input(ui, Modifiers::SHIFT)?;
 
-    let event = self.events.iter_mut().find(|x| x.id == ev_id)?;
-
-    match dir {
-      Left => super::move_event_end(
-        event,
-        event.end + Duration::days(-1),
-        self.min_event_duration,
-      ),
-      Right => super::move_event_end(
-        event,
-        event.end + Duration::days(1),
-        self.min_event_duration,
-      ),
-      Up => super::move_event_end(
-        event,
-        event.end - self.min_event_duration,
-        self.min_event_duration,
-      ),
-      Down => super::move_event_end(
-        event,
-        event.end + self.min_event_duration,
-        self.min_event_duration,
-      ),
+    let mut event = self.events.iter().find(|x| x.id == ev_id)?.clone();
+    let (start, end) = (event.start, event.end);
+
+    if event.all_day {
+      match dir {
+        Left if end - start > Duration::days(1) => {
+          event.mark_changed();
+          event.end -= Duration::days(1);
+        }
+        Right => {
+          event.mark_changed();
+          event.end += Duration::days(1);
+        }
+        _ => (),
+      }
+    } else {
+      match dir {
+        Left => super::move_event_end(
+          &mut event,
+          end + Duration::days(-1),
+          self.min_event_duration,
+        ),
+        Right => super::move_event_end(
+          &mut event,
+          end + Duration::days(1),
+          self.min_event_duration,
+        ),
+        Up => super::move_event_end(
+          &mut event,
+          end - self.min_event_duration,
+          self.min_event_duration,
+        ),
+        Down => super::move_event_end(
+          &mut event,
+          end + self.min_event_duration,
+          self.min_event_duration,
+        ),
+      }
     }
 
+    self.apply_keyboard_change(ui, event);
     Some(())
   }
 
@@ -699,9 +763,20 @@ impl ScheduleUi {
     rect: Rect,
     event: &Event,
   ) -> Response {
-    let (layout, clipped) = self.shorten_event_label(ui, rect, &event.title);
+    let fill = self
+      .event_color(event)
+      .map(|[r, g, b]| Color32::from(Rgba::from_rgb(r, g, b)));
+    let text_color = match fill {
+      Some(fill) => contrasting_text_color(fill),
+      None => ui.visuals().text_color(),
+    };
+    let (layout, clipped) =
+      self.shorten_event_label(ui, rect, &event.title, text_color);
 
-    let button = egui::Button::new(layout).sense(Sense::click_and_drag());
+    let mut button = egui::Button::new(layout).sense(Sense::click_and_drag());
+    if let Some(fill) = fill {
+      button = button.fill(fill);
+    }
     let resp = ui.put(rect, button);
     disable_built_in_keyboard_focus_navigation(&resp);
 
@@ -710,12 +785,15 @@ impl ScheduleUi {
       resp.clone().on_hover_text(event.title.clone());
     }
 
-    Self::event_context_menu(event, &resp);
+    let calendar = self
+      .calendar_info(&event.calendar)
+      .map_or(event.calendar.as_str(), |c| c.label());
+    Self::event_context_menu(event, calendar, &resp);
 
     resp
   }
 
-  fn event_context_menu(event: &Event, resp: &Response) {
+  fn event_context_menu(event: &Event, calendar: &str, resp: &Response) {
     let format_time = |time: DateTime| {
       if time.second() == 0 {
         time.format("%H:%M")
@@ -729,18 +807,32 @@ impl ScheduleUi {
         ui.label(desc.to_string());
       }
 
-      ui.label(format!(
-        "{}--{} ({})",
-        format_time(event.start),
-        format_time(event.end),
-        (event.end - event.start)
-          .to_std()
-          .map(|d| humantime::format_duration(d).to_string())
-          .unwrap_or_else(|_| "negative duration".to_string())
-      ));
+      if event.all_day {
+        let last_day = event.end.date_naive() - Duration::days(1);
+        ui.label(match last_day > event.start.date_naive() {
+          true => {
+            format!("All day, {}--{}", event.start.format("%F"), last_day)
+          }
+          false => format!("All day, {}", event.start.format("%F")),
+        });
+      } else {
+        ui.label(format!(
+          "{}--{} ({})",
+          format_time(event.start),
+          format_time(event.end),
+          (event.end - event.start)
+            .to_std()
+            .map(|d| humantime::format_duration(d).to_string())
+            .unwrap_or_else(|_| "negative duration".to_string())
+        ));
+      }
+
+      ui.label(format!("Calendar: {calendar}"));
 
       ui.separator();
 
+      recurrence_menu(ui, event);
+
       if ui.button("Delete").clicked() {
         DeletedEvent::set(ui, &event.id);
         ui.close_menu();
@@ -757,9 +849,9 @@ impl ScheduleUi {
     ui: &mut Ui,
     rect: Rect,
     label: &str,
+    color: Color32,
   ) -> (impl Into<egui::WidgetText>, bool) {
     let font_id = egui::TextStyle::Button.resolve(ui.style());
-    let color = ui.visuals().text_color();
 
     let layout_job = |text| {
       let mut j = LayoutJob::simple_singleline(text, font_id.clone(), color);
@@ -873,8 +965,16 @@ impl ScheduleUi {
         if response.dragged_by(egui::PointerButton::Primary) =>
       {
         let mut event = self.new_event();
-        let pointer_pos = self.relative_pointer_pos(ui)?;
-        let init_time = self.pointer_to_datetime_auto(ui, pointer_pos)?;
+        let init_time = match self.pointer_to_all_day_date(ui) {
+          Some(date) => {
+            event.all_day = true;
+            all_day_time(date)
+          }
+          None => {
+            let pointer_pos = self.relative_pointer_pos(ui)?;
+            self.pointer_to_datetime_auto(ui, pointer_pos)?
+          }
+        };
         let new_state =
           self.assign_new_event_dates(ui, init_time, &mut event)?;
 
@@ -917,6 +1017,16 @@ impl ScheduleUi {
   ) -> Option<FocusedEventState> {
     use FocusedEventState::{DraggingEventEnd, DraggingEventStart};
 
+    if event.all_day {
+      let pointer_pos = ui.input(|input| input.pointer.interact_pos())?;
+      let date = self.pointer_pos_to_date(ui, pointer_pos)?;
+      let init_date = init_time.date_naive();
+
+      event.start = all_day_time(init_date.min(date));
+      event.end = all_day_time(init_date.max(date) + Duration::days(1));
+      return Some(DraggingEventEnd);
+    }
+
     let pointer_pos = self.relative_pointer_pos(ui)?;
     let new_time = self.pointer_to_datetime_auto(ui, pointer_pos)?;
 
@@ -957,36 +1067,107 @@ impl ScheduleUi {
         RefocusingEvent::request_focus(ui, &event.id);
 
         let change = Change::new_changed(&self.events, event);
-        change.apply(&mut self.events);
-        self.history.save(change);
+        self.apply_change(ui, change);
       }
     }
 
     // commit deleted event
     if let Some(event_id) = DeletedEvent::take(ui) {
       if let Some(change) = Change::new_removed(&self.events, &event_id) {
+        self.apply_change(ui, change);
+      }
+    }
+
+    // commit recurrence rule changes, which apply to the whole series
+    if let Some(edit) = RecurrenceEdit::take(ui) {
+      if let Some(event) = self.events.iter().find(|e| e.id == edit.event_id) {
+        let new_event = edit.apply_to(event);
+        let change = Change::new_changed(&self.events, new_event)
+          .with_scope(RecurrenceScope::All);
         change.apply(&mut self.events);
         self.history.save(change);
       }
     }
   }
 
-  pub(super) fn refocus_edited_event(&self, ui: &Ui) {
-    RefocusingEvent::apply_focus(ui);
-  }
+  // commit an event moved or resized with the keyboard like a drag
+  fn apply_keyboard_change(&mut self, ui: &Ui, event: Event) {
+    if self.events.contains(&event) {
+      return;
+    }
 
-  pub(super) fn handle_undo(&mut self, ui: &mut Ui) {
-    let ctrl_z =
-      ui.input_mut(|input| input.consume_key(Modifiers::CTRL, egui::Key::Z));
+    let change = Change::new_changed(&self.events, event);
+    self.apply_change(ui, change);
+  }
 
-    if !ctrl_z {
+  fn apply_change(&mut self, ui: &Ui, change: Change) {
+    if change.is_occurrence_change() {
+      // ask which occurrences to change first
+      PendingOccurrenceChange::set(ui, change);
       return;
     }
 
-    if let Some(change) = self.history.pop() {
-      change.reverse().apply(&mut self.events)
+    change.apply(&mut self.events);
+    self.history.save(change);
+  }
+
+  pub(super) fn ask_recurrence_scope(&mut self, ui: &Ui) {
+    use RecurrenceScope::*;
+
+    let Some(change) = PendingOccurrenceChange::get(ui) else {
+      return;
+    };
+
+    let verb = match change {
+      Change::Removed { .. } => "Delete",
+      _ => "Change",
+    };
+
+    let mut answer = None;
+    egui::Window::new("Recurring event")
+      .collapsible(false)
+      .resizable(false)
+      .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+      .show(ui.ctx(), |ui| {
+        ui.label(format!("{verb} a recurring event:"));
+        if ui.button("Only this event").clicked() {
+          answer = Some(Some(ThisOnly));
+        }
+        if ui.button("This and following events").clicked() {
+          answer = Some(Some(ThisAndFollowing));
+        }
+        if ui.button("All events").clicked() {
+          answer = Some(Some(All));
+        }
+        if ui.button("Cancel").clicked()
+          || ui.input(|input| input.key_pressed(Key::Escape))
+        {
+          answer = Some(None);
+        }
+      });
+
+    let Some(scope) = answer else {
+      return;
+    };
+
+    PendingOccurrenceChange::discard(ui);
+
+    if let Some(scope) = scope {
+      let change = change.with_scope(scope);
+      change.apply(&mut self.events);
+      self.history.save(change);
     }
   }
+
+  // focus the event and scroll to it, it must be shown already
+  pub(super) fn focus_event(&mut self, ui: &Ui, event_id: &EventId) {
+    RefocusingEvent::request_focus(ui, event_id);
+    self.scroll_event_into_view(ui, event_id);
+  }
+
+  pub(super) fn refocus_edited_event(&self, ui: &Ui) {
+    RefocusingEvent::apply_focus(ui);
+  }
 }
 
 fn find_nearest_event(events: &[Event], now: &DateTime) -> Option<EventId> {
@@ -1098,10 +1279,7 @@ fn find_juxtaposed_event(
   offset: isize,
   events: &[Event],
 ) -> Option<EventId> {
-  let ev = match events.iter().find(|x| &x.id == event_id) {
-    Some(ev) => ev,
-    None => return None,
-  };
+  let ev = events.iter().find(|x| &x.id == event_id)?;
 
   let t = ev.start + Duration::days(offset as i64);
   let dist = |e: &&Event| e.start.timestamp().abs_diff(t.timestamp());
@@ -1122,10 +1300,7 @@ fn find_adjacent_event(
   offset: isize,
   events: &[Event],
 ) -> Option<EventId> {
-  let i = match events.iter().position(|x| x.id == *event_id) {
-    Some(i) => i,
-    None => return None,
-  };
+  let i = events.iter().position(|x| x.id == *event_id)?;
 
   let new_i = i as isize + offset;
   if new_i < 0 || new_i >= events.len() as isize {
@@ -1135,6 +1310,17 @@ fn find_adjacent_event(
   Some(events[new_i as usize].id.clone())
 }
 
+// black or white, whichever is more readable on the background
+fn contrasting_text_color(background: Color32) -> Color32 {
+  let [r, g, b, _] = background.to_array();
+  let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+  if luma > 140.0 {
+    Color32::BLACK
+  } else {
+    Color32::WHITE
+  }
+}
+
 fn disable_built_in_keyboard_focus_navigation(resp: &Response) {
   // avoid built-in arrow navigation
   let event_filter = EventFilter {
@@ -21,19 +21,63 @@ impl<'a> From<(&'a EventId, i64, i64)> for Ev<'a> {
 pub struct Layout {
   // EventId => [left, right]
   layout: HashMap<EventId, [f32; 2]>,
+  // EventId => row, for all-day events
+  rows: HashMap<EventId, usize>,
 }
 
 impl Layout {
   fn from_map(layout: HashMap<EventId, [f32; 2]>) -> Self {
-    Self { layout }
+    Self {
+      layout,
+      ..Default::default()
+    }
   }
 
   pub fn query(&self, id: &EventId) -> Option<[f32; 2]> {
     self.layout.get(id).cloned()
   }
 
+  pub fn query_row(&self, id: &EventId) -> Option<usize> {
+    self.rows.get(id).cloned()
+  }
+
+  pub fn row_count(&self) -> usize {
+    self.rows.values().max().map_or(0, |row| row + 1)
+  }
+
   pub fn merge(&mut self, other: Layout) {
-    self.layout.extend(other.layout)
+    self.layout.extend(other.layout);
+    self.rows.extend(other.rows);
+  }
+}
+
+// Stack all-day events into rows, each event taking the first row
+// that is free for its whole span
+pub fn stack_rows(mut events: Vec<Ev<'_>>) -> Layout {
+  events.sort_by_key(|e| (e.start, e.start - e.end));
+
+  // the end of the last event in each row
+  let mut row_ends: Vec<i64> = vec![];
+  let mut rows = HashMap::new();
+
+  for event in events {
+    let row = match row_ends.iter().position(|&end| end <= event.start) {
+      Some(row) => {
+        row_ends[row] = event.end;
+        row
+      }
+      None => {
+        row_ends.push(event.end);
+        row_ends.len() - 1
+      }
+    };
+
+    rows.insert(event.id.clone(), row);
+  }
+
+  Layout {
+    rows,
+    ..Default::default()
   }
 }
 
@@ -41,6 +85,7 @@ pub trait LayoutAlgorithm {
   fn compute(events: Vec<Ev<'_>>) -> Layout;
 }
 
+#[allow(unused)]
 pub struct NaiveAlgorithm;
 
 impl LayoutAlgorithm for NaiveAlgorithm {
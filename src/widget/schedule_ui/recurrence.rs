@@ -0,0 +1,290 @@
+use chrono::{Datelike, NaiveDate, TimeZone, Weekday};
+use eframe::egui::{self, Ui};
+
+use crate::{
+  event::Event,
+  recurrence::{Frequency, RRule, Recurrence, WeekdayNum},
+};
+
+use super::EventId;
+
+// Editing recurrence rules from the event context menu
+
+const WEEKDAYS: [Weekday; 7] = [
+  Weekday::Mon,
+  Weekday::Tue,
+  Weekday::Wed,
+  Weekday::Thu,
+  Weekday::Fri,
+  Weekday::Sat,
+  Weekday::Sun,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RepeatKind {
+  Never,
+  Daily,
+  Weekdays,
+  Weekly,
+  Monthly,
+  // a rule we can't edit, kept as it is
+  Custom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RepeatEnd {
+  Never,
+  Count,
+  Until,
+}
+
+#[derive(Clone, Debug)]
+struct RecurrenceDraft {
+  kind: RepeatKind,
+  // Monday first
+  weekdays: [bool; 7],
+  end: RepeatEnd,
+  count: u32,
+  until: String,
+  custom: Option<RRule>,
+}
+
+impl RecurrenceDraft {
+  fn id(event_id: &EventId) -> egui::Id {
+    egui::Id::new(("recurrence_draft", event_id))
+  }
+
+  fn from_event(event: &Event) -> Self {
+    let rule = event.recurrence.as_ref().and_then(|r| r.rule.clone());
+    let mut weekdays = [false; 7];
+    weekdays[event.start.weekday().num_days_from_monday() as usize] = true;
+
+    let mut draft = Self {
+      kind: RepeatKind::Never,
+      weekdays,
+      end: RepeatEnd::Never,
+      count: 10,
+      until: (event.start.date_naive() + chrono::Duration::days(30))
+        .format("%F")
+        .to_string(),
+      custom: None,
+    };
+
+    let Some(rule) = rule else {
+      return draft;
+    };
+
+    if let Some(count) = rule.count {
+      draft.end = RepeatEnd::Count;
+      draft.count = count;
+    }
+    if let Some(until) = rule.until {
+      draft.end = RepeatEnd::Until;
+      draft.until = until
+        .with_timezone(event.start.offset())
+        .format("%F")
+        .to_string();
+    }
+
+    let simple_by_day = rule.by_day.iter().all(|d| d.nth.is_none());
+    let simple = rule.interval == 1
+      && rule.by_month_day.is_empty()
+      && rule.by_month.is_empty()
+      && rule.by_set_pos.is_empty()
+      && simple_by_day;
+    let by_day: Vec<Weekday> = rule.by_day.iter().map(|d| d.weekday).collect();
+
+    draft.kind = match rule.freq {
+      Frequency::Daily if simple && by_day.is_empty() => RepeatKind::Daily,
+      Frequency::Weekly if simple && by_day == WEEKDAYS[0..5] => {
+        RepeatKind::Weekdays
+      }
+      Frequency::Weekly if simple => {
+        if !by_day.is_empty() {
+          draft.weekdays = WEEKDAYS.map(|wd| by_day.contains(&wd));
+        }
+        RepeatKind::Weekly
+      }
+      Frequency::Monthly if simple && by_day.is_empty() => RepeatKind::Monthly,
+      _ => RepeatKind::Custom,
+    };
+
+    if draft.kind == RepeatKind::Custom {
+      draft.custom = Some(rule);
+    }
+
+    draft
+  }
+
+  fn to_rule(&self, event: &Event) -> Result<Option<RRule>, String> {
+    let mut rule = match self.kind {
+      RepeatKind::Never => return Ok(None),
+      RepeatKind::Custom => match &self.custom {
+        Some(rule) => rule.clone(),
+        None => return Ok(None),
+      },
+      RepeatKind::Daily => RRule::new(Frequency::Daily),
+      RepeatKind::Weekdays => {
+        let mut rule = RRule::new(Frequency::Weekly);
+        rule.by_day = weekday_nums(&WEEKDAYS[0..5]);
+        rule
+      }
+      RepeatKind::Weekly => {
+        let mut rule = RRule::new(Frequency::Weekly);
+        let days: Vec<Weekday> = WEEKDAYS
+          .into_iter()
+          .zip(self.weekdays)
+          .filter_map(|(wd, checked)| checked.then_some(wd))
+          .collect();
+        rule.by_day = weekday_nums(&days);
+        rule
+      }
+      RepeatKind::Monthly => RRule::new(Frequency::Monthly),
+    };
+
+    rule.count = None;
+    rule.until = None;
+
+    match self.end {
+      RepeatEnd::Never => (),
+      RepeatEnd::Count => rule.count = Some(self.count.max(1)),
+      RepeatEnd::Until => {
+        let date = NaiveDate::parse_from_str(self.until.trim(), "%F")
+          .map_err(|_| "Invalid date, use YYYY-MM-DD".to_string())?;
+        let until = event
+          .start
+          .timezone()
+          .from_local_datetime(&date.and_hms_opt(23, 59, 59).unwrap())
+          .single()
+          .ok_or_else(|| "Invalid date".to_string())?;
+        rule.until = Some(until);
+      }
+    }
+
+    Ok(Some(rule))
+  }
+}
+
+// the rule chosen from the context menu for an event
+#[derive(Clone, Debug)]
+pub(super) struct RecurrenceEdit {
+  pub(super) event_id: EventId,
+  pub(super) rule: Option<RRule>,
+}
+
+impl RecurrenceEdit {
+  fn id() -> egui::Id {
+    egui::Id::new("recurrence_edit")
+  }
+
+  fn set(ui: &Ui, event_id: &EventId, rule: Option<RRule>) {
+    let value = Self {
+      event_id: event_id.clone(),
+      rule,
+    };
+    ui.memory_mut(|mem| mem.data.insert_temp(Self::id(), value));
+  }
+
+  pub(super) fn take(ui: &Ui) -> Option<Self> {
+    let edit = ui.memory(|mem| mem.data.get_temp(Self::id()));
+    ui.memory_mut(|mem| mem.data.remove::<Self>(Self::id()));
+    edit
+  }
+
+  // the event with the recurrence rule replaced
+  pub(super) fn apply_to(&self, event: &Event) -> Event {
+    let mut event = event.clone();
+    let recurrence = event.recurrence.take().unwrap_or_default();
+    let recurrence = Recurrence {
+      rule: self.rule.clone(),
+      ..recurrence
+    };
+
+    let is_occurrence = event.recurrence_id.is_some();
+    if is_occurrence || recurrence.rule.is_some() {
+      event.recurrence = Some(recurrence);
+    }
+
+    event
+  }
+}
+
+pub(super) fn recurrence_menu(ui: &mut Ui, event: &Event) {
+  ui.menu_button("Repeat", |ui| {
+    let id = RecurrenceDraft::id(&event.id);
+    let mut draft = ui
+      .memory(|mem| mem.data.get_temp(id))
+      .unwrap_or_else(|| RecurrenceDraft::from_event(event));
+
+    if event.recurrence_id.is_some() {
+      ui.label("Changes apply to all occurrences");
+      ui.separator();
+    }
+
+    ui.radio_value(&mut draft.kind, RepeatKind::Never, "Does not repeat");
+    ui.radio_value(&mut draft.kind, RepeatKind::Daily, "Daily");
+    ui.radio_value(&mut draft.kind, RepeatKind::Weekdays, "Every weekday");
+    ui.radio_value(&mut draft.kind, RepeatKind::Weekly, "Weekly on");
+    if draft.kind == RepeatKind::Weekly {
+      ui.horizontal(|ui| {
+        for (wd, checked) in WEEKDAYS.iter().zip(draft.weekdays.iter_mut()) {
+          ui.checkbox(checked, &wd.to_string()[0..2]);
+        }
+      });
+    }
+    ui.radio_value(&mut draft.kind, RepeatKind::Monthly, "Monthly");
+    if let Some(rule) = &draft.custom {
+      ui.radio_value(&mut draft.kind, RepeatKind::Custom, rule.to_string());
+    }
+
+    if draft.kind != RepeatKind::Never {
+      ui.separator();
+      ui.label("Ends");
+      ui.radio_value(&mut draft.end, RepeatEnd::Never, "Never");
+      ui.horizontal(|ui| {
+        ui.radio_value(&mut draft.end, RepeatEnd::Count, "After");
+        ui.add_enabled(
+          draft.end == RepeatEnd::Count,
+          egui::DragValue::new(&mut draft.count)
+            .clamp_range(1..=999)
+            .suffix(" times"),
+        );
+      });
+      ui.horizontal(|ui| {
+        ui.radio_value(&mut draft.end, RepeatEnd::Until, "On");
+        ui.add_enabled(
+          draft.end == RepeatEnd::Until,
+          egui::TextEdit::singleline(&mut draft.until)
+            .hint_text("YYYY-MM-DD")
+            .desired_width(90.0),
+        );
+      });
+    }
+
+    ui.separator();
+
+    let rule = draft.to_rule(event);
+    if let Err(e) = &rule {
+      ui.label(egui::RichText::new(e).color(ui.visuals().error_fg_color));
+    }
+
+    if ui
+      .add_enabled(rule.is_ok(), egui::Button::new("Apply"))
+      .clicked()
+    {
+      RecurrenceEdit::set(ui, &event.id, rule.unwrap_or_default());
+      ui.memory_mut(|mem| mem.data.remove::<RecurrenceDraft>(id));
+      ui.close_menu();
+      return;
+    }
+
+    ui.memory_mut(|mem| mem.data.insert_temp(id, draft));
+  });
+}
+
+fn weekday_nums(weekdays: &[Weekday]) -> Vec<WeekdayNum> {
+  weekdays
+    .iter()
+    .map(|&weekday| WeekdayNum { nth: None, weekday })
+    .collect()
+}
@@ -0,0 +1,159 @@
+use eframe::egui::{self, Key, Modifiers, Ui};
+
+use crate::{event::Event, search::SearchQuery};
+
+use super::{interaction::InteractingEvent, ScheduleUi};
+
+// Searching events from a panel opened with / or Ctrl+F. The search
+// itself is done by the app, which picks up the pending query and
+// loads the results back.
+
+// the results shown at most
+const MAX_RESULTS: usize = 100;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct SearchPanel {
+  text: String,
+  // the text the results were found for
+  searched: Option<String>,
+  results: Vec<Event>,
+  selected: usize,
+}
+
+impl ScheduleUi {
+  // the query to run when the search text changed
+  pub fn pending_search(&self) -> Option<SearchQuery> {
+    let panel = self.search.as_ref()?;
+    if panel.searched.as_ref() == Some(&panel.text) {
+      return None;
+    }
+
+    Some(SearchQuery::new(panel.text.as_str()))
+  }
+
+  pub fn load_search_results(
+    &mut self,
+    query: &SearchQuery,
+    mut results: Vec<Event>,
+  ) {
+    let Some(panel) = self.search.as_mut() else {
+      return;
+    };
+
+    results.truncate(MAX_RESULTS);
+    panel.searched = Some(query.text.clone());
+    panel.results = results;
+    panel.selected = 0;
+  }
+
+  pub(super) fn is_searching(&self) -> bool {
+    self.search.is_some()
+  }
+
+  pub(super) fn handle_search(&mut self, ui: &Ui) {
+    let opened = self.search.is_none()
+      && !InteractingEvent::is_interacting(ui)
+      && ui.input_mut(|input| {
+        input.consume_key(Modifiers::NONE, Key::Slash)
+          || input.consume_key(Modifiers::CTRL, Key::F)
+      });
+    if opened {
+      self.search = Some(SearchPanel::default());
+    }
+
+    let Some(mut panel) = self.search.take() else {
+      return;
+    };
+
+    let (up, down, enter, escape) = ui.input_mut(|input| {
+      (
+        input.consume_key(Modifiers::NONE, Key::ArrowUp),
+        input.consume_key(Modifiers::NONE, Key::ArrowDown),
+        input.key_pressed(Key::Enter),
+        input.key_pressed(Key::Escape),
+      )
+    });
+
+    let last = panel.results.len().saturating_sub(1);
+    if up {
+      panel.selected = panel.selected.saturating_sub(1);
+    }
+    if down {
+      panel.selected = (panel.selected + 1).min(last);
+    }
+
+    let mut clicked = None;
+    egui::Window::new("Search")
+      .collapsible(false)
+      .resizable(false)
+      .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+      .show(ui.ctx(), |ui| {
+        let resp = ui.text_edit_singleline(&mut panel.text);
+        if opened {
+          resp.request_focus();
+        }
+
+        if panel.searched.is_some() && panel.results.is_empty() {
+          ui.label("No events found");
+        }
+
+        egui::ScrollArea::vertical()
+          .max_height(300.0)
+          .show(ui, |ui| {
+            for (i, event) in panel.results.iter().enumerate() {
+              let selected = i == panel.selected;
+              let resp =
+                ui.selectable_label(selected, self.result_label(event));
+              if selected && (up || down) {
+                resp.scroll_to_me(None);
+              }
+              if resp.clicked() {
+                clicked = Some(i);
+              }
+            }
+          });
+      });
+
+    let chosen = clicked.or(enter.then_some(panel.selected));
+    match chosen.and_then(|i| panel.results.get(i)) {
+      Some(event) => self.jump_to_event(event),
+      None if escape => (),
+      None => self.search = Some(panel),
+    }
+  }
+
+  fn result_label(&self, event: &Event) -> String {
+    if event.all_day {
+      return format!("{}  {}", event.start.format("%F %a"), event.title);
+    }
+
+    let start = event.start.with_timezone(&self.timezone);
+    format!("{}  {}", start.format("%F %a %H:%M"), event.title)
+  }
+
+  // show the days around the event, it's focused once loaded
+  fn jump_to_event(&mut self, event: &Event) {
+    let date = match event.all_day {
+      true => event.start.date_naive(),
+      false => event.start.with_timezone(&self.timezone).date_naive(),
+    };
+
+    self.show_date(date);
+    self.search_hit = Some(event.id.clone());
+  }
+
+  pub(super) fn focus_search_hit(&mut self, ui: &Ui) {
+    // wait for the events of the new days
+    if self.scope_updated || self.loading {
+      return;
+    }
+
+    let Some(event_id) = self.search_hit.take() else {
+      return;
+    };
+
+    if self.events.iter().any(|e| e.id == event_id) {
+      self.focus_event(ui, &event_id);
+    }
+  }
+}
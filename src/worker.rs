@@ -0,0 +1,750 @@
+use std::{
+  any::Any,
+  sync::{
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    Arc, Mutex,
+  },
+  thread::{self, JoinHandle},
+  time::Duration,
+};
+
+use crate::{
+  backend::{Backend, CalendarInfo, Conflict, TrashedEvent, Waker},
+  event::{Event, EventId},
+  history::{Change, HistoryStore, HistoryUpdate},
+  journal::Journal,
+  notifier::Notifier,
+  recurrence::RecurrenceScope,
+  search::SearchQuery,
+  util::{utc_now, DateTime, Result},
+};
+
+// how often changes made by other programs are picked up, for backends
+// that can't tell when there are any
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+// Runs the backend on its own thread, so that slow disks or large
+// calendars don't freeze the window. Requests are handled in the order
+// they are sent, the responses are taken by the app on the next frame.
+pub struct BackendWorker {
+  requests: Sender<Request>,
+  responses: Receiver<Response>,
+  // called whenever there is a new response
+  waker: Arc<Mutex<Option<Waker>>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+pub enum Request {
+  // reload the calendars from disk
+  Refresh,
+  LoadEvents {
+    id: u64,
+    from: DateTime,
+    to: DateTime,
+  },
+  Search(SearchQuery),
+  // save the changed and deleted events
+  Apply(Vec<Event>),
+  // drop the changes left in the journal
+  Discard(Vec<EventId>),
+  ListTrash,
+  Restore(Box<TrashedEvent>),
+  // remove the events deleted before the time from the trash
+  PurgeTrash(DateTime),
+  UpdateHistory(Vec<HistoryUpdate>),
+  // pick up the changes made by other programs
+  SyncChanges,
+  Stop,
+}
+
+pub enum Response {
+  Calendars(Result<Vec<CalendarInfo>>),
+  Events { id: u64, events: Result<Vec<Event>> },
+  SearchResults(SearchQuery, Result<Vec<Event>>),
+  Applied(Applied),
+  Trash(Result<Vec<TrashedEvent>>),
+  Restored(Result<()>),
+  // events were changed by other programs
+  Changed,
+  // the worker is gone for the reason, no more requests are handled
+  Stopped(String),
+}
+
+#[derive(Debug, Default)]
+pub struct Applied {
+  // the events saved
+  pub saved: Vec<EventId>,
+  // the events not saved because of an error, which are kept as they
+  // were sent to be tried again
+  pub failed: Vec<(Event, String)>,
+  // whether other occurrences of a series are affected
+  pub series_changed: bool,
+  // changes not saved because another program changed the file
+  pub conflicts: Vec<Conflict>,
+  // the changes made to whole series for the occurrences saved, by
+  // occurrence id, to be undone instead
+  pub series: Vec<(EventId, Vec<Change>)>,
+}
+
+struct Worker {
+  // only ever used on the worker thread
+  backend: Box<dyn Backend>,
+  notifier: Notifier,
+  // when the notifications were last scheduled
+  notified_at: Option<DateTime>,
+  journal: Option<Journal>,
+  history: Option<HistoryStore>,
+  // the events saved by the latest changes applied
+  saved: Vec<EventId>,
+  // the changes made to whole series by them
+  series: Vec<(EventId, Vec<Change>)>,
+  responses: Sender<Response>,
+  waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl BackendWorker {
+  pub fn start(
+    mut backend: Box<dyn Backend>,
+    notifier: Notifier,
+    journal: Option<Journal>,
+    history: Option<HistoryStore>,
+  ) -> Self {
+    let (requests, request_receiver) = channel();
+    let (response_sender, responses) = channel();
+    let waker: Arc<Mutex<Option<Waker>>> = Arc::default();
+
+    // sync as soon as the backend sees changes
+    let sync_requests = requests.clone();
+    backend.set_waker(Arc::new(move || {
+      let _ = sync_requests.send(Request::SyncChanges);
+    }));
+
+    let worker = Worker {
+      backend,
+      notifier,
+      notified_at: None,
+      journal,
+      history,
+      saved: vec![],
+      series: vec![],
+      responses: response_sender,
+      waker: waker.clone(),
+    };
+    let wake_on_exit = WakeOnExit(waker.clone());
+    let thread = thread::spawn(move || {
+      let _wake_on_exit = wake_on_exit;
+      worker.run(request_receiver)
+    });
+
+    Self {
+      requests,
+      responses,
+      waker,
+      thread: Some(thread),
+    }
+  }
+
+  pub fn set_waker(&self, waker: Waker) {
+    *self.waker.lock().unwrap() = Some(waker);
+  }
+
+  pub fn send(&self, request: Request) {
+    if self.requests.send(request).is_err() {
+      log::error!("Backend worker is gone");
+    }
+  }
+
+  pub fn take_responses(&mut self) -> Vec<Response> {
+    let mut responses = vec![];
+    loop {
+      match self.responses.try_recv() {
+        Ok(response) => responses.push(response),
+        Err(TryRecvError::Empty) => break,
+        // reported once, when the thread is joined
+        Err(TryRecvError::Disconnected) => {
+          if let Some(thread) = self.thread.take() {
+            let reason = match thread.join() {
+              Ok(()) => "stopped".to_string(),
+              Err(panic) => panic_message(panic),
+            };
+            log::error!("Backend worker is gone: {reason}");
+            responses.push(Response::Stopped(reason));
+          }
+          break;
+        }
+      }
+    }
+    responses
+  }
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+  if let Some(message) = panic.downcast_ref::<&str>() {
+    return message.to_string();
+  }
+
+  match panic.downcast::<String>() {
+    Ok(message) => *message,
+    Err(_) => "panicked".to_string(),
+  }
+}
+
+impl Drop for BackendWorker {
+  // finish saving the changes already sent
+  fn drop(&mut self) {
+    self.send(Request::Stop);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+// Wakes the app when dropped at the end of the worker thread, also
+// when it panicked, so that a worker gone is noticed right away. The
+// worker and its responses are dropped before.
+struct WakeOnExit(Arc<Mutex<Option<Waker>>>);
+
+impl Drop for WakeOnExit {
+  fn drop(&mut self) {
+    if let Some(wake) = self.0.lock().ok().as_deref().and_then(Option::as_ref) {
+      wake();
+    }
+  }
+}
+
+impl Worker {
+  fn run(mut self, requests: Receiver<Request>) {
+    loop {
+      let rescheduled_at = self
+        .notified_at
+        .map(|notified_at| notified_at + Notifier::reschedule_interval());
+      if rescheduled_at.is_none_or(|at| at <= utc_now()) {
+        self.schedule_notifications();
+      }
+
+      let request = match requests.recv_timeout(SYNC_INTERVAL) {
+        Ok(Request::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+        Ok(request) => request,
+        Err(RecvTimeoutError::Timeout) => Request::SyncChanges,
+      };
+
+      if let Some(response) = self.handle(request) {
+        self.respond(response);
+      }
+    }
+  }
+
+  fn handle(&mut self, request: Request) -> Option<Response> {
+    match request {
+      Request::Refresh => {
+        let backend = &mut self.backend;
+        let calendars = backend.force_refresh().map(|_| backend.calendars());
+        Some(Response::Calendars(calendars))
+      }
+      Request::LoadEvents { id, from, to } => {
+        let events = self.backend.get_events(from, to);
+        Some(Response::Events { id, events })
+      }
+      Request::Search(query) => {
+        let hits = self.backend.search(&query);
+        Some(Response::SearchResults(query, hits))
+      }
+      Request::Apply(events) => Some(Response::Applied(self.apply(events))),
+      Request::Discard(event_ids) => {
+        self.forget(&event_ids);
+        None
+      }
+      Request::ListTrash => {
+        Some(Response::Trash(self.backend.trashed_events()))
+      }
+      Request::Restore(trashed) => {
+        let restored = self.backend.restore_event(&trashed);
+        if restored.is_ok() {
+          self.schedule_notifications();
+        }
+        Some(Response::Restored(restored))
+      }
+      Request::PurgeTrash(before) => {
+        if let Err(e) = self.backend.purge_trash(before) {
+          log::error!("Failed purging the trash: {e:?}");
+        }
+        None
+      }
+      Request::UpdateHistory(mut updates) => {
+        let history = self.history.as_mut()?;
+        // the changes are applied before their history is stored, the
+        // files written are remembered to undo the changes after a
+        // restart
+        for update in updates.iter_mut() {
+          let HistoryUpdate::Saved(entry) = update else {
+            continue;
+          };
+          let event_id = entry.change.event_id();
+          if let Some((_, series)) =
+            self.series.iter().find(|(id, _)| id == event_id)
+          {
+            entry.series = series.clone();
+          }
+        }
+        let stored = history.update(&updates).and_then(|event_ids| {
+          for event_id in event_ids.iter().filter(|id| self.saved.contains(id))
+          {
+            let written = self.backend.get_event(event_id).ok();
+            history
+              .record_written(event_id, written.and_then(|e| e.fingerprint))?;
+          }
+          Ok(())
+        });
+        if let Err(e) = stored {
+          log::error!("Failed storing the history: {e:?}");
+        }
+        None
+      }
+      Request::SyncChanges => match self.backend.sync_changes() {
+        Ok(true) => {
+          self.schedule_notifications();
+          Some(Response::Changed)
+        }
+        Ok(false) => None,
+        Err(e) => {
+          log::error!("Failed syncing changes {:?}", e);
+          None
+        }
+      },
+      Request::Stop => None,
+    }
+  }
+
+  // notify of the events starting until the notifications are
+  // scheduled again
+  fn schedule_notifications(&mut self) {
+    let now = utc_now();
+    let until = now + Notifier::reschedule_interval();
+    match self.backend.get_events(now, until) {
+      Ok(events) => self.notifier.schedule(events),
+      Err(e) => log::error!("Failed scheduling notifications: {e:?}"),
+    }
+    self.notified_at = Some(now);
+  }
+
+  fn apply(&mut self, events: Vec<Event>) -> Applied {
+    if let Some(journal) = self.journal.as_mut() {
+      if let Err(e) = journal.record(&events) {
+        log::error!("Failed recording changes in the journal: {e:?}");
+      }
+    }
+
+    let event_ids: Vec<_> = events.iter().map(|e| e.id.clone()).collect();
+    let applied = apply_changes(self.backend.as_mut(), events);
+    // the series changed are saved as well
+    let series_changed = applied.series.iter().flat_map(|(_, changes)| {
+      changes.iter().map(|change| change.event_id().clone())
+    });
+    self.saved = applied
+      .saved
+      .iter()
+      .cloned()
+      .chain(series_changed)
+      .collect();
+    self.series = applied.series.clone();
+
+    // only the failed changes are left, the conflicting ones are given
+    // up on
+    let failed: Vec<_> = applied.failed.iter().map(|(e, _)| &e.id).collect();
+    let done: Vec<_> = event_ids
+      .into_iter()
+      .filter(|id| !failed.contains(&id))
+      .collect();
+    self.forget(&done);
+
+    if !applied.saved.is_empty() {
+      self.schedule_notifications();
+    }
+    applied
+  }
+
+  fn forget(&self, event_ids: &[EventId]) {
+    let Some(journal) = self.journal.as_ref() else {
+      return;
+    };
+
+    for event_id in event_ids {
+      if let Err(e) = journal.remove(event_id) {
+        log::error!("Failed removing {event_id} from the journal: {e:?}");
+      }
+    }
+  }
+
+  fn respond(&self, response: Response) {
+    // the app is gone when the receiver is
+    if self.responses.send(response).is_err() {
+      return;
+    }
+
+    if let Some(wake) = self.waker.lock().unwrap().as_ref() {
+      wake();
+    }
+  }
+}
+
+fn apply_changes(backend: &mut dyn Backend, events: Vec<Event>) -> Applied {
+  let mut applied = Applied::default();
+
+  for event in events {
+    let scope = event.recurrence_scope;
+    let is_occurrence = event.recurrence_id.is_some();
+    // the series as it was before the change of an occurrence
+    let master = match is_occurrence {
+      true => backend.get_event(&event.uid().to_string()).ok(),
+      false => None,
+    };
+    let mut following = None;
+
+    let result = if event.deleted && is_occurrence {
+      applied.series_changed |= scope != RecurrenceScope::ThisOnly;
+      backend.delete_occurrences(&event, scope)
+    } else if event.deleted {
+      applied.series_changed |= event.is_recurring();
+      backend.delete_event(&event)
+    } else if event.changed && is_occurrence {
+      applied.series_changed |= scope != RecurrenceScope::ThisOnly;
+      backend
+        .update_occurrences(&event, scope)
+        .map(|split_off| following = split_off)
+    } else if event.changed {
+      // a recurrence was set on the event
+      applied.series_changed |= event.is_recurring();
+      backend.update_event(&event)
+    } else {
+      continue;
+    };
+
+    match result.map_err(|e| e.downcast::<Conflict>()) {
+      Ok(()) => {
+        if let Some(master) = master {
+          let changes = series_changes(backend, master, following);
+          applied.series.push((event.id.clone(), changes));
+        }
+        applied.saved.push(event.id)
+      }
+      // show the version of the other program instead
+      Err(Ok(conflict)) => {
+        log::warn!("Not saving event: {conflict}");
+        applied.conflicts.push(conflict);
+        applied.series_changed = true;
+      }
+      Err(Err(e)) => {
+        log::error!("Failed saving event {}: {e:?}", event.id);
+        applied.failed.push((event, format!("{e:#}")));
+      }
+    }
+  }
+
+  applied
+}
+
+// The change of an occurrence as the changes made to whole series:
+// the series before and after (if anything is left of it), and the
+// series split off from it
+fn series_changes(
+  backend: &mut dyn Backend,
+  master: Event,
+  following: Option<EventId>,
+) -> Vec<Change> {
+  let mut changes = vec![match backend.get_event(&master.id) {
+    Ok(new) => Change::Modified { old: master, new },
+    Err(_) => Change::Removed { old: master },
+  }];
+
+  if let Some(following) = following {
+    match backend.get_event(&following) {
+      Ok(new) => changes.push(Change::Added { new }),
+      Err(e) => log::warn!("Series {following} split off not found: {e:?}"),
+    }
+  }
+
+  changes
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::DateTime as ChronoDateTime;
+  use tempfile::TempDir;
+
+  use super::*;
+  use crate::{
+    backend::{LocalDir, LocalDirBuilder},
+    config::Config,
+    history::History,
+  };
+
+  const DAILY: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:test\r
+BEGIN:VEVENT\r
+UID:daily\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20240304T090000Z\r
+DTEND:20240304T100000Z\r
+SUMMARY:daily\r
+RRULE:FREQ=DAILY;COUNT=5\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+  // the backend along with the events and the history of the schedule
+  struct Session {
+    _dir: TempDir,
+    backend: LocalDir,
+    events: Vec<Event>,
+    history: History,
+  }
+
+  impl Session {
+    fn new() -> Self {
+      let dir = TempDir::new().unwrap();
+      std::fs::write(dir.path().join("daily.ics"), DAILY).unwrap();
+      let backend = LocalDirBuilder::default()
+        .dir(dir.path())
+        .calendar("test")
+        .build()
+        .unwrap();
+
+      let mut session = Self {
+        _dir: dir,
+        backend,
+        events: vec![],
+        history: History::default(),
+      };
+      session.reload();
+      session
+    }
+
+    fn reload(&mut self) {
+      let from = time("2024-03-04T00:00:00Z");
+      let to = time("2024-03-09T00:00:00Z");
+      self.events = self.backend.get_events(from, to).unwrap();
+      self.events.sort_by_key(|e| e.start);
+    }
+
+    // change the occurrence on the given day
+    fn change(
+      &mut self,
+      day: usize,
+      scope: RecurrenceScope,
+      f: fn(&mut Event),
+    ) {
+      let mut event = self.events[day].clone();
+      f(&mut event);
+      let change = match event.deleted {
+        true => Change::new_removed(&self.events, &event.id).unwrap(),
+        false => Change::new_changed(&self.events, event),
+      };
+      let change = change.with_scope(scope);
+      change.apply(&mut self.events);
+      self.history.save(change);
+      self.save();
+    }
+
+    fn undo(&mut self) {
+      for change in self.history.undo().unwrap().into_iter().rev() {
+        change.reverse().apply(&mut self.events);
+      }
+      self.save();
+    }
+
+    fn redo(&mut self) {
+      for change in self.history.redo().unwrap() {
+        change.apply(&mut self.events);
+      }
+      self.save();
+    }
+
+    fn save(&mut self) {
+      let changes = self
+        .events
+        .iter()
+        .filter(|e| e.changed || e.deleted)
+        .cloned()
+        .collect();
+      let applied = apply_changes(&mut self.backend, changes);
+      assert!(applied.failed.is_empty() && applied.conflicts.is_empty());
+      for (event_id, changes) in applied.series.iter() {
+        self.history.set_series(event_id, changes);
+      }
+      self.reload();
+    }
+
+    // the title and time of the occurrences
+    fn summary(&self) -> Vec<String> {
+      self
+        .events
+        .iter()
+        .map(|e| format!("{} {}", e.title, e.start.format("%d %H:%M")))
+        .collect()
+    }
+  }
+
+  fn time(s: &str) -> DateTime {
+    ChronoDateTime::parse_from_rfc3339(s).unwrap()
+  }
+
+  fn moved(event: &mut Event) {
+    event.start += chrono::Duration::hours(1);
+    event.end += chrono::Duration::hours(1);
+    event.mark_changed();
+  }
+
+  fn renamed(event: &mut Event) {
+    event.title = "standup".to_string();
+    event.mark_changed();
+  }
+
+  fn deleted(event: &mut Event) {
+    event.mark_deleted();
+  }
+
+  #[test]
+  fn undoing_changes_to_all_occurrences() {
+    let mut session = Session::new();
+    let original = session.summary();
+
+    session.change(1, RecurrenceScope::All, moved);
+    assert_eq!(session.summary()[0], "daily 04 10:00");
+
+    session.undo();
+    assert_eq!(session.summary(), original);
+    session.redo();
+    assert_eq!(session.summary()[4], "daily 08 10:00");
+  }
+
+  #[test]
+  fn undoing_changes_to_the_following_occurrences() {
+    let mut session = Session::new();
+    let original = session.summary();
+
+    session.change(2, RecurrenceScope::ThisAndFollowing, renamed);
+    let split = session.summary();
+    assert_eq!(split[1], "daily 05 09:00");
+    assert_eq!(split[2], "standup 06 09:00");
+
+    // the series split off is deleted
+    session.undo();
+    assert_eq!(session.summary(), original);
+    let uids: Vec<_> = session.events.iter().map(|e| e.uid()).collect();
+    assert_eq!(uids, ["daily"; 5]);
+
+    session.redo();
+    assert_eq!(session.summary(), split);
+  }
+
+  #[test]
+  fn undoing_the_deletion_of_a_series() {
+    let mut session = Session::new();
+    let original = session.summary();
+
+    session.change(2, RecurrenceScope::All, deleted);
+    assert!(session.events.is_empty());
+
+    session.undo();
+    assert_eq!(session.summary(), original);
+    session.redo();
+    assert!(session.events.is_empty());
+  }
+
+  #[test]
+  fn undoing_changes_to_one_occurrence() {
+    let mut session = Session::new();
+    let original = session.summary();
+
+    session.change(1, RecurrenceScope::ThisOnly, moved);
+    session.change(3, RecurrenceScope::ThisAndFollowing, deleted);
+    assert_eq!(
+      session.summary(),
+      ["daily 04 09:00", "daily 05 10:00", "daily 06 09:00"]
+    );
+
+    session.undo();
+    session.undo();
+    assert_eq!(session.summary(), original);
+  }
+
+  // a backend failing to read the calendars
+  struct Broken;
+
+  impl Backend for Broken {
+    fn calendars(&self) -> Vec<CalendarInfo> {
+      vec![]
+    }
+
+    fn get_event(&mut self, _event_id: &EventId) -> Result<Event> {
+      unimplemented!()
+    }
+
+    fn get_events(
+      &mut self,
+      _from: DateTime,
+      _to: DateTime,
+    ) -> Result<Vec<Event>> {
+      panic!("backend broken")
+    }
+
+    fn delete_event(&mut self, _event: &Event) -> Result<()> {
+      unimplemented!()
+    }
+
+    fn search(&mut self, _query: &SearchQuery) -> Result<Vec<Event>> {
+      unimplemented!()
+    }
+
+    fn update_event(&mut self, _event: &Event) -> Result<()> {
+      unimplemented!()
+    }
+
+    fn update_occurrences(
+      &mut self,
+      _occurrence: &Event,
+      _scope: RecurrenceScope,
+    ) -> Result<Option<EventId>> {
+      unimplemented!()
+    }
+
+    fn delete_occurrences(
+      &mut self,
+      _occurrence: &Event,
+      _scope: RecurrenceScope,
+    ) -> Result<()> {
+      unimplemented!()
+    }
+
+    fn create_event(&mut self, _event: &Event) -> Result<()> {
+      unimplemented!()
+    }
+  }
+
+  #[test]
+  fn a_worker_gone_is_reported() {
+    let notifier = Notifier::new(&Config::default());
+    let mut worker =
+      BackendWorker::start(Box::new(Broken), notifier, None, None);
+    worker.send(Request::Refresh);
+
+    let mut stopped = vec![];
+    for _ in 0..500 {
+      stopped.extend(worker.take_responses().into_iter().filter_map(
+        |response| match response {
+          Response::Stopped(reason) => Some(reason),
+          _ => None,
+        },
+      ));
+      if !stopped.is_empty() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(stopped.len(), 1);
+    assert_eq!(stopped[0], "backend broken");
+    // reported once
+    assert!(worker.take_responses().is_empty());
+  }
+}